@@ -1,6 +1,8 @@
 use std::{sync::Arc, time::Duration};
 
-use lfkv_db::{size, Engine, EngineConfig};
+use lfkv_db::{
+  size, Engine, EngineConfig, KeyComparator, LogMode, SyncMode, VerifyLevel,
+};
 
 fn main() {
   let engine = Arc::new(
@@ -17,6 +19,24 @@ fn main() {
       checkpoint_count: 10000,
       group_commit_delay: Duration::from_millis(10),
       group_commit_count: 100,
+      repair: false,
+      file_mode: None,
+      sync_mode: SyncMode::Data,
+      log_mode: LogMode::Physical,
+      verify_on_open: VerifyLevel::None,
+      recovery_threads: 4,
+      transaction_timeout: None,
+      io_buffer_budget: Some(size::mb(8)),
+      punch_holes: false,
+      key_comparator: KeyComparator::Lexicographic,
+      data_path: None,
+      wal_path: None,
+      undo_path: None,
+      in_memory: false,
+      eviction_policy: Default::default(),
+      max_open_transactions: None,
+      wal_buffer_bytes: None,
+      alloc_strategy: Default::default(),
     })
     .unwrap(),
   );
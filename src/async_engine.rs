@@ -0,0 +1,99 @@
+use std::sync::Arc;
+
+use crate::{
+  oneshot, size, Engine, Oneshot, Result, Serializable, SharedWorkThread, WriteBatch,
+};
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Runs engine operations on a worker pool and returns futures of their results,
+/// so async callers do not block on the engine.
+pub struct AsyncEngine {
+  engine: Arc<Engine>,
+  pool: SharedWorkThread<Job>,
+}
+impl AsyncEngine {
+  pub fn new(engine: Engine, count: usize) -> Self {
+    Self {
+      engine: Arc::new(engine),
+      pool: SharedWorkThread::new("async engine", size::mb(2), count, |job: Job| job()),
+    }
+  }
+
+  fn spawn<F, R>(&self, f: F) -> Oneshot<R>
+  where
+    F: FnOnce(&Engine) -> R + Send + 'static,
+    R: Send + 'static,
+  {
+    let (tx, rx) = oneshot();
+    let engine = self.engine.clone();
+    self.pool.execute(Box::new(move || {
+      if !tx.is_disconnected() {
+        tx.send(f(&engine))
+      }
+    }));
+    rx
+  }
+
+  pub fn get<T>(&self, key: Vec<u8>) -> Oneshot<Result<T>>
+  where
+    T: Serializable + Send + 'static,
+  {
+    self.spawn(move |engine| {
+      let cursor = engine.new_transaction()?;
+      let value = cursor.get(&key)?;
+      cursor.commit()?;
+      Ok(value)
+    })
+  }
+
+  pub fn insert<T>(&self, key: Vec<u8>, value: T) -> Oneshot<Result>
+  where
+    T: Serializable + Send + 'static,
+  {
+    self.spawn(move |engine| {
+      let cursor = engine.new_transaction()?;
+      cursor.insert(key, value)?;
+      cursor.commit()
+    })
+  }
+
+  pub fn commit(&self, batch: WriteBatch) -> Oneshot<Result> {
+    self.spawn(move |engine| engine.apply_batch(batch))
+  }
+}
+impl Drop for AsyncEngine {
+  fn drop(&mut self) {
+    self.pool.close();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::{engine::tests::config, Engine, Page, WriteBatch};
+
+  use super::AsyncEngine;
+
+  #[tokio::test(flavor = "multi_thread")]
+  async fn _1() {
+    let config = config("lfkv-async-engine");
+    let dir = config.base_path.clone();
+    let engine = AsyncEngine::new(Engine::bootstrap(config).unwrap(), 2);
+
+    let mut batch = WriteBatch::new();
+    for i in 0..100u32 {
+      let mut page = Page::new();
+      page.writer().write_u32(i).unwrap();
+      batch.put(i.to_be_bytes().to_vec(), &page).unwrap();
+    }
+    engine.commit(batch).await.unwrap();
+
+    let found: Page = engine.get(99u32.to_be_bytes().to_vec()).await.unwrap();
+    assert_eq!(found.scanner().read_u32().unwrap(), 99);
+    engine.insert(b"k".to_vec(), Page::new()).await.unwrap();
+    assert!(engine.get::<Page>(b"k".to_vec()).await.is_ok());
+
+    drop(engine);
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+}
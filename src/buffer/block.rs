@@ -1,3 +1,5 @@
+use std::sync::Arc;
+
 use crate::{size, Error, Page, Serializable, PAGE_SIZE};
 
 pub const BLOCK_SIZE: usize = size::kb(4);
@@ -7,7 +9,10 @@ pub struct DataBlock {
   pub commit_index: usize,
   pub tx_id: usize,
   pub undo_index: Option<usize>,
-  pub data: Page,
+  /// Used length of the page, kept in the header so the size of a value
+  /// is known without reading the page.
+  pub len: usize,
+  pub data: Arc<Page>,
 }
 
 impl DataBlock {
@@ -25,17 +30,26 @@ impl DataBlock {
       commit_index,
       tx_id,
       undo_index,
-      data,
+      len: data.used_len(),
+      data: Arc::new(data),
     }
   }
 
+  /// The page is shared with the copy instead of being copied,
+  /// since a page is never modified once it is in a block.
   pub fn copy(&self) -> Self {
-    Self::new(
-      self.commit_index,
-      self.tx_id,
-      self.undo_index,
-      self.data.copy(),
-    )
+    Self {
+      commit_index: self.commit_index,
+      tx_id: self.tx_id,
+      undo_index: self.undo_index,
+      len: self.len,
+      data: self.data.clone(),
+    }
+  }
+
+  /// Takes the page, copying it only if it is still shared.
+  pub fn into_page(self) -> Page {
+    Arc::try_unwrap(self.data).unwrap_or_else(|data| data.copy())
   }
 }
 impl Serializable<Error, BLOCK_SIZE> for DataBlock {
@@ -44,11 +58,15 @@ impl Serializable<Error, BLOCK_SIZE> for DataBlock {
     let mut wt = page.writer();
     wt.write(self.commit_index.to_be_bytes().as_ref())?;
     wt.write(self.tx_id.to_be_bytes().as_ref())?;
-    if let Some(i) = self.undo_index {
-      wt.write(&[1u8])?;
-      wt.write(i.to_be_bytes().as_ref())?;
+    match self.undo_index {
+      Some(i) => {
+        wt.write(&[1u8])?;
+        wt.write(i.to_be_bytes().as_ref())?;
+      }
+      None => wt.write(&[0u8])?,
     }
-    wt.write(self.data.as_ref())?;
+    wt.write((self.len as u16).to_be_bytes().as_ref())?;
+    wt.write(self.data.as_ref().as_ref())?;
     Ok(page)
   }
   fn deserialize(value: &Page<BLOCK_SIZE>) -> std::prelude::v1::Result<Self, Error> {
@@ -60,7 +78,14 @@ impl Serializable<Error, BLOCK_SIZE> for DataBlock {
     } else {
       None
     };
-    let data = sc.read_n(PAGE_SIZE)?.into();
-    Ok(Self::new(commit_index, tx_id, undo_index, data))
+    let len = sc.read_u16()? as usize;
+    let data: Page = sc.read_n(PAGE_SIZE)?.into();
+    Ok(Self {
+      commit_index,
+      tx_id,
+      undo_index,
+      len,
+      data: Arc::new(data),
+    })
   }
 }
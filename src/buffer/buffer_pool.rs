@@ -1,27 +1,38 @@
 use std::{
-  collections::BTreeMap,
+  collections::{BTreeMap, BTreeSet},
   ops::Mul,
   sync::{Arc, Mutex},
 };
 
+#[cfg(test)]
+use std::sync::atomic::{AtomicUsize, Ordering};
+
 use crate::{
-  disk::Finder, wal::CommitInfo, BackgroundThread, BackgroundWork, Error, Page, Result,
-  ShortenedMutex,
+  disk::{DiskStats, Finder},
+  wal::CommitInfo,
+  BackgroundThread, BackgroundWork, Error, Page, Result, ShortenedMutex,
 };
 
-use super::{CacheStorage, DataBlock, RollbackStorage, BLOCK_SIZE};
+use super::{CacheStorage, DataBlock, EvictionPolicy, RollbackStorage, BLOCK_SIZE};
+
+#[cfg(any(test, feature = "fault-injection"))]
+use crate::disk::FaultSpec;
 
 pub struct BufferPool {
   cache: Arc<CacheStorage>,
   rollback: Arc<RollbackStorage>,
   uncommitted: Arc<Mutex<BTreeMap<usize, Vec<usize>>>>,
   disk: Arc<Finder<BLOCK_SIZE>>,
+  /// Pages looked up by transactions, counted to test the caches above the pool.
+  #[cfg(test)]
+  reads: AtomicUsize,
 }
 impl BufferPool {
   pub fn generate(
     rollback: Arc<RollbackStorage>,
     disk: Arc<Finder<BLOCK_SIZE>>,
     max_cache_size: usize,
+    eviction: EvictionPolicy,
   ) -> (
     Self,
     BackgroundThread<(), Option<usize>>,
@@ -38,6 +49,7 @@ impl BufferPool {
 
     let cache = Arc::new(CacheStorage::new(
       max_cache_size.div_ceil(BLOCK_SIZE),
+      eviction,
       write_c,
     ));
 
@@ -106,6 +118,8 @@ impl BufferPool {
         rollback,
         uncommitted,
         disk,
+        #[cfg(test)]
+        reads: Default::default(),
       },
       flush_c,
       commit_c,
@@ -123,47 +137,463 @@ impl BufferPool {
   //   });
   // }
 
-  pub fn get(&self, commit_index: usize, index: usize) -> Result<Page> {
-    let block = {
-      match self.cache.get(&index) {
-        Some(block) => block.copy(),
-        None => {
-          let block: DataBlock = self.disk.read_to(index)?;
-          self.cache.insert(index, block.copy());
-          block
-        }
-      }
+  pub fn get(&self, tx_id: usize, commit_index: usize, index: usize) -> Result<Page> {
+    self
+      .get_with_version(tx_id, commit_index, index)
+      .map(|(_, page)| page)
+  }
+
+  /// Returns the newest page regardless of its commit index,
+  /// including pages written by transactions that are not committed yet.
+  pub fn get_latest(&self, index: usize) -> Result<Page> {
+    Ok(self.get_block(index)?.data.copy())
+  }
+
+  /// Returns the page shared with the cache instead of a copy.
+  /// The page stays in memory while the returned reference is alive,
+  /// even if the block is evicted from the cache.
+  pub fn get_ref(
+    &self,
+    tx_id: usize,
+    commit_index: usize,
+    index: usize,
+  ) -> Result<Arc<Page>> {
+    self
+      .find_version(tx_id, commit_index, index)
+      .map(|(_, page, _)| page)
+  }
+
+  /// Returns the used length of the page from the block header,
+  /// falling back to the undo record for an older version.
+  pub fn get_len(
+    &self,
+    tx_id: usize,
+    commit_index: usize,
+    index: usize,
+  ) -> Result<usize> {
+    self
+      .find_version(tx_id, commit_index, index)
+      .map(|(_, _, len)| len)
+  }
+
+  #[cfg(test)]
+  pub fn pin_count(&self, index: usize) -> usize {
+    self.cache.pin_count(&index)
+  }
+
+  /// Returns the transaction which wrote the page and is still pending,
+  /// unless it is the given transaction.
+  pub fn get_holder(&self, tx_id: usize, index: usize) -> Result<Option<usize>> {
+    let block = match self.get_block(index) {
+      Ok(block) => block,
+      Err(Error::NotFound) => return Ok(None),
+      Err(err) => return Err(err),
     };
+    if block.tx_id.eq(&tx_id) || block.commit_index.ne(&0) {
+      return Ok(None);
+    }
+    Ok(self.is_pending(block.tx_id).then_some(block.tx_id))
+  }
+
+  fn get_block(&self, index: usize) -> Result<DataBlock> {
+    match self.cache.get(&index) {
+      Some(block) => Ok(block),
+      None => {
+        let block: DataBlock = self.disk.read_to(index)?;
+        self.cache.insert(index, block.copy());
+        Ok(block)
+      }
+    }
+  }
+
+  pub fn get_with_version(
+    &self,
+    tx_id: usize,
+    commit_index: usize,
+    index: usize,
+  ) -> Result<(usize, Page)> {
+    self
+      .find_version(tx_id, commit_index, index)
+      .map(|(version, page, _)| (version, page.copy()))
+  }
 
-    if block.commit_index.le(&commit_index) {
-      return Ok(block.data.copy());
+  /// Finds the version of the page seen by the transaction at the snapshot,
+  /// and returns it with its commit index and used length.
+  fn find_version(
+    &self,
+    tx_id: usize,
+    commit_index: usize,
+    index: usize,
+  ) -> Result<(usize, Arc<Page>, usize)> {
+    #[cfg(test)]
+    self.reads.fetch_add(1, Ordering::SeqCst);
+    let mut block = self.get_block(index)?;
+    if block.commit_index.eq(&0)
+      && block.tx_id.ne(&tx_id)
+      && !self.is_pending(block.tx_id)
+    {
+      // the commit can be applied between reading the block and the check,
+      // so the block is read again to get its commit index.
+      block = self.get_block(index)?;
     }
-    match block.undo_index {
-      Some(i) => self.rollback.get(commit_index, i),
-      None => Err(Error::NotFound),
+
+    if self.is_visible(tx_id, commit_index, block.tx_id, block.commit_index) {
+      return Ok((block.commit_index, block.data, block.len));
     }
+    let undo_index = block.undo_index.ok_or(Error::NotFound)?;
+    let (version, page) = self.rollback.get_with_version(undo_index, |tx, commit| {
+      self.is_visible(tx_id, commit_index, tx, commit)
+    })?;
+    let len = page.used_len();
+    Ok((version, Arc::new(page), len))
+  }
+
+  /// A version is visible to the transaction which wrote it,
+  /// and to the others once committed at or before their snapshot.
+  /// Blocks left uncommitted by a previous run are not pending,
+  /// so they are seen as committed.
+  fn is_visible(
+    &self,
+    reader: usize,
+    snapshot: usize,
+    tx_id: usize,
+    commit_index: usize,
+  ) -> bool {
+    if tx_id.eq(&reader) {
+      return true;
+    }
+    if commit_index.gt(&snapshot) {
+      return false;
+    }
+    commit_index.ne(&0) || !self.is_pending(tx_id)
+  }
+
+  /// True if the transaction has written pages which are not committed yet.
+  pub fn is_pending(&self, tx_id: usize) -> bool {
+    self.uncommitted.l().contains_key(&tx_id)
   }
 
   pub fn insert(&self, tx_id: usize, index: usize, data: Page) -> Result<()> {
-    let undo_index = {
-      match self.cache.get(&index) {
-        Some(block) => Some(self.rollback.append(block.copy())?),
-        None => match self.disk.read_to::<DataBlock>(index) {
-          Ok(block) => Some(self.rollback.append(block)?),
-          Err(Error::NotFound) => None,
-          Err(err) => return Err(err),
-        },
+    self.insert_block(tx_id, None, index, data)
+  }
+
+  /// Inserts the page unless another transaction holds an uncommitted write
+  /// to it, or committed it after the snapshot, in which case the page was
+  /// built from a version the other writer has replaced. Fails with
+  /// `Error::WriteConflict` with no key, which the caller knows.
+  pub fn insert_checked(
+    &self,
+    tx_id: usize,
+    snapshot: usize,
+    index: usize,
+    data: Page,
+  ) -> Result<()> {
+    self.insert_block(tx_id, Some(snapshot), index, data)
+  }
+
+  fn insert_block(
+    &self,
+    tx_id: usize,
+    snapshot: Option<usize>,
+    index: usize,
+    data: Page,
+  ) -> Result<()> {
+    loop {
+      // read and chained to its undo record without the lock,
+      // so writers of different pages do not wait for each other's io.
+      let block = match self.get_block(index) {
+        Ok(block) => Some(block),
+        Err(Error::NotFound) => None,
+        Err(err) => return Err(err),
+      };
+      // checked before the undo record is appended, so a conflicting writer
+      // fails without appending one.
+      Self::check_conflict(tx_id, snapshot, &block, &self.uncommitted.l())?;
+      let undo_index = match &block {
+        Some(block) => self.undo_index_of(tx_id, block.copy())?,
+        None => None,
+      };
+
+      // held from the check to the insert, so they are not interleaved with
+      // another writer or with the commit of the holder.
+      let mut uncommitted = self.uncommitted.l();
+      let current = self.cache.get(&index);
+      if !Self::is_same_version(&block, &current) {
+        // replaced or committed while the lock was released, so the block is
+        // read again. the undo record appended for it is reused by the ring.
+        continue;
       }
+      Self::check_conflict(tx_id, snapshot, &current, &uncommitted)?;
+
+      // registered before the block is inserted,
+      // so readers never see the block without the transaction pending.
+      uncommitted.entry(tx_id).or_default().push(index);
+      let new_block = DataBlock::uncommitted(tx_id, undo_index, data);
+      self.cache.insert_new(index, new_block);
+      return Ok(());
+    }
+  }
+
+  /// A write conflicts with another transaction holding an uncommitted write
+  /// to the block, or which committed it after the snapshot.
+  /// Writes without a snapshot never conflict.
+  fn check_conflict(
+    tx_id: usize,
+    snapshot: Option<usize>,
+    block: &Option<DataBlock>,
+    uncommitted: &BTreeMap<usize, Vec<usize>>,
+  ) -> Result<()> {
+    let (Some(block), Some(snapshot)) = (block, snapshot) else {
+      return Ok(());
     };
+    let conflicted = block.tx_id.ne(&tx_id)
+      && match block.commit_index {
+        0 => uncommitted.contains_key(&block.tx_id),
+        commit_index => commit_index.gt(&snapshot),
+      };
+    if conflicted {
+      return Err(Error::WriteConflict {
+        key: vec![],
+        holder: block.tx_id,
+      });
+    }
+    Ok(())
+  }
 
-    let new_block = DataBlock::uncommitted(tx_id, undo_index, data);
-    self.cache.insert_new(index, new_block);
-    self.uncommitted.l().entry(tx_id).or_default().push(index);
+  /// Blocks share the page until it is replaced, and the commit index is the
+  /// only field changed in place.
+  fn is_same_version(read: &Option<DataBlock>, current: &Option<DataBlock>) -> bool {
+    match (read, current) {
+      (None, None) => true,
+      (Some(read), Some(current)) => {
+        Arc::ptr_eq(&read.data, &current.data)
+          && read.commit_index.eq(&current.commit_index)
+      }
+      _ => false,
+    }
+  }
+
+  /// Returns the undo record the new version of the page chains to.
+  /// A page written again by the same transaction keeps the version before
+  /// the transaction, since the one in between is never committed and would
+  /// be seen as committed by older readers once the writer is.
+  fn undo_index_of(&self, tx_id: usize, block: DataBlock) -> Result<Option<usize>> {
+    if block.tx_id.eq(&tx_id) && block.commit_index.eq(&0) {
+      return Ok(block.undo_index);
+    }
+    self.rollback.append(block).map(Some)
+  }
+
+  /// Restores the pages written by the transaction to the versions before it
+  /// from the undo records, and returns the pages which did not exist before,
+  /// so they can be freed without waiting for defragmentation.
+  pub fn abort(&self, tx_id: usize) -> Result<Vec<usize>> {
+    // held until every page is restored, so readers keep skipping the aborted blocks.
+    let mut uncommitted = self.uncommitted.l();
+    let mut indexes = uncommitted.get(&tx_id).cloned().unwrap_or_default();
+    indexes.sort_unstable();
+    indexes.dedup();
+
+    let mut freed = vec![];
+    for index in indexes {
+      let mut block = match self.get_block(index) {
+        Ok(block) => block,
+        // written by a transaction before a crash, but never flushed.
+        Err(Error::NotFound) => continue,
+        Err(err) => return Err(err),
+      };
+      while block.tx_id.eq(&tx_id) {
+        match block.undo_index {
+          Some(i) => block = self.rollback.release(i)?,
+          None => break,
+        }
+      }
+      if block.tx_id.ne(&tx_id) {
+        self.cache.insert_new(index, block);
+        continue;
+      }
+      self.cache.remove(&index);
+      freed.push(index);
+    }
+    uncommitted.remove(&tx_id);
+    Ok(freed)
+  }
+
+  /// Restores the pages written by transactions which did not commit
+  /// before a crash, since a checkpoint may have flushed them to the disk.
+  pub fn abort_crashed(&self, writes: &[(usize, usize)]) -> Result {
+    let mut transactions = BTreeSet::new();
+    {
+      let mut uncommitted = self.uncommitted.l();
+      for &(tx_id, index) in writes {
+        uncommitted.entry(tx_id).or_default().push(index);
+        transactions.insert(tx_id);
+      }
+    }
+    for tx_id in transactions {
+      self.abort(tx_id)?;
+    }
     Ok(())
   }
 
+  pub fn last_committed(&self) -> Result<(usize, usize)> {
+    let mut last_index = 0;
+    let mut last_transaction = 0;
+    for index in 0..self.disk.len()? {
+      let block: DataBlock = match self.disk.read_to(index) {
+        Ok(block) => block,
+        Err(Error::NotFound) => continue,
+        Err(err) => return Err(err),
+      };
+      last_index = block.commit_index.max(last_index);
+      last_transaction = block.tx_id.max(last_transaction);
+    }
+    Ok((last_index, last_transaction))
+  }
+
+  #[cfg(any(test, feature = "fault-injection"))]
+  pub fn set_disk_fault(&self, spec: FaultSpec) {
+    self.disk.set_fault(spec);
+  }
+
+  #[cfg(test)]
+  pub fn read_count(&self) -> usize {
+    self.reads.load(Ordering::SeqCst)
+  }
+
+  pub fn disk_stats(&self) -> DiskStats {
+    self.disk.stats()
+  }
+
   pub fn before_shutdown(&self) {
     self.cache.before_shutdown();
     self.rollback.destroy();
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use std::{path::PathBuf, sync::Arc, time::Duration};
+
+  use crate::{
+    buffer::{RollbackStorage, RollbackStorageConfig},
+    disk::{Finder, FinderConfig},
+    size,
+    wal::CommitInfo,
+    BackgroundThread, Error, Page, Result,
+  };
+
+  use super::BufferPool;
+
+  fn open(name: &str) -> (BufferPool, BackgroundThread<CommitInfo, Result>, PathBuf) {
+    let dir = std::env::temp_dir().join(format!("{name}-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let rollback = Arc::new(
+      RollbackStorage::open(RollbackStorageConfig {
+        fsync_delay: Duration::from_millis(10),
+        fsync_count: 100,
+        max_cache_size: size::mb(1),
+        max_file_size: size::mb(1),
+        path: dir.join("undo.db"),
+        file_mode: None,
+        sync_mode: Default::default(),
+        io_budget: None,
+        in_memory: false,
+      })
+      .unwrap(),
+    );
+    let disk = Arc::new(
+      Finder::open(FinderConfig {
+        path: dir.join("data.db"),
+        batch_delay: Duration::from_millis(10),
+        batch_size: 100,
+        file_mode: None,
+        sync_mode: Default::default(),
+        io_budget: None,
+        in_memory: false,
+      })
+      .unwrap(),
+    );
+    let (pool, _, commit_c) =
+      BufferPool::generate(rollback, disk, size::mb(1), Default::default());
+    (pool, commit_c, dir)
+  }
+
+  #[test]
+  fn _1() {
+    let (pool, commit_c, dir) = open("lfkv-buffer-pool");
+    let mut page = Page::new();
+    page.writer().write(&[1]).unwrap();
+    pool.insert(1, 5, page.copy()).unwrap();
+    commit_c.send_await(CommitInfo::new(1, 3)).unwrap();
+
+    assert!(matches!(pool.get(2, 2, 5), Err(Error::NotFound)));
+    assert_eq!(pool.get(2, 3, 5).unwrap(), page);
+    assert_eq!(pool.get_latest(5).unwrap(), page);
+
+    let pinned = pool.get_ref(2, 3, 5).unwrap();
+    assert_eq!(*pinned, page);
+    assert_eq!(pool.pin_count(5), 1);
+    drop(pinned);
+    assert_eq!(pool.pin_count(5), 0);
+
+    commit_c.close();
+    pool.before_shutdown();
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn _2() {
+    let (pool, commit_c, dir) = open("lfkv-buffer-pool-visibility");
+
+    let mut first = Page::new();
+    first.writer().write(&[1]).unwrap();
+    pool.insert(1, 5, first.copy()).unwrap();
+    commit_c.send_await(CommitInfo::new(1, 3)).unwrap();
+
+    let mut second = Page::new();
+    second.writer().write(&[2]).unwrap();
+    pool.insert(4, 5, second.copy()).unwrap();
+
+    assert_eq!(pool.get(4, 3, 5).unwrap(), second);
+    assert_eq!(pool.get(5, usize::MAX, 5).unwrap(), first);
+    assert_eq!(pool.get_len(5, usize::MAX, 5).unwrap(), 1);
+    assert_eq!(pool.get_latest(5).unwrap(), second);
+
+    commit_c.send_await(CommitInfo::new(4, 6)).unwrap();
+    assert_eq!(pool.get(5, 5, 5).unwrap(), first);
+    assert_eq!(pool.get(5, 6, 5).unwrap(), second);
+
+    commit_c.close();
+    pool.before_shutdown();
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn _3() {
+    let (pool, commit_c, dir) = open("lfkv-buffer-pool-rewrite");
+    let page = |b: u8| {
+      let mut page = Page::new();
+      page.writer().write(&[b]).unwrap();
+      page
+    };
+    pool.insert(1, 5, page(1)).unwrap();
+    commit_c.send_await(CommitInfo::new(1, 3)).unwrap();
+
+    // the page written twice by the same transaction.
+    pool.insert(4, 5, page(2)).unwrap();
+    pool.insert(4, 5, page(3)).unwrap();
+    commit_c.send_await(CommitInfo::new(4, 6)).unwrap();
+    assert_eq!(pool.get(5, 5, 5).unwrap(), page(1));
+    assert_eq!(pool.get(5, 6, 5).unwrap(), page(3));
+
+    pool.insert(7, 5, page(4)).unwrap();
+    pool.insert(7, 5, page(5)).unwrap();
+    pool.abort(7).unwrap();
+    assert_eq!(pool.get_latest(5).unwrap(), page(3));
+
+    commit_c.close();
+    pool.before_shutdown();
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+}
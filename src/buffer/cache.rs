@@ -8,11 +8,11 @@ use crate::{
   Serializable, ShortenedMutex,
 };
 
-use super::{DataBlock, LRUCache, BLOCK_SIZE};
+use super::{DataBlock, EvictionCache, EvictionPolicy, BLOCK_SIZE};
 
 pub struct CacheStorage(Mutex<CacheStorageCore>);
 struct CacheStorageCore {
-  cache: LRUCache<usize, DataBlock>,
+  cache: Box<dyn EvictionCache<usize, DataBlock>>,
   evicted: BTreeMap<usize, DataBlock>,
   max_cache_size: usize,
   dirty: BTreeSet<usize>,
@@ -21,10 +21,11 @@ struct CacheStorageCore {
 impl CacheStorage {
   pub fn new(
     max_cache_size: usize,
+    eviction: EvictionPolicy,
     write_c: BackgroundThread<(usize, Page<BLOCK_SIZE>), Result>,
   ) -> Self {
     Self(Mutex::new(CacheStorageCore {
-      cache: Default::default(),
+      cache: eviction.cache(),
       evicted: Default::default(),
       max_cache_size,
       dirty: Default::default(),
@@ -47,6 +48,19 @@ impl CacheStorage {
     })
   }
 
+  /// Returns the number of references to the cached page
+  /// held outside of the cache.
+  #[cfg(test)]
+  pub fn pin_count(&self, index: &usize) -> usize {
+    let core = self.0.l();
+    core
+      .cache
+      .get_only(index)
+      .or_else(|| core.evicted.get(index))
+      .map(|block| std::sync::Arc::strong_count(&block.data).saturating_sub(1))
+      .unwrap_or(0)
+  }
+
   pub fn insert(&self, index: usize, block: DataBlock) {
     let mut core = self.0.l();
     core.evicted.remove(&index);
@@ -66,6 +80,13 @@ impl CacheStorage {
     }
   }
 
+  pub fn remove(&self, index: &usize) {
+    let mut core = self.0.l();
+    core.cache.remove(index);
+    core.evicted.remove(index);
+    core.dirty.remove(index);
+  }
+
   pub fn commit(
     &self,
     index: usize,
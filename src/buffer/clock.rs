@@ -0,0 +1,266 @@
+use std::{
+  collections::HashMap,
+  hash::Hash,
+  mem::{replace, take},
+};
+
+use super::LRUCache;
+
+/// Page replacement of the buffer pool cache.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EvictionPolicy {
+  #[default]
+  Lru,
+  /// Second chance clock, cheaper per access than the lru list
+  /// at the cost of a lower hit ratio.
+  Clock,
+}
+impl EvictionPolicy {
+  pub fn cache<K, V>(&self) -> Box<dyn EvictionCache<K, V>>
+  where
+    K: Eq + Hash + Clone + Send + 'static,
+    V: Send + 'static,
+  {
+    match self {
+      EvictionPolicy::Lru => Box::new(LRUCache::new()),
+      EvictionPolicy::Clock => Box::new(ClockCache::new()),
+    }
+  }
+}
+
+/// Cache which picks the entry to evict when the owner asks for it.
+pub trait EvictionCache<K, V>: Send {
+  fn get(&mut self, k: &K) -> Option<&V>;
+  /// Reads the entry without counting it as an access.
+  fn get_only(&self, k: &K) -> Option<&V>;
+  fn get_mut(&mut self, k: &K) -> Option<&mut V>;
+  fn insert(&mut self, k: K, v: V) -> Option<V>;
+  fn remove(&mut self, k: &K) -> Option<V>;
+  fn len(&self) -> usize;
+  fn pop_old(&mut self) -> Option<(K, V)>;
+}
+impl<K, V> EvictionCache<K, V> for LRUCache<K, V>
+where
+  K: Eq + Hash + Send,
+  V: Send,
+{
+  fn get(&mut self, k: &K) -> Option<&V> {
+    LRUCache::get(self, k)
+  }
+  fn get_only(&self, k: &K) -> Option<&V> {
+    LRUCache::get_only(self, k)
+  }
+  fn get_mut(&mut self, k: &K) -> Option<&mut V> {
+    LRUCache::get_mut(self, k)
+  }
+  fn insert(&mut self, k: K, v: V) -> Option<V> {
+    LRUCache::insert(self, k, v)
+  }
+  fn remove(&mut self, k: &K) -> Option<V> {
+    LRUCache::remove(self, k)
+  }
+  fn len(&self) -> usize {
+    LRUCache::len(self)
+  }
+  fn pop_old(&mut self) -> Option<(K, V)> {
+    LRUCache::pop_old(self)
+  }
+}
+
+struct Frame<K, V> {
+  key: K,
+  value: V,
+  referenced: bool,
+}
+
+/// Circular buffer of frames with a reference bit set on access.
+/// The hand clears the bits it passes and evicts the first frame
+/// whose bit is already clear, so an entry accessed since the last pass
+/// gets a second chance.
+pub struct ClockCache<K, V> {
+  frames: Vec<Option<Frame<K, V>>>,
+  index: HashMap<K, usize>,
+  vacant: Vec<usize>,
+  hand: usize,
+}
+impl<K, V> ClockCache<K, V>
+where
+  K: Eq + Hash + Clone,
+{
+  pub fn new() -> Self {
+    Self {
+      frames: vec![],
+      index: HashMap::new(),
+      vacant: vec![],
+      hand: 0,
+    }
+  }
+
+  pub fn get(&mut self, k: &K) -> Option<&V> {
+    let i = *self.index.get(k)?;
+    let frame = self.frames[i].as_mut()?;
+    frame.referenced = true;
+    Some(&frame.value)
+  }
+
+  pub fn get_only(&self, k: &K) -> Option<&V> {
+    let i = *self.index.get(k)?;
+    self.frames[i].as_ref().map(|frame| &frame.value)
+  }
+
+  pub fn get_mut(&mut self, k: &K) -> Option<&mut V> {
+    let i = *self.index.get(k)?;
+    self.frames[i].as_mut().map(|frame| &mut frame.value)
+  }
+
+  /// Replacing an entry counts as an access,
+  /// while a new entry starts with a clear bit.
+  pub fn insert(&mut self, k: K, v: V) -> Option<V> {
+    if let Some(&i) = self.index.get(&k) {
+      let frame = self.frames[i].as_mut()?;
+      frame.referenced = true;
+      return Some(replace(&mut frame.value, v));
+    }
+
+    let frame = Some(Frame {
+      key: k.clone(),
+      value: v,
+      referenced: false,
+    });
+    let i = match self.vacant.pop() {
+      Some(i) => {
+        self.frames[i] = frame;
+        i
+      }
+      None => {
+        self.frames.push(frame);
+        self.frames.len() - 1
+      }
+    };
+    self.index.insert(k, i);
+    None
+  }
+
+  pub fn remove(&mut self, k: &K) -> Option<V> {
+    let i = self.index.remove(k)?;
+    self.vacant.push(i);
+    take(&mut self.frames[i]).map(|frame| frame.value)
+  }
+
+  pub fn len(&self) -> usize {
+    self.index.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.index.is_empty()
+  }
+
+  /// Sweeps the hand until it finds a frame with a clear bit,
+  /// which takes at most two rounds.
+  pub fn pop_old(&mut self) -> Option<(K, V)> {
+    if self.is_empty() {
+      return None;
+    }
+    loop {
+      let i = self.hand;
+      self.hand = (self.hand + 1) % self.frames.len();
+      let frame = match self.frames[i].as_mut() {
+        Some(frame) => frame,
+        None => continue,
+      };
+      if frame.referenced {
+        frame.referenced = false;
+        continue;
+      }
+
+      let frame = take(&mut self.frames[i])?;
+      self.index.remove(&frame.key);
+      self.vacant.push(i);
+      return Some((frame.key, frame.value));
+    }
+  }
+}
+impl<K, V> Default for ClockCache<K, V>
+where
+  K: Eq + Hash + Clone,
+{
+  fn default() -> Self {
+    Self::new()
+  }
+}
+impl<K, V> EvictionCache<K, V> for ClockCache<K, V>
+where
+  K: Eq + Hash + Clone + Send,
+  V: Send,
+{
+  fn get(&mut self, k: &K) -> Option<&V> {
+    ClockCache::get(self, k)
+  }
+  fn get_only(&self, k: &K) -> Option<&V> {
+    ClockCache::get_only(self, k)
+  }
+  fn get_mut(&mut self, k: &K) -> Option<&mut V> {
+    ClockCache::get_mut(self, k)
+  }
+  fn insert(&mut self, k: K, v: V) -> Option<V> {
+    ClockCache::insert(self, k, v)
+  }
+  fn remove(&mut self, k: &K) -> Option<V> {
+    ClockCache::remove(self, k)
+  }
+  fn len(&self) -> usize {
+    ClockCache::len(self)
+  }
+  fn pop_old(&mut self) -> Option<(K, V)> {
+    ClockCache::pop_old(self)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::ClockCache;
+
+  #[test]
+  fn _1() {
+    let mut cache = ClockCache::new();
+    for i in 1..=4 {
+      assert_eq!(cache.insert(i, i * 10), None);
+    }
+    assert_eq!(cache.get(&1), Some(&10));
+    assert_eq!(cache.get(&3), Some(&30));
+    assert_eq!(cache.get_only(&2), Some(&20));
+
+    // 1 is referenced and skipped, 2 was only peeked.
+    assert_eq!(cache.pop_old(), Some((2, 20)));
+    // 3 is referenced and skipped.
+    assert_eq!(cache.pop_old(), Some((4, 40)));
+    // the hand cleared the bits of 1 and 3 on its way.
+    assert_eq!(cache.pop_old(), Some((1, 10)));
+
+    assert_eq!(cache.insert(5, 50), None);
+    assert_eq!(cache.insert(3, 31), Some(30));
+    assert_eq!(cache.len(), 2);
+    assert_eq!(cache.pop_old(), Some((5, 50)));
+    assert_eq!(cache.pop_old(), Some((3, 31)));
+    assert_eq!(cache.pop_old(), None);
+  }
+
+  #[test]
+  fn _2() {
+    let mut cache = ClockCache::new();
+    for i in 0..8 {
+      cache.insert(i, i);
+    }
+    assert_eq!(cache.remove(&2), Some(2));
+    assert_eq!(cache.remove(&2), None);
+    for i in (0..8).filter(|i| i % 2 == 1) {
+      cache.get(&i);
+    }
+
+    let mut evicted = vec![];
+    while let Some((k, _)) = cache.pop_old() {
+      evicted.push(k);
+    }
+    assert_eq!(evicted, vec![0, 4, 6, 1, 3, 5, 7]);
+  }
+}
@@ -1,5 +1,8 @@
 mod lru;
-use lru::*;
+pub(crate) use lru::*;
+
+mod clock;
+pub use clock::*;
 
 mod cache;
 use cache::*;
@@ -1,19 +1,19 @@
 use std::{
   ops::{Add, DivAssign},
   path::PathBuf,
-  sync::Mutex,
+  sync::{Arc, Mutex},
   time::Duration,
 };
 
 use crate::{
-  disk::{Finder, FinderConfig},
+  disk::{Finder, FinderConfig, IoBudget, SyncMode},
   wal::CommitInfo,
   Error, Page, Result, Serializable, ShortenedMutex, PAGE_SIZE,
 };
 
 use super::{DataBlock, LRUCache};
 
-const UNDO_PAGE_SIZE: usize = PAGE_SIZE + 32;
+const UNDO_PAGE_SIZE: usize = PAGE_SIZE + 40;
 
 #[derive(Debug)]
 pub struct UndoLog {
@@ -42,13 +42,9 @@ impl UndoLog {
   }
 
   fn from_data(index: usize, data: DataBlock) -> Self {
-    Self::new(
-      index,
-      data.commit_index,
-      data.tx_id,
-      data.data,
-      data.undo_index,
-    )
+    let (commit_index, tx_id, undo_index) =
+      (data.commit_index, data.tx_id, data.undo_index);
+    Self::new(index, commit_index, tx_id, data.into_page(), undo_index)
   }
 }
 impl Clone for UndoLog {
@@ -64,13 +60,7 @@ impl Clone for UndoLog {
 }
 impl From<DataBlock> for UndoLog {
   fn from(value: DataBlock) -> Self {
-    Self::new(
-      0,
-      value.commit_index,
-      value.tx_id,
-      value.data,
-      value.undo_index,
-    )
+    Self::from_data(0, value)
   }
 }
 
@@ -81,9 +71,12 @@ impl Serializable<Error, UNDO_PAGE_SIZE> for UndoLog {
     wt.write(&self.index.to_be_bytes())?;
     wt.write(&self.commit_index.to_be_bytes())?;
     wt.write(&self.tx_id.to_be_bytes())?;
-    if let Some(i) = self.undo_index {
-      wt.write(&[1])?;
-      wt.write(&i.to_be_bytes())?;
+    match self.undo_index {
+      Some(i) => {
+        wt.write(&[1])?;
+        wt.write(&i.to_be_bytes())?;
+      }
+      None => wt.write(&[0])?,
     }
     wt.write(self.data.as_ref())?;
 
@@ -111,6 +104,10 @@ pub struct RollbackStorageConfig {
   pub max_cache_size: usize,
   pub max_file_size: usize,
   pub path: PathBuf,
+  pub file_mode: Option<u32>,
+  pub sync_mode: SyncMode,
+  pub io_budget: Option<Arc<IoBudget>>,
+  pub in_memory: bool,
 }
 
 pub struct RollbackStorage {
@@ -128,6 +125,10 @@ impl RollbackStorage {
       path: config.path.clone(),
       batch_delay: config.fsync_delay,
       batch_size: config.fsync_count,
+      file_mode: config.file_mode,
+      sync_mode: config.sync_mode,
+      io_budget: config.io_budget.clone(),
+      in_memory: config.in_memory,
     })?;
     let cache = Default::default();
     let cursor = Default::default();
@@ -166,13 +167,27 @@ impl RollbackStorage {
     Ok(())
   }
 
-  pub fn get(&self, commit_index: usize, undo_index: usize) -> Result<Page> {
+  /// Position of the undo record in the file, which is used as a ring.
+  fn position(&self, undo_index: usize) -> usize {
+    undo_index.rem_euclid(self.config.max_file_size)
+  }
+
+  /// Walks the undo chain from the undo index and returns the first version
+  /// accepted by the visibility check on its transaction id and commit index.
+  pub fn get_with_version<F>(
+    &self,
+    undo_index: usize,
+    visible: F,
+  ) -> Result<(usize, Page)>
+  where
+    F: Fn(usize, usize) -> bool,
+  {
     let mut current = undo_index;
     loop {
       let mut cache = self.cache.l();
       if let Some(log) = cache.get(&current) {
-        if commit_index.ge(&log.commit_index) {
-          return Ok(log.data.copy());
+        if visible(log.tx_id, log.commit_index) {
+          return Ok((log.commit_index, log.data.copy()));
         }
         match log.undo_index {
           Some(i) => {
@@ -183,19 +198,18 @@ impl RollbackStorage {
         }
       }
 
-      let log: UndoLog = self
-        .disk
-        .read_to(undo_index.rem_euclid(self.config.max_file_size))?;
-      if log.index.ne(&undo_index) {
-        return Err(Error::NotFound);
+      let log: UndoLog = self.disk.read_to(self.position(current))?;
+      if log.index.ne(&current) {
+        // the version is still needed, but the ring has wrapped over it.
+        return Err(Error::SnapshotExpired);
       }
 
-      cache.insert(undo_index, log.clone());
+      cache.insert(current, log.clone());
       if cache.len().ge(&self.config.max_cache_size) {
         cache.pop_old();
       }
-      if commit_index.ge(&log.commit_index) {
-        return Ok(log.data);
+      if visible(log.tx_id, log.commit_index) {
+        return Ok((log.commit_index, log.data));
       }
 
       match log.undo_index {
@@ -217,7 +231,7 @@ impl RollbackStorage {
     };
     self
       .disk
-      .batch_write_from(index, &UndoLog::from_data(index, data))?;
+      .batch_write_from(self.position(index), &UndoLog::from_data(index, data))?;
     Ok(index)
   }
 
@@ -228,7 +242,7 @@ impl RollbackStorage {
       if let Some(log) = cache.get_mut(&current) {
         if commit.tx_id.eq(&log.tx_id) {
           log.commit_index = commit.commit_index;
-          return self.disk.batch_write_from(current, log);
+          return self.disk.batch_write_from(self.position(current), log);
         }
 
         match log.undo_index {
@@ -240,18 +254,16 @@ impl RollbackStorage {
         }
       }
 
-      let mut log: UndoLog = self
-        .disk
-        .read_to(undo_index.rem_euclid(self.config.max_file_size))?;
-      if log.index.ne(&undo_index) {
+      let mut log: UndoLog = self.disk.read_to(self.position(current))?;
+      if log.index.ne(&current) {
         return Err(Error::NotFound);
       }
 
       if commit.tx_id.eq(&log.tx_id) {
         log.commit_index = commit.commit_index;
-        self.disk.batch_write_from(current, &log)?;
+        self.disk.batch_write_from(self.position(current), &log)?;
 
-        cache.insert(undo_index, log.clone());
+        cache.insert(current, log.clone());
         if cache.len().ge(&self.config.max_cache_size) {
           cache.pop_old();
         }
@@ -269,6 +281,28 @@ impl RollbackStorage {
     }
   }
 
+  /// Returns the block recorded at the undo index and drops it from the cache,
+  /// since the record is not read again once the writing transaction is aborted.
+  /// The space in the file is reused when the ring wraps around.
+  pub fn release(&self, undo_index: usize) -> Result<DataBlock> {
+    let log = match self.cache.l().remove(&undo_index) {
+      Some(log) => log,
+      None => {
+        let log: UndoLog = self.disk.read_to(self.position(undo_index))?;
+        if log.index.ne(&undo_index) {
+          return Err(Error::NotFound);
+        }
+        log
+      }
+    };
+    Ok(DataBlock::new(
+      log.commit_index,
+      log.tx_id,
+      log.undo_index,
+      log.data,
+    ))
+  }
+
   pub fn destroy(&self) {
     self.disk.close();
   }
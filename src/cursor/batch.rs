@@ -0,0 +1,45 @@
+use crate::{Page, Result, Serializable};
+
+pub enum BatchOperation {
+  Put(Vec<u8>, Box<Page>),
+  Delete(Vec<u8>),
+}
+
+#[derive(Default)]
+pub struct WriteBatch {
+  operations: Vec<BatchOperation>,
+}
+impl WriteBatch {
+  pub fn new() -> Self {
+    Default::default()
+  }
+
+  pub fn put<T>(&mut self, key: Vec<u8>, value: &T) -> Result
+  where
+    T: Serializable,
+  {
+    let page = value.serialize()?;
+    self.operations.push(BatchOperation::Put(key, Box::new(page)));
+    Ok(())
+  }
+
+  pub fn delete(&mut self, key: Vec<u8>) {
+    self.operations.push(BatchOperation::Delete(key));
+  }
+
+  pub fn len(&self) -> usize {
+    self.operations.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.operations.is_empty()
+  }
+}
+impl IntoIterator for WriteBatch {
+  type Item = BatchOperation;
+  type IntoIter = std::vec::IntoIter<BatchOperation>;
+
+  fn into_iter(self) -> Self::IntoIter {
+    self.operations.into_iter()
+  }
+}
@@ -0,0 +1,156 @@
+use crate::{Error, Result, Serializable};
+
+use super::{Cursor, ScanIter};
+
+/// Handle which stores keys under a transformed sort key, so a scan follows
+/// the collation of the sort key while the tree still compares bytes.
+/// The stored key is the escaped sort key followed by the original key,
+/// which keeps keys with an equal sort key distinct.
+pub struct Collated<'a, F> {
+  cursor: &'a Cursor,
+  sort_key: F,
+}
+impl<'a, F> Collated<'a, F>
+where
+  F: Fn(&[u8]) -> Vec<u8>,
+{
+  pub fn new(cursor: &'a Cursor, sort_key: F) -> Self {
+    Self { cursor, sort_key }
+  }
+
+  pub fn get<T>(&self, key: &[u8]) -> Result<T>
+  where
+    T: Serializable,
+  {
+    self.cursor.get(&self.encode(key))
+  }
+
+  pub fn insert<T>(&self, key: &[u8], value: T) -> Result
+  where
+    T: Serializable,
+  {
+    self.cursor.insert(self.encode(key), value)
+  }
+
+  pub fn remove(&self, key: &[u8]) -> Result {
+    self.cursor.remove(&self.encode(key))
+  }
+
+  /// Scans the keys whose sort key is in `[sort_key(start), sort_key(end))`
+  /// in the order of the sort key.
+  pub fn scan<T>(&self, start: &[u8], end: &[u8]) -> Result<CollatedIter<'a, T>>
+  where
+    T: Serializable,
+  {
+    let start = encode_sort_key(&(self.sort_key)(start));
+    let end = match end.is_empty() {
+      true => vec![],
+      false => encode_sort_key(&(self.sort_key)(end)),
+    };
+    Ok(CollatedIter {
+      inner: self.cursor.scan(&start, &end)?,
+    })
+  }
+
+  fn encode(&self, key: &[u8]) -> Vec<u8> {
+    let mut encoded = encode_sort_key(&(self.sort_key)(key));
+    encoded.extend_from_slice(key);
+    encoded
+  }
+}
+
+pub struct CollatedIter<'a, T> {
+  inner: ScanIter<'a, T>,
+}
+impl<'a, T> CollatedIter<'a, T>
+where
+  T: Serializable,
+{
+  /// Returns the next entry with its original key.
+  pub fn try_next(&mut self) -> Result<Option<(Vec<u8>, T)>> {
+    match self.inner.try_next()? {
+      Some((key, value)) => Ok(Some((decode_key(&key)?, value))),
+      None => Ok(None),
+    }
+  }
+}
+
+/// Escapes `0x00` as `0x00 0xff` and terminates with `0x00 0x00`,
+/// so the byte order of encoded keys is the order of the sort keys.
+fn encode_sort_key(sort_key: &[u8]) -> Vec<u8> {
+  let mut encoded = Vec::with_capacity(sort_key.len() + 2);
+  for &b in sort_key {
+    encoded.push(b);
+    if b.eq(&0) {
+      encoded.push(0xff);
+    }
+  }
+  encoded.extend_from_slice(&[0, 0]);
+  encoded
+}
+
+/// Returns the original key after the terminator of the sort key.
+fn decode_key(encoded: &[u8]) -> Result<Vec<u8>> {
+  let mut i = 0;
+  while i.lt(&encoded.len()) {
+    if encoded[i].eq(&0) {
+      match encoded.get(i + 1) {
+        Some(0) => return Ok(encoded[i + 2..].to_vec()),
+        Some(0xff) => i += 1,
+        _ => return Err(Error::Invalid),
+      }
+    }
+    i += 1;
+  }
+  Err(Error::Invalid)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{decode_key, encode_sort_key};
+
+  fn encode(key: &[u8]) -> Vec<u8> {
+    let mut encoded = encode_sort_key(&key.to_ascii_lowercase());
+    encoded.extend_from_slice(key);
+    encoded
+  }
+
+  #[test]
+  fn _1() {
+    let keys: [&[u8]; 5] = [b"banana", b"Apple", b"cherry", b"apple", b"Bananas"];
+    let mut encoded = keys.iter().map(|k| encode(k)).collect::<Vec<_>>();
+    encoded.sort();
+
+    let decoded = encoded
+      .iter()
+      .map(|k| decode_key(k).unwrap())
+      .collect::<Vec<_>>();
+    assert_eq!(
+      decoded,
+      vec![
+        b"Apple".to_vec(),
+        b"apple".to_vec(),
+        b"banana".to_vec(),
+        b"Bananas".to_vec(),
+        b"cherry".to_vec(),
+      ]
+    );
+
+    let start = encode_sort_key(b"b");
+    let end = encode_sort_key(b"c");
+    let in_range = encoded
+      .iter()
+      .filter(|k| k.ge(&&start) && k.lt(&&end))
+      .count();
+    assert_eq!(in_range, 2);
+  }
+
+  #[test]
+  fn _2() {
+    let key = b"a\0b";
+    assert!(encode(key).lt(&encode(b"a\0c")));
+    assert!(encode(b"a").lt(&encode(key)));
+    assert_eq!(decode_key(&encode(key)).unwrap(), key.to_vec());
+    assert!(decode_key(b"abc").is_err());
+  }
+}
@@ -1,134 +1,1279 @@
-use std::sync::{Arc, RwLock};
+use std::{
+  collections::{BTreeMap, BTreeSet},
+  io::Write,
+  ops::{Add, AddAssign},
+  sync::{
+    atomic::{AtomicBool, AtomicIsize, AtomicUsize, Ordering},
+    Arc, Mutex, RwLock, RwLockReadGuard,
+  },
+  time::{Duration, Instant},
+};
+
+use crossbeam::channel::{bounded, Receiver};
 
 use crate::{
-  buffer::{BufferPool, BLOCK_SIZE},
+  buffer::{BufferPool, LRUCache, BLOCK_SIZE},
   disk::FreeList,
   logger,
   wal::WriteAheadLog,
-  Error, Result, Serializable, ShortenedRwLock,
+  Error, Page, Result, Serializable, ShortenedMutex, ShortenedRwLock,
 };
 
 use super::{
-  CursorEntry, CursorWriter, InternalNode, LeafNode, TreeHeader, HEADER_INDEX,
-  MAX_NODE_LEN,
+  abort_writer, corruption, verify_pages, ActiveTransactions, BatchOperation, Clock,
+  Collated, CursorEntry, CursorWriter, Expirable, HotKeys, InternalNode, KeyComparator,
+  Keyspace, KeyspaceInfo, LeafNode, MultiScan, ProbeHints, ResumeToken, ScanItem,
+  ScanIter, ScanMap, ScanPage, Snapshots, TombstoneScan, TransactionHandle, TreeHeader,
+  UnexpiredScan, ValueGuard, WriteBatch, DEFAULT_KEYSPACE, HEADER_INDEX, MAX_NODE_LEN,
 };
 
+const MAX_READ_CACHE_LEN: usize = 64;
+const SCAN_CHANNEL_SIZE: usize = 64;
+
+/// Previous state of a key written by the cursor.
+struct WriteLog {
+  keyspace: Vec<u8>,
+  key: Vec<u8>,
+  existed: bool,
+  /// Page of the key before the write, kept only when undo is enabled.
+  previous: Option<Page>,
+}
+
+/// State of a key as seen by a transaction, telling a key which was
+/// never there apart from a key deleted by the transaction itself.
+#[derive(Debug, PartialEq, Eq)]
+pub enum KeyState<T> {
+  Absent,
+  Present(T),
+  /// Existed before the transaction, which deleted it and has not committed.
+  DeletedInTx,
+}
+
+/// What a transaction reads besides its own writes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum IsolationLevel {
+  /// The commits before the transaction began, for its whole lifetime.
+  #[default]
+  Snapshot,
+  /// The commits before each operation began, so a key read twice can
+  /// change between the reads. Old versions are looked up in the undo log
+  /// only for commits made during the operation.
+  ReadCommitted,
+}
+
 pub struct Cursor {
   committed: Arc<RwLock<bool>>,
+  timed_out: Arc<AtomicBool>,
   freelist: Arc<FreeList<BLOCK_SIZE>>,
-  writer: CursorWriter,
+  writer: Arc<CursorWriter>,
+  transactions: Arc<ActiveTransactions>,
+  snapshots: Arc<Snapshots>,
+  clock: Arc<dyn Clock>,
+  /// Values of the default keyspace resolved by `get`, dropped on writes
+  /// of this cursor to the key. Scans read through without filling it.
+  read_cache: Mutex<LRUCache<Vec<u8>, Page>>,
+  hot_keys: Arc<HotKeys>,
+  writes: Mutex<Vec<WriteLog>>,
+  isolation: IsolationLevel,
+  undo: bool,
+  /// Keys committed in all keyspaces, shared by every cursor of the engine.
+  key_count: Arc<AtomicUsize>,
+  /// Keys inserted minus keys removed by this cursor, added on commit.
+  key_delta: AtomicIsize,
 }
 impl Cursor {
   pub fn new(
     freelist: Arc<FreeList<BLOCK_SIZE>>,
     wal: Arc<WriteAheadLog>,
     buffer: Arc<BufferPool>,
+    clock: Arc<dyn Clock>,
+    hot_keys: Arc<HotKeys>,
+    transactions: Arc<ActiveTransactions>,
+    snapshots: Arc<Snapshots>,
   ) -> Result<Self> {
     let (tx_id, last_commit_index) = wal.new_transaction()?;
     logger::info(format!(
       "cursor id {} and lsn {} init",
       tx_id, last_commit_index
     ));
+    let committed = Arc::new(RwLock::new(false));
+    let timed_out = Arc::new(AtomicBool::new(false));
+    let writer = Arc::new(CursorWriter::new(tx_id, last_commit_index, wal, buffer));
+    transactions.register(TransactionHandle::new(
+      committed.clone(),
+      timed_out.clone(),
+      writer.clone(),
+      freelist.clone(),
+    ));
     Ok(Self {
-      committed: Arc::new(RwLock::new(false)),
+      committed,
+      timed_out,
       freelist,
-      writer: CursorWriter::new(tx_id, last_commit_index, wal, buffer),
+      writer,
+      transactions,
+      snapshots,
+      clock,
+      read_cache: Default::default(),
+      hot_keys,
+      writes: Default::default(),
+      isolation: Default::default(),
+      undo: false,
+      key_count: Default::default(),
+      key_delta: AtomicIsize::new(0),
     })
   }
 
-  pub fn initialize(&self) -> Result {
-    if let Err(Error::NotFound) = self.writer.get::<TreeHeader>(HEADER_INDEX) {
-      logger::info("there are no tree header and will be initialized");
-      let header = TreeHeader::initial_state();
-      let root = header.get_root();
-      self.writer.insert(HEADER_INDEX, header)?;
-      self
-        .writer
-        .insert(root, CursorEntry::Leaf(LeafNode::empty()))?;
-      self.freelist.fetch(root + 1);
+  /// Id of the transaction, as reported by `Error::WriteConflict`.
+  pub fn get_id(&self) -> usize {
+    self.writer.get_id()
+  }
+
+  /// Creates the tree header with the comparator on a new database,
+  /// or checks that an existing database was created with the same one.
+  pub fn initialize(&self, comparator: KeyComparator) -> Result {
+    let header = match self.writer.get::<TreeHeader>(HEADER_INDEX) {
+      Ok(header) => header,
+      Err(Error::NotFound) => {
+        logger::info("there are no tree header and will be initialized");
+        let header = TreeHeader::initial_state(comparator);
+        let root = header.get_root();
+        self.writer.insert(HEADER_INDEX, header)?;
+        self
+          .writer
+          .insert(root, CursorEntry::Leaf(LeafNode::empty()))?;
+        self.freelist.fetch(root + 1);
+        return Ok(());
+      }
+      Err(err) => return Err(err),
     };
-    Ok(())
+    match header.get_comparator() {
+      existing if existing.eq(&comparator) => Ok(()),
+      existing => Err(Error::ComparatorMismatch(existing)),
+    }
   }
 
   pub fn get<T>(&self, key: &Vec<u8>) -> Result<T>
   where
     T: Serializable,
   {
-    if self.committed.rl().eq(&true) {
-      return Err(Error::TransactionClosed);
+    let _open = self.ensure_open()?;
+
+    if let Some(page) = self.read_cache.l().get(key) {
+      return page.deserialize();
     }
 
-    let i = self.get_index(key)?;
-    self.writer.get(i)
+    let i = self.get_index(DEFAULT_KEYSPACE, key)?;
+    let page: Page = self.writer.get(i)?;
+    let mut cache = self.read_cache.l();
+    cache.insert(key.clone(), page.copy());
+    if cache.len().gt(&MAX_READ_CACHE_LEN) {
+      cache.pop_old();
+    }
+    page.deserialize()
+  }
+
+  /// Reads the keys in key order, returning the values in the order of
+  /// the keys. Each probe reuses the nodes of the previous one and searches
+  /// them from its slot, so sorted neighbouring keys cost few comparisons.
+  pub fn get_many<T>(&self, keys: &[Vec<u8>]) -> Result<Vec<Option<T>>>
+  where
+    T: Serializable,
+  {
+    let _open = self.ensure_open()?;
+
+    let mut values = keys.iter().map(|_| None).collect::<Vec<_>>();
+    let header: TreeHeader = self.writer.get(HEADER_INDEX)?;
+    let cmp = header.get_comparator();
+    let root = match header.get_keyspace_root(DEFAULT_KEYSPACE) {
+      Some(root) => root,
+      None => return Ok(values),
+    };
+    let mut order = (0..keys.len()).collect::<Vec<_>>();
+    order.sort_by(|&a, &b| cmp.compare(&keys[a], &keys[b]));
+
+    let mut hints: ProbeHints<CursorEntry> = ProbeHints::new();
+    for i in order {
+      let (mut index, mut depth) = (root, 0);
+      let found = loop {
+        let found = match hints.get(depth, index) {
+          Some((entry, slot)) => {
+            let (slot, found) = entry.find_or_next_from(&keys[i], cmp, Some(slot));
+            hints.update_slot(depth, slot);
+            found
+          }
+          None => {
+            let entry: CursorEntry = self.writer.get(index)?;
+            let (slot, found) = entry.find_or_next_from(&keys[i], cmp, None);
+            hints.set(depth, index, entry, slot);
+            found
+          }
+        };
+        match found {
+          Ok(data) => break Some(data),
+          Err(Some(child)) => (index, depth) = (child, depth.add(1)),
+          Err(None) => break None,
+        }
+      };
+      if let Some(data) = found {
+        values[i] = Some(self.writer.get(data)?);
+      }
+    }
+    Ok(values)
+  }
+
+  /// Reads the key like `get`, and for a missing key tells whether this
+  /// transaction deleted it. A key inserted and then deleted by the
+  /// transaction did not exist before it, so it is absent.
+  pub fn get_state<T>(&self, key: &Vec<u8>) -> Result<KeyState<T>>
+  where
+    T: Serializable,
+  {
+    match self.get(key) {
+      Ok(value) => return Ok(KeyState::Present(value)),
+      Err(Error::NotFound) => {}
+      Err(err) => return Err(err),
+    };
+    let existed = self
+      .writes
+      .l()
+      .iter()
+      .find(|log| log.keyspace.eq(DEFAULT_KEYSPACE) && log.key.eq(key))
+      .is_some_and(|log| log.existed);
+    match existed {
+      true => Ok(KeyState::DeletedInTx),
+      false => Ok(KeyState::Absent),
+    }
+  }
+
+  /// Borrows the raw page of the value without copying it.
+  /// See `ValueGuard` for how long the page is pinned.
+  pub fn get_ref(&self, key: &Vec<u8>) -> Result<Option<ValueGuard>> {
+    let _open = self.ensure_open()?;
+
+    match self.get_index(DEFAULT_KEYSPACE, key) {
+      Ok(index) => Ok(Some(ValueGuard::new(self.writer.get_ref(index)?))),
+      Err(Error::NotFound) => Ok(None),
+      Err(err) => Err(err),
+    }
+  }
+
+  pub fn get_with_version<T>(&self, key: &Vec<u8>) -> Result<(usize, T)>
+  where
+    T: Serializable,
+  {
+    let _open = self.ensure_open()?;
+
+    let i = self.get_index(DEFAULT_KEYSPACE, key)?;
+    self.writer.get_with_version(i)
+  }
+
+  /// Writes the value only if the version of the key is the expected one,
+  /// none if the key must be absent, and returns whether it was written.
+  /// A key written by this transaction has the version 0 until committed.
+  pub fn put_if_version<T>(
+    &self,
+    key: Vec<u8>,
+    expected: Option<usize>,
+    value: T,
+  ) -> Result<bool>
+  where
+    T: Serializable,
+  {
+    let current = match self.get_with_version::<Page>(&key) {
+      Ok((version, _)) => Some(version),
+      Err(Error::NotFound) => None,
+      Err(err) => return Err(err),
+    };
+    if current.ne(&expected) {
+      return Ok(false);
+    }
+
+    self.insert(key, value)?;
+    Ok(true)
+  }
+
+  /// Removes the key only if its value is the expected bytes,
+  /// and returns whether it was removed.
+  pub fn delete_if(&self, key: &Vec<u8>, expected: &[u8]) -> Result<bool> {
+    let _open = self.ensure_open()?;
+
+    let mut expected_page = Page::new();
+    expected_page.writer().write(expected)?;
+    let current: Page = match self.get_index(DEFAULT_KEYSPACE, key) {
+      Ok(index) => self.writer.get(index)?,
+      Err(Error::NotFound) => return Ok(false),
+      Err(err) => return Err(err),
+    };
+    if current.ne(&expected_page) {
+      return Ok(false);
+    }
+
+    self.remove(key)?;
+    Ok(true)
+  }
+
+  /// Exchanges the values of the keys in this transaction, so the exchange
+  /// commits or aborts as a whole. If only one of the keys exists,
+  /// its value moves to the other key. Existing keys are overwritten
+  /// in place, so the leaves are not touched when both keys exist.
+  pub fn swap(&self, a: &Vec<u8>, b: &Vec<u8>) -> Result {
+    let _open = self.ensure_open()?;
+
+    let read = |key: &Vec<u8>| -> Result<Option<Page>> {
+      match self.get_index(DEFAULT_KEYSPACE, key) {
+        Ok(index) => self.writer.get(index).map(Some),
+        Err(Error::NotFound) => Ok(None),
+        Err(err) => Err(err),
+      }
+    };
+    match (read(a)?, read(b)?) {
+      (Some(va), Some(vb)) => {
+        self.insert(a.clone(), vb)?;
+        self.insert(b.clone(), va)
+      }
+      (Some(va), None) => {
+        self.insert(b.clone(), va)?;
+        self.remove(a)
+      }
+      (None, Some(vb)) => {
+        self.insert(a.clone(), vb)?;
+        self.remove(b)
+      }
+      (None, None) => Ok(()),
+    }
   }
 
   pub fn insert<T>(&self, key: Vec<u8>, value: T) -> Result
   where
     T: Serializable,
   {
-    if self.committed.rl().eq(&true) {
-      return Err(Error::TransactionClosed);
+    let _open = self.ensure_open()?;
+
+    self.hot_keys.record(&key);
+    self.insert_in(DEFAULT_KEYSPACE, key, value)
+  }
+
+  /// Inserts the value and returns the previous value of the key if exists.
+  pub fn insert_returning<T>(&self, key: Vec<u8>, value: T) -> Result<Option<T>>
+  where
+    T: Serializable,
+  {
+    let _open = self.ensure_open()?;
+
+    let previous = match self.get_index(DEFAULT_KEYSPACE, &key) {
+      Ok(index) => Some(self.writer.get(index)?),
+      Err(Error::NotFound) => None,
+      Err(err) => return Err(err),
+    };
+    self.insert(key, value)?;
+    Ok(previous)
+  }
+
+  /// Pending writes of this cursor are seen by the scan in every leaf,
+  /// since the pages it wrote are read back through the buffer pool.
+  /// Leaves split by other transactions during the scan are read
+  /// as of the snapshot, so the scan yields neither gaps nor duplicates.
+  pub fn scan<T>(&self, start: &Vec<u8>, end: &[u8]) -> Result<ScanIter<'_, T>>
+  where
+    T: Serializable,
+  {
+    let _open = self.ensure_open()?;
+
+    ScanIter::seek(&self.writer, DEFAULT_KEYSPACE, start, end)
+  }
+
+  /// Scans the range including the keys deleted by this transaction,
+  /// which are yielded with `None` in key order among the live keys.
+  /// Deletes are applied to the tree in place and only this transaction
+  /// remembers them, so once committed a deleted key simply won't appear.
+  pub fn scan_with_tombstones<T>(
+    &self,
+    start: &Vec<u8>,
+    end: &[u8],
+  ) -> Result<TombstoneScan<'_, T>>
+  where
+    T: Serializable,
+  {
+    let _open = self.ensure_open()?;
+
+    let live = ScanIter::seek(&self.writer, DEFAULT_KEYSPACE, start, end)?;
+    let cmp = live.get_comparator();
+    // a key with a previous version was live before one of the writes,
+    // so it is deleted if the scan does not find it.
+    let tombstones = self
+      .writes
+      .l()
+      .iter()
+      .filter(|log| log.keyspace.eq(DEFAULT_KEYSPACE) && log.existed)
+      .filter(|log| cmp.ge(&log.key, start) && cmp.lt(&log.key, end))
+      .map(|log| log.key.clone())
+      .collect();
+    Ok(TombstoneScan::new(live, tombstones))
+  }
+
+  /// Scans the range skipping entries whose stored value size is rejected
+  /// by the filter, without reading those values.
+  pub fn scan_filtered<'a, T, F>(
+    &'a self,
+    start: &Vec<u8>,
+    end: &[u8],
+    filter: F,
+  ) -> Result<ScanIter<'a, T>>
+  where
+    T: Serializable,
+    F: Fn(usize) -> bool + 'a,
+  {
+    Ok(self.scan(start, end)?.with_size_filter(filter))
+  }
+
+  /// Scans the range yielding only the keys accepted by the predicate.
+  /// The predicate runs on the keys of the leaves, so the values of
+  /// rejected keys are never read.
+  pub fn scan_keys_where<'a, T, F>(
+    &'a self,
+    start: &Vec<u8>,
+    end: &[u8],
+    predicate: F,
+  ) -> Result<ScanIter<'a, T>>
+  where
+    T: Serializable,
+    F: Fn(&[u8]) -> bool + 'a,
+  {
+    Ok(self.scan(start, end)?.with_key_filter(predicate))
+  }
+
+  /// Scans several ranges in one descent and one walk of the leaf chain,
+  /// yielding each entry with the position of its range in `ranges`.
+  /// Entries are yielded in key order, and a key in overlapping ranges is
+  /// yielded once per range in the order of the ranges.
+  pub fn scan_multi<T>(&self, ranges: Vec<(Vec<u8>, Vec<u8>)>) -> Result<MultiScan<'_, T>>
+  where
+    T: Serializable,
+  {
+    let _open = self.ensure_open()?;
+
+    MultiScan::seek(&self.writer, DEFAULT_KEYSPACE, ranges)
+  }
+
+  /// Scans the range applying the projection to each key and value as they
+  /// are read, so no owned key or value is allocated for the entry.
+  /// The value is the raw page as `get_ref` returns it, and both slices are
+  /// only valid during the call.
+  pub fn scan_map<'a, R, F>(
+    &'a self,
+    start: &Vec<u8>,
+    end: &[u8],
+    f: F,
+  ) -> Result<ScanMap<'a, F>>
+  where
+    F: FnMut(&[u8], &[u8]) -> R,
+  {
+    Ok(ScanMap::new(self.scan(start, end)?, f))
+  }
+
+  /// Scans the range failing with `Error::Timeout` once the deadline passes,
+  /// checked before reading each leaf after the first.
+  /// The resume token of the scan continues after the last yielded key.
+  pub fn scan_until<T>(
+    &self,
+    start: &Vec<u8>,
+    end: &[u8],
+    deadline: Instant,
+  ) -> Result<ScanIter<'_, T>>
+  where
+    T: Serializable,
+  {
+    Ok(self.scan(start, end)?.with_deadline(deadline))
+  }
+
+  /// Scans the range while a background thread reads up to `depth` leaves
+  /// ahead with their values, so the reads of a scan over a cold cache
+  /// overlap with the consumption of the entries before them.
+  /// Entries are yielded in key order as `scan` yields them.
+  pub fn scan_prefetch<T>(
+    &self,
+    start: &Vec<u8>,
+    end: &[u8],
+    depth: usize,
+  ) -> Result<ScanIter<'_, T>>
+  where
+    T: Serializable,
+  {
+    self
+      .scan(start, end)?
+      .with_prefetch(self.writer.snapshot(), depth)
+  }
+
+  /// Runs the scan on a background thread and streams the results
+  /// through a bounded channel. The scan stops when the receiver is dropped,
+  /// or with the closed error once the transaction is committed or aborted.
+  pub fn scan_channel<T>(&self, start: &[u8], end: &[u8]) -> Result<Receiver<ScanItem<T>>>
+  where
+    T: Serializable + Send + 'static,
+  {
+    let _open = self.ensure_open()?;
+
+    let writer = self.writer.snapshot();
+    let start = start.to_vec();
+    let end = end.to_vec();
+    let (tx, rx) = bounded(SCAN_CHANNEL_SIZE);
+    let committed = self.committed.clone();
+    let timed_out = self.timed_out.clone();
+    std::thread::Builder::new()
+      .name(format!("cursor {} scan", writer.get_id()))
+      .spawn(move || {
+        let iter = match ScanIter::seek(&writer, DEFAULT_KEYSPACE, &start, &end) {
+          Ok(iter) => iter,
+          Err(err) => {
+            tx.send(Err(err)).ok();
+            return;
+          }
+        };
+        for item in iter.items() {
+          if committed.rl().eq(&true) {
+            let err = match timed_out.load(Ordering::SeqCst) {
+              true => Error::TransactionTimedOut,
+              false => Error::TransactionClosed,
+            };
+            tx.send(Err(err)).ok();
+            return;
+          }
+          if tx.send(item).is_err() {
+            return;
+          }
+        }
+      })
+      .map_err(Error::IO)?;
+    Ok(rx)
+  }
+
+  /// Scans at most `limit` items of the range,
+  /// and returns a token for the next page if the page is full.
+  pub fn scan_page<T>(
+    &self,
+    start: &Vec<u8>,
+    end: &[u8],
+    limit: usize,
+  ) -> Result<ScanPage<T>>
+  where
+    T: Serializable,
+  {
+    read_page(self.scan(start, end)?, limit)
+  }
+
+  /// Continues a paginated scan after the last key of the previous page.
+  pub fn scan_resume<T>(&self, token: &ResumeToken, limit: usize) -> Result<ScanPage<T>>
+  where
+    T: Serializable,
+  {
+    let _open = self.ensure_open()?;
+
+    let iter = ScanIter::seek_from(
+      &self.writer,
+      DEFAULT_KEYSPACE,
+      token.get_start(),
+      token.is_exclusive(),
+      token.get_end(),
+    )?;
+    read_page(iter, limit)
+  }
+
+  /// Sets the isolation of the reads. A scan in progress keeps reading
+  /// its remaining leaves at the commit of the latest operation.
+  pub fn with_isolation(mut self, isolation: IsolationLevel) -> Self {
+    self.isolation = isolation;
+    self
+  }
+
+  /// Keeps the previous page of every write so that `undo_last` can
+  /// revert it. Without this each write records only whether the key existed.
+  pub fn with_undo(mut self) -> Self {
+    self.undo = true;
+    self
+  }
+
+  /// Shares the count of committed keys with other cursors,
+  /// which the commit of this cursor brings up to date.
+  pub fn with_key_count(mut self, key_count: Arc<AtomicUsize>) -> Self {
+    self.key_count = key_count;
+    self
+  }
+
+  /// Pins the cursor to an older snapshot for reading.
+  pub fn with_snapshot(mut self, commit_index: usize) -> Self {
+    *self.read_cache.l() = Default::default();
+    let mut writer = self.writer.snapshot();
+    writer.set_snapshot(commit_index);
+    self.writer = Arc::new(writer);
+    self
+  }
+
+  pub fn range_map<T>(
+    &self,
+    start: &Vec<u8>,
+    end: &[u8],
+    limit: Option<usize>,
+  ) -> Result<BTreeMap<Vec<u8>, T>>
+  where
+    T: Serializable,
+  {
+    let mut iter = self.scan(start, end)?;
+    let mut map = BTreeMap::new();
+    while let Some((key, value)) = iter.try_next()? {
+      if limit.map(|l| map.len().ge(&l)).unwrap_or(false) {
+        return Err(Error::ResultTooLarge);
+      }
+      map.insert(key, value);
     }
+    Ok(map)
+  }
 
-    match self.get_index(&key) {
-      Ok(index) => self.writer.insert(index, value),
-      Err(Error::NotFound) => {
-        let mut header: TreeHeader = self.writer.get(HEADER_INDEX)?;
-        if let Ok((s, i)) = self.append_at(header.get_root(), key, value)? {
-          let nri = self.freelist.acquire();
-          let new_root = CursorEntry::Internal(InternalNode {
-            keys: vec![s],
-            children: vec![header.get_root(), i],
+  pub fn remove(&self, key: &Vec<u8>) -> Result {
+    let _open = self.ensure_open()?;
+
+    self.remove_in(DEFAULT_KEYSPACE, key)
+  }
+
+  /// Returns a handle to the named keyspace, which is created on first insert.
+  pub fn keyspace(&self, name: &[u8]) -> Keyspace<'_> {
+    Keyspace::new(self, name)
+  }
+
+  /// Returns a handle which orders keys by the given sort key on scans.
+  pub fn collated<F>(&self, sort_key: F) -> Collated<'_, F>
+  where
+    F: Fn(&[u8]) -> Vec<u8>,
+  {
+    Collated::new(self, sort_key)
+  }
+
+  pub fn apply(&self, batch: WriteBatch) -> Result {
+    for operation in batch {
+      match operation {
+        BatchOperation::Put(key, page) => self.insert(key, *page)?,
+        BatchOperation::Delete(key) => match self.remove(&key) {
+          Ok(_) | Err(Error::NotFound) => {}
+          Err(err) => return Err(err),
+        },
+      }
+    }
+    Ok(())
+  }
+
+  /// Inserts pairs which are sorted ascending with unique keys.
+  /// Consecutive keys are appended to the last leaf without descending
+  /// from the root until the leaf is full or the key is beyond its bound,
+  /// and the leaf is written once before moving on.
+  /// Returns `Error::NotSorted` at the first key out of order.
+  pub fn put_all_sorted<T, I>(&self, pairs: I) -> Result
+  where
+    T: Serializable,
+    I: IntoIterator<Item = (Vec<u8>, T)>,
+  {
+    let _open = self.ensure_open()?;
+
+    let mut leaf = None;
+    let mut pending = false;
+    let result = self.append_sorted(pairs, &mut leaf, &mut pending);
+    // the leaf is written even on error so the appended keys stay reachable.
+    if let Some((index, node, _)) = leaf.filter(|_| pending) {
+      self.writer.insert(index, node)?;
+    }
+    result
+  }
+
+  /// Appends pairs to `leaf` while they fit, setting `pending` until the
+  /// leaf is written back.
+  fn append_sorted<T, I>(
+    &self,
+    pairs: I,
+    leaf: &mut Option<(usize, LeafNode, Option<Vec<u8>>)>,
+    pending: &mut bool,
+  ) -> Result
+  where
+    T: Serializable,
+    I: IntoIterator<Item = (Vec<u8>, T)>,
+  {
+    let header: TreeHeader = self.writer.get(HEADER_INDEX)?;
+    let cmp = header.get_comparator();
+    let mut previous: Option<Vec<u8>> = None;
+    for (key, value) in pairs {
+      ensure_ascending(previous.as_ref(), &key, cmp)?;
+      previous = Some(key.clone());
+      self.read_cache.l().remove(&key);
+      self.hot_keys.record(&key);
+
+      if let Some((_, node, upper)) = leaf.as_mut() {
+        let fits = node.len().lt(&MAX_NODE_LEN)
+          && node
+            .keys
+            .last()
+            .map(|(k, _)| cmp.lt(k, &key))
+            .unwrap_or(false)
+          && upper.as_ref().map(|u| cmp.lt(&key, u)).unwrap_or(true);
+        if fits {
+          let value = value.serialize()?;
+          self
+            .writer
+            .log_change(DEFAULT_KEYSPACE, &key, Some(&value))?;
+          let pi = self.freelist.acquire();
+          self.writer.insert(pi, value)?;
+          node.keys.push((key.clone(), pi));
+          *pending = true;
+          self.writes.l().push(WriteLog {
+            keyspace: DEFAULT_KEYSPACE.to_vec(),
+            key,
+            existed: false,
+            previous: None,
           });
-          self.writer.insert(nri, new_root)?;
+          self.key_delta.fetch_add(1, Ordering::SeqCst);
+          continue;
+        }
+      }
 
-          header.set_root(nri);
-          self.writer.insert(HEADER_INDEX, header)?;
+      if let Some((index, node, _)) = leaf.take().filter(|_| *pending) {
+        self.writer.insert(index, node)?;
+      }
+      *pending = false;
+      self.insert_in(DEFAULT_KEYSPACE, key.clone(), value)?;
+      *leaf = Some(find_leaf(&self.writer, DEFAULT_KEYSPACE, &key)?);
+    }
+    Ok(())
+  }
+
+  pub fn set_ttl<T>(&self, key: Vec<u8>, value: T, ttl: Duration) -> Result
+  where
+    T: Serializable,
+  {
+    self.insert(key, Expirable::new(value, self.clock.now(), ttl))
+  }
+
+  pub fn get_unexpired<T>(&self, key: &Vec<u8>) -> Result<T>
+  where
+    T: Serializable,
+  {
+    let value: Expirable<T> = self.get(key)?;
+    if value.is_expired(self.clock.now()) {
+      return Err(Error::NotFound);
+    }
+    Ok(value.into_inner())
+  }
+
+  /// Scans a range of values written by `set_ttl`, skipping the expired ones.
+  pub fn scan_unexpired<T>(
+    &self,
+    start: &Vec<u8>,
+    end: &[u8],
+  ) -> Result<UnexpiredScan<'_, T>>
+  where
+    T: Serializable,
+  {
+    Ok(UnexpiredScan::new(self.scan(start, end)?, self.clock.now()))
+  }
+
+  /// Removes the expired keys in a range of values written by `set_ttl`,
+  /// and returns how many were removed. Their pages are freed at commit.
+  /// Values in the range written otherwise are read as an expiry.
+  pub fn purge_expired(&self, start: &Vec<u8>, end: &[u8]) -> Result<usize> {
+    let now = self.clock.now();
+    let mut expired = vec![];
+    let mut iter = self.scan::<Expirable<Page>>(start, end)?;
+    while let Some((key, value)) = iter.try_next()? {
+      if value.is_expired(now) {
+        expired.push(key);
+      }
+    }
+    drop(iter);
+    for key in &expired {
+      self.remove(key)?;
+    }
+    Ok(expired.len())
+  }
+
+  /// Number of keys in all keyspaces committed so far, with the keys
+  /// inserted and removed by this cursor. It does not follow the snapshot,
+  /// so commits made after the cursor began are counted too.
+  pub fn key_count(&self) -> Result<usize> {
+    let _open = self.ensure_open()?;
+
+    let delta = self.key_delta.load(Ordering::SeqCst);
+    let count = self.key_count.load(Ordering::SeqCst);
+    Ok(count.saturating_add_signed(delta))
+  }
+
+  /// Number of keys recorded in the tree header at the last clean shutdown.
+  pub fn stored_key_count(&self) -> Result<usize> {
+    let _open = self.ensure_open()?;
+
+    let header: TreeHeader = self.writer.get(HEADER_INDEX)?;
+    Ok(header.get_key_count())
+  }
+
+  /// Records the number of keys in the tree header, so the next open
+  /// does not have to count them.
+  pub fn store_key_count(&self, count: usize) -> Result {
+    let _open = self.ensure_open()?;
+
+    let mut header: TreeHeader = self.writer.get(HEADER_INDEX)?;
+    header.set_key_count(count);
+    self.writer.insert(HEADER_INDEX, header)
+  }
+
+  /// Lists the default keyspace and every named keyspace with the number
+  /// of keys in its tree, which is counted by walking the leaves.
+  pub fn keyspaces(&self) -> Result<Vec<KeyspaceInfo>> {
+    let _open = self.ensure_open()?;
+
+    let header: TreeHeader = self.writer.get(HEADER_INDEX)?;
+    let mut keyspaces = vec![(DEFAULT_KEYSPACE.to_vec(), header.get_root())];
+    keyspaces.extend(header.get_keyspaces());
+    keyspaces
+      .into_iter()
+      .map(|(name, root)| {
+        let mut key_count = 0;
+        self.walk_subtree(root, &mut |_, entry| {
+          if let CursorEntry::Leaf(leaf) = entry {
+            key_count.add_assign(leaf.len());
+          }
+        })?;
+        Ok(KeyspaceInfo {
+          name,
+          root,
+          key_count,
+        })
+      })
+      .collect()
+  }
+
+  /// Removes the named keyspace with every key in it and releases
+  /// the pages of its tree, which return to the freelist on commit.
+  /// Returns false if the keyspace does not exist.
+  /// Fails with `Error::Invalid` for the default keyspace.
+  pub fn drop_keyspace(&self, name: &[u8]) -> Result<bool> {
+    let _open = self.ensure_open()?;
+
+    if name.eq(DEFAULT_KEYSPACE) {
+      return Err(Error::Invalid);
+    }
+    let mut header: TreeHeader = self.writer.get(HEADER_INDEX)?;
+    let root = match header.remove_keyspace(name) {
+      Some(root) => root,
+      None => return Ok(false),
+    };
+
+    let mut released = vec![];
+    let mut keys = vec![];
+    self.walk_subtree(root, &mut |index, entry| {
+      released.push(index);
+      if let CursorEntry::Leaf(leaf) = entry {
+        for (key, i) in &leaf.keys {
+          released.push(*i);
+          keys.push(key.clone());
         }
-        Ok(())
       }
-      Err(err) => Err(err),
+    })?;
+    for key in &keys {
+      self.writer.log_change(name, key, None)?;
     }
+
+    self.writer.insert(HEADER_INDEX, header)?;
+    self
+      .key_delta
+      .fetch_sub(keys.len() as isize, Ordering::SeqCst);
+    for index in released {
+      self.writer.release(index);
+    }
+    Ok(true)
+  }
+
+  /// Writes the pages of every keyspace tree, the default keyspace first.
+  pub fn dump_tree(&self, w: &mut dyn Write) -> Result {
+    let _open = self.ensure_open()?;
+    let header: TreeHeader = self.writer.get(HEADER_INDEX)?;
+    let mut roots = vec![(DEFAULT_KEYSPACE.to_vec(), header.get_root())];
+    roots.extend(header.get_keyspaces());
+    for (name, root) in roots {
+      writeln!(w, "keyspace {:?}", String::from_utf8_lossy(&name)).map_err(Error::IO)?;
+      self.dump_at(root, 0, w)?;
+    }
+    Ok(())
+  }
+
+  /// Checks that the tree header is readable and returns
+  /// the indexes of the header and the roots.
+  pub fn verify_header(&self) -> Result<BTreeSet<usize>> {
+    let header: TreeHeader = self
+      .writer
+      .get(HEADER_INDEX)
+      .map_err(|err| corruption(HEADER_INDEX, err))?;
+    let mut referenced: BTreeSet<usize> = header.get_roots().into_iter().collect();
+    if !referenced.insert(HEADER_INDEX) {
+      return Err(corruption(HEADER_INDEX, "referenced as a root"));
+    }
+    Ok(referenced)
+  }
+
+  /// Checks the structure of every keyspace tree and returns
+  /// the indexes of the pages in use, including the header.
+  pub fn verify(&self) -> Result<BTreeSet<usize>> {
+    verify_pages(&self.writer)
+  }
+
+  /// Reverts the most recent write of this cursor.
+  /// Returns false if there is nothing to undo, and fails with
+  /// `Error::Invalid` unless the cursor was made with `with_undo`.
+  pub fn undo_last(&self) -> Result<bool> {
+    let _open = self.ensure_open()?;
+    if !self.undo {
+      return Err(Error::Invalid);
+    }
+
+    let log = match self.writes.l().pop() {
+      Some(log) => log,
+      None => return Ok(false),
+    };
+    self.forget_read(&log.keyspace, &log.key);
+    match log.previous {
+      Some(page) => self.put_in(&log.keyspace, log.key, page).map(|_| true),
+      None => self.delete_in(&log.keyspace, &log.key).map(|_| true),
+    }
+  }
+
+  /// Returns true if a key written by this cursor has a version
+  /// committed after the snapshot of this cursor.
+  pub fn would_conflict(&self) -> Result<bool> {
+    let _open = self.ensure_open()?;
+
+    let snapshot = self.writer.get_snapshot();
+    let mut latest = self.writer.snapshot();
+    latest.set_snapshot(usize::MAX);
+    let committed = self.writer.committed();
+    for log in self.writes.l().iter() {
+      // a key which existed before the write is looked up in the committed
+      // tree, so it is still found after this cursor deleted it.
+      let reader = match log.existed {
+        true => &committed,
+        false => &latest,
+      };
+      let index = match find_index(reader, &log.keyspace, &log.key) {
+        Ok(index) => index,
+        Err(Error::NotFound) => continue,
+        Err(err) => return Err(err),
+      };
+      let (version, _) = reader.get_with_version::<Page>(index)?;
+      if version.gt(&snapshot) {
+        return Ok(true);
+      }
+    }
+    Ok(false)
   }
 
   pub fn commit(&self) -> Result {
+    self.commit_by(|writer| writer.commit())
+  }
+
+  /// Commits without waiting for the wal fsync. The commit is visible
+  /// to other transactions once this returns, and the ack is called once
+  /// on a background thread when the commit is durable.
+  /// The ack is not called if this returns an error. Pages released by the
+  /// transaction go to the free list only once the commit is durable.
+  pub fn commit_with_ack<F>(&self, ack: F) -> Result
+  where
+    F: FnOnce(Result) + Send + 'static,
+  {
+    let freelist = self.freelist.clone();
+    let snapshots = self.snapshots.clone();
+    self.commit_by(|writer| {
+      let released = writer.take_released();
+      writer.commit_with_ack(Box::new(move |result| {
+        if result.is_ok() {
+          freelist.insert_many(&snapshots.release(released));
+        }
+        ack(result)
+      }))
+    })
+  }
+
+  fn commit_by<F>(&self, commit: F) -> Result
+  where
+    F: FnOnce(&CursorWriter) -> Result,
+  {
     let mut committed = self.committed.wl();
     if committed.eq(&true) {
-      return Err(Error::TransactionClosed);
+      return Err(self.closed_error());
     }
 
     logger::info(format!("cursor id {} commit start", self.writer.get_id()));
-    self.writer.commit()?;
+    commit(&self.writer)?;
+    let delta = self.key_delta.load(Ordering::SeqCst);
+    match delta.ge(&0) {
+      true => self
+        .key_count
+        .fetch_add(delta.unsigned_abs(), Ordering::SeqCst),
+      false => self
+        .key_count
+        .fetch_sub(delta.unsigned_abs(), Ordering::SeqCst),
+    };
+    let freed = self.snapshots.release(self.writer.take_released());
+    self.freelist.insert_many(&freed);
     *committed = true;
+    self.transactions.remove(self.writer.get_id());
     Ok(())
   }
 
+  /// Pages allocated by the transaction are returned to the free list
+  /// as soon as it is aborted.
   pub fn abort(&self) -> Result {
-    println!("abort not implemented");
-    Ok(())
+    let mut committed = self.committed.wl();
+    if committed.eq(&true) {
+      return Err(self.closed_error());
+    }
+
+    logger::info(format!("cursor id {} abort start", self.writer.get_id()));
+    *committed = true;
+    self.transactions.remove(self.writer.get_id());
+    self.writes.l().clear();
+    abort_writer(&self.writer, &self.freelist)
+  }
+
+  /// Holds the cursor open for the operation, so the transaction is not
+  /// aborted by the timeout sweeper until the returned guard is dropped.
+  /// Every operation starts here, so a read committed cursor
+  /// moves its snapshot to the latest commit here too.
+  fn ensure_open(&self) -> Result<RwLockReadGuard<'_, bool>> {
+    let committed = self.committed.rl();
+    if committed.eq(&true) {
+      return Err(self.closed_error());
+    }
+    if self.isolation.eq(&IsolationLevel::ReadCommitted) && self.writer.refresh_snapshot()
+    {
+      *self.read_cache.l() = Default::default();
+    }
+    Ok(committed)
+  }
+
+  fn closed_error(&self) -> Error {
+    match self.timed_out.load(Ordering::SeqCst) {
+      true => Error::TransactionTimedOut,
+      false => Error::TransactionClosed,
+    }
   }
 }
 impl Cursor {
-  fn get_index(&self, key: &Vec<u8>) -> Result<usize> {
+  /// Visits every node of the tree rooted at the index, parents first.
+  fn walk_subtree<F>(&self, index: usize, f: &mut F) -> Result
+  where
+    F: FnMut(usize, &CursorEntry),
+  {
+    let entry: CursorEntry = self.writer.get(index)?;
+    f(index, &entry);
+    if let CursorEntry::Internal(node) = entry {
+      for i in node.children {
+        self.walk_subtree(i, f)?;
+      }
+    }
+    Ok(())
+  }
+
+  fn dump_at(&self, index: usize, depth: usize, w: &mut dyn Write) -> Result {
+    let entry: CursorEntry = self.writer.get(index)?;
+    entry.dump(index, depth, w)?;
+    if let CursorEntry::Internal(node) = entry {
+      for i in node.children {
+        self.dump_at(i, depth.add(1), w)?;
+      }
+    }
+    Ok(())
+  }
+
+  pub(super) fn get_in<T>(&self, keyspace: &[u8], key: &Vec<u8>) -> Result<T>
+  where
+    T: Serializable,
+  {
+    let _open = self.ensure_open()?;
+
+    let i = self.get_index(keyspace, key)?;
+    self.writer.get(i)
+  }
+
+  pub(super) fn insert_in<T>(&self, keyspace: &[u8], key: Vec<u8>, value: T) -> Result
+  where
+    T: Serializable,
+  {
+    let _open = self.ensure_open()?;
+
+    self.forget_read(keyspace, &key);
+    let (existed, previous) =
+      conflict_on(self.put_in(keyspace, key.clone(), value), &key)?;
+    self.writes.l().push(WriteLog {
+      keyspace: keyspace.to_vec(),
+      key,
+      existed,
+      previous,
+    });
+    Ok(())
+  }
+
+  pub(super) fn remove_in(&self, keyspace: &[u8], key: &Vec<u8>) -> Result {
+    let _open = self.ensure_open()?;
+
+    self.forget_read(keyspace, key);
+    let previous = conflict_on(self.delete_in(keyspace, key), key)?;
+    self.writes.l().push(WriteLog {
+      keyspace: keyspace.to_vec(),
+      key: key.clone(),
+      existed: true,
+      previous,
+    });
+    Ok(())
+  }
+
+  /// Drops the cached value of the key, which only holds the default keyspace.
+  fn forget_read(&self, keyspace: &[u8], key: &Vec<u8>) {
+    if keyspace.eq(DEFAULT_KEYSPACE) {
+      self.read_cache.l().remove(key);
+    }
+  }
+
+  /// Writes the value and returns whether the key existed, with its
+  /// previous page if undo is enabled.
+  fn put_in<T>(
+    &self,
+    keyspace: &[u8],
+    key: Vec<u8>,
+    value: T,
+  ) -> Result<(bool, Option<Page>)>
+  where
+    T: Serializable,
+  {
+    let value = value.serialize()?;
+    match self.get_index(keyspace, &key) {
+      Ok(index) => {
+        self.check_holder(index, &key)?;
+        self.writer.log_change(keyspace, &key, Some(&value))?;
+        let previous = self.previous(index)?;
+        self.writer.insert(index, value)?;
+        Ok((true, previous))
+      }
+      Err(Error::NotFound) => {
+        self.writer.log_change(keyspace, &key, Some(&value))?;
+        let mut header: TreeHeader = self.writer.get(HEADER_INDEX)?;
+        // the header is written only when a root changes.
+        let mut rooted = false;
+        let root = match header.get_keyspace_root(keyspace) {
+          Some(root) => root,
+          None => {
+            let root = self.freelist.acquire();
+            self
+              .writer
+              .insert(root, CursorEntry::Leaf(LeafNode::empty()))?;
+            header.set_keyspace_root(keyspace, root);
+            rooted = true;
+            root
+          }
+        };
+        let cmp = header.get_comparator();
+        if let Ok((s, i)) = self.append_at(root, key, value, cmp)? {
+          let nri = self.freelist.acquire();
+          let new_root = CursorEntry::Internal(InternalNode {
+            keys: vec![s],
+            children: vec![root, i],
+          });
+          self.writer.insert(nri, new_root)?;
+
+          header.set_keyspace_root(keyspace, nri);
+          rooted = true;
+        }
+        if rooted {
+          self.writer.insert(HEADER_INDEX, header)?;
+        }
+        self.key_delta.fetch_add(1, Ordering::SeqCst);
+        Ok((false, None))
+      }
+      Err(err) => Err(err),
+    }
+  }
+
+  /// Removes the key and returns the removed page if undo is enabled.
+  fn delete_in(&self, keyspace: &[u8], key: &Vec<u8>) -> Result<Option<Page>> {
     let header: TreeHeader = self.writer.get(HEADER_INDEX)?;
-    let mut index = header.get_root();
+    let cmp = header.get_comparator();
+    let mut index = header.get_keyspace_root(keyspace).ok_or(Error::NotFound)?;
     loop {
-      let entry: CursorEntry = self.writer.get(index)?;
-      match entry.find_or_next(key) {
-        Ok(i) => return Ok(i),
-        Err(n) => match n {
-          Some(i) => index = i,
-          None => return Err(Error::NotFound),
-        },
+      match self.writer.get(index)? {
+        CursorEntry::Internal(node) => index = node.next(key, cmp),
+        CursorEntry::Leaf(mut node) => {
+          let released = match node.remove(key, cmp) {
+            Some(i) => i,
+            None => return Err(Error::NotFound),
+          };
+          self.check_holder(released, key)?;
+          let previous = self.previous(released)?;
+          self.writer.log_change(keyspace, key, None)?;
+          self.writer.insert(index, node)?;
+          self.key_delta.fetch_sub(1, Ordering::SeqCst);
+          self.writer.release(released);
+          return Ok(previous);
+        }
       }
     }
   }
 
+  fn previous(&self, index: usize) -> Result<Option<Page>> {
+    match self.undo {
+      true => self.writer.get(index).map(Some),
+      false => Ok(None),
+    }
+  }
+
+  pub(super) fn scan_in<T>(
+    &self,
+    keyspace: &[u8],
+    start: &Vec<u8>,
+    end: &[u8],
+  ) -> Result<ScanIter<'_, T>>
+  where
+    T: Serializable,
+  {
+    let _open = self.ensure_open()?;
+
+    ScanIter::seek(&self.writer, keyspace, start, end)
+  }
+
+  /// Points the leaf back at the leaf inserted before it by a split,
+  /// so the chain stays walkable in both directions.
+  fn relink_prev(&self, index: usize, prev: usize) -> Result {
+    match self.writer.get(index)? {
+      CursorEntry::Leaf(mut leaf) => {
+        leaf.prev = Some(prev);
+        self.writer.insert(index, leaf)
+      }
+      CursorEntry::Internal(_) => Err(Error::Invalid),
+    }
+  }
+
+  /// Each key has its own value page, so a value page written by another
+  /// pending transaction is a write intent on the key.
+  fn check_holder(&self, index: usize, key: &[u8]) -> Result {
+    match self.writer.get_holder(index)? {
+      Some(holder) => Err(Error::WriteConflict {
+        key: key.to_vec(),
+        holder,
+      }),
+      None => Ok(()),
+    }
+  }
+
+  fn get_index(&self, keyspace: &[u8], key: &Vec<u8>) -> Result<usize> {
+    find_index(&self.writer, keyspace, key)
+  }
+
   fn append_at<T>(
     &self,
     current: usize,
     key: Vec<u8>,
     value: T,
+    cmp: KeyComparator,
   ) -> Result<core::result::Result<(Vec<u8>, usize), Option<Vec<u8>>>>
   where
     T: Serializable,
@@ -136,10 +1281,10 @@ impl Cursor {
     let entry: CursorEntry = self.writer.get(current)?;
     match entry {
       CursorEntry::Internal(mut node) => {
-        let i = node.next(&key);
-        match self.append_at(i, key, value)? {
+        let i = node.next(&key, cmp);
+        match self.append_at(i, key, value, cmp)? {
           Ok((s, ni)) => {
-            node.add(s, ni);
+            node.add(s, ni, cmp);
             if node.len().le(&MAX_NODE_LEN) {
               self.writer.insert(current, node)?;
               return Ok(Err(None));
@@ -151,38 +1296,122 @@ impl Cursor {
             self.writer.insert(current, node)?;
             Ok(Ok((s, new_i)))
           }
-          Err(oi) => {
-            if let Some(s) = oi {
-              node.keys.insert(i - 1, s);
-              self.writer.insert(current, node)?;
-            }
-            Ok(Err(None))
-          }
+          // a new first key of the child is still above the key it was
+          // routed by, so the node is unchanged.
+          Err(_) => Ok(Err(None)),
         }
       }
       CursorEntry::Leaf(mut node) => {
-        if let Some(i) = node.find(&key) {
+        if let Some(i) = node.find(&key, cmp) {
           self.writer.insert(node.keys[i].1, value)?;
           return Ok(Err(None));
         };
 
         let pi = self.freelist.acquire();
         self.writer.insert(pi, value)?;
-        let lk = node.add(key, pi);
+        let lk = node.add(key, pi, cmp);
         if node.len().le(&MAX_NODE_LEN) {
           self.writer.insert(current, node)?;
           return Ok(Err(lk));
         }
 
         let ni = self.freelist.acquire();
+        let next = node.next;
         let (n, s) = node.split(current, ni);
         self.writer.insert(ni, n)?;
         self.writer.insert(current, node)?;
+        if let Some(next) = next {
+          self.relink_prev(next, ni)?;
+        }
         Ok(Ok((s, ni)))
       }
     }
   }
 }
+pub(super) fn find_index(
+  writer: &CursorWriter,
+  keyspace: &[u8],
+  key: &Vec<u8>,
+) -> Result<usize> {
+  let header: TreeHeader = writer.get(HEADER_INDEX)?;
+  let cmp = header.get_comparator();
+  let mut index = header.get_keyspace_root(keyspace).ok_or(Error::NotFound)?;
+  loop {
+    let entry: CursorEntry = writer.get(index)?;
+    match entry.find_or_next(key, cmp) {
+      Ok(i) => return Ok(i),
+      Err(n) => match n {
+        Some(i) => index = i,
+        None => return Err(Error::NotFound),
+      },
+    }
+  }
+}
+
+/// Descends to the leaf which contains the key and returns it
+/// with the smallest separator greater than the key, if any.
+fn find_leaf(
+  writer: &CursorWriter,
+  keyspace: &[u8],
+  key: &Vec<u8>,
+) -> Result<(usize, LeafNode, Option<Vec<u8>>)> {
+  let header: TreeHeader = writer.get(HEADER_INDEX)?;
+  let cmp = header.get_comparator();
+  let mut index = header.get_keyspace_root(keyspace).ok_or(Error::NotFound)?;
+  let mut upper = None;
+  loop {
+    match writer.get(index)? {
+      CursorEntry::Internal(node) => {
+        if let Some(s) = node.keys.iter().find(|s| cmp.lt(key, s)) {
+          upper = Some(s.clone());
+        }
+        index = node.next(key, cmp);
+      }
+      CursorEntry::Leaf(node) => return Ok((index, node, upper)),
+    }
+  }
+}
+
+fn ensure_ascending(
+  previous: Option<&Vec<u8>>,
+  key: &Vec<u8>,
+  cmp: KeyComparator,
+) -> Result {
+  match previous {
+    Some(p) if cmp.ge(p, key) => Err(Error::NotSorted),
+    _ => Ok(()),
+  }
+}
+
+fn read_page<T>(mut iter: ScanIter<'_, T>, limit: usize) -> Result<ScanPage<T>>
+where
+  T: Serializable,
+{
+  let mut items = vec![];
+  while items.len().lt(&limit) {
+    match iter.try_next()? {
+      Some(item) => items.push(item),
+      None => return Ok((items, None)),
+    }
+  }
+  Ok((items, Some(iter.resume_token())))
+}
+
+/// Fills in the key of a conflict on a tree page,
+/// since the writer of the page does not know the key.
+fn conflict_on<T>(result: Result<T>, key: &[u8]) -> Result<T> {
+  result.map_err(|err| match err {
+    Error::WriteConflict {
+      key: conflicted,
+      holder,
+    } if conflicted.is_empty() => Error::WriteConflict {
+      key: key.to_vec(),
+      holder,
+    },
+    err => err,
+  })
+}
+
 impl Drop for Cursor {
   fn drop(&mut self) {
     if self.committed.rl().eq(&true) {
@@ -197,6 +1426,70 @@ impl Drop for Cursor {
   }
 }
 
+#[cfg(test)]
+mod tests {
+  use crate::{cursor::KeyComparator, engine::tests::config, Engine, Error, Page};
+
+  use super::{ensure_ascending, CursorEntry, TreeHeader, HEADER_INDEX};
+
+  const BYTES: KeyComparator = KeyComparator::Lexicographic;
+
+  #[test]
+  fn _1() {
+    let keys = (0..10000u32)
+      .map(|i| i.to_be_bytes().to_vec())
+      .collect::<Vec<_>>();
+    let mut previous = None;
+    for key in &keys {
+      ensure_ascending(previous, key, BYTES).unwrap();
+      previous = Some(key);
+    }
+
+    assert!(matches!(
+      ensure_ascending(Some(&keys[5]), &keys[5], BYTES),
+      Err(Error::NotSorted)
+    ));
+    assert!(matches!(
+      ensure_ascending(Some(&keys[5]), &keys[4], BYTES),
+      Err(Error::NotSorted)
+    ));
+    ensure_ascending(
+      Some(&keys[5]),
+      &keys[4],
+      KeyComparator::ReverseLexicographic,
+    )
+    .unwrap();
+  }
+
+  #[test]
+  #[cfg(debug_assertions)]
+  #[should_panic(expected = "scan out of order")]
+  fn _2() {
+    let mut config = config("lfkv-cursor-scan-order");
+    config.in_memory = true;
+    let engine = Engine::bootstrap(config).unwrap();
+    let cursor = engine.new_transaction().unwrap();
+    for key in [b"a", b"b", b"c", b"d"] {
+      cursor.insert(key.to_vec(), Page::new()).unwrap();
+    }
+
+    // the root leaf with two keys swapped, as a corrupted split could leave it.
+    let header: TreeHeader = cursor.writer.get(HEADER_INDEX).unwrap();
+    let mut node = match cursor.writer.get(header.get_root()).unwrap() {
+      CursorEntry::Leaf(node) => node,
+      CursorEntry::Internal(_) => panic!("root is not a leaf"),
+    };
+    node.keys.swap(1, 2);
+    cursor
+      .writer
+      .insert(header.get_root(), CursorEntry::Leaf(node))
+      .unwrap();
+
+    let mut iter = cursor.scan::<Page>(&vec![], &[0xff]).unwrap();
+    while iter.try_next().unwrap().is_some() {}
+  }
+}
+
 // pub struct Cursor {
 //   writer: CursorWriter,
 //   locks: CursorLocks,
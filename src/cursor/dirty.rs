@@ -0,0 +1,25 @@
+use crate::{buffer::BufferPool, Error, Result, Serializable};
+
+use super::{CursorEntry, TreeHeader, HEADER_INDEX};
+
+/// Reads the newest value of the key including uncommitted writes.
+/// This violates transaction isolation and is only for diagnostics.
+pub fn dirty_read<T>(buffer: &BufferPool, key: &Vec<u8>) -> Result<Option<T>>
+where
+  T: Serializable,
+{
+  let header: TreeHeader = match buffer.get_latest(HEADER_INDEX) {
+    Ok(page) => page.deserialize()?,
+    Err(Error::NotFound) => return Ok(None),
+    Err(err) => return Err(err),
+  };
+  let mut index = header.get_root();
+  loop {
+    let entry: CursorEntry = buffer.get_latest(index)?.deserialize()?;
+    match entry.find_or_next(key, header.get_comparator()) {
+      Ok(i) => return buffer.get_latest(i)?.deserialize().map(Some),
+      Err(Some(i)) => index = i,
+      Err(None) => return Ok(None),
+    }
+  }
+}
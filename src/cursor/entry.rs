@@ -1,13 +1,15 @@
-use std::ops::Add;
+use std::{io::Write, ops::Add};
 
 use crate::{
   disk::{Page, Serializable},
   error::Error,
 };
 
+use super::{gallop_by, KeyComparator};
+
 pub static MAX_NODE_LEN: usize = 12;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum CursorEntry {
   Internal(InternalNode),
   Leaf(LeafNode),
@@ -19,6 +21,12 @@ impl Serializable for CursorEntry {
       Self::Internal(node) => node.serialize(),
     }
   }
+  fn serialize_into(&self, page: &mut Page) -> Result<(), Error> {
+    match self {
+      Self::Leaf(node) => node.serialize_into(page),
+      Self::Internal(node) => node.serialize_into(page),
+    }
+  }
   fn deserialize(value: &Page) -> Result<Self, Error> {
     let mut sc = value.scanner();
     match sc.read()? {
@@ -29,18 +37,81 @@ impl Serializable for CursorEntry {
   }
 }
 impl CursorEntry {
-  pub fn find_or_next(&self, key: &Vec<u8>) -> Result<usize, Option<usize>> {
+  pub fn dump(&self, index: usize, depth: usize, w: &mut dyn Write) -> Result<(), Error> {
+    let indent = "  ".repeat(depth);
+    match self {
+      Self::Internal(node) => writeln!(
+        w,
+        "{indent}[{index}] internal keys={:?} children={:?}",
+        node.keys.iter().map(|k| display_key(k)).collect::<Vec<_>>(),
+        node.children,
+      ),
+      Self::Leaf(node) => writeln!(
+        w,
+        "{indent}[{index}] leaf keys={:?} prev={:?} next={:?}",
+        node
+          .keys
+          .iter()
+          .map(|(k, i)| format!("{}->{}", display_key(k), i))
+          .collect::<Vec<_>>(),
+        node.prev,
+        node.next,
+      ),
+    }
+    .map_err(Error::IO)
+  }
+
+  pub fn find_or_next(
+    &self,
+    key: &Vec<u8>,
+    cmp: KeyComparator,
+  ) -> Result<usize, Option<usize>> {
     match self {
-      Self::Internal(node) => Err(Some(node.next(key))),
-      Self::Leaf(node) => match node.find(key) {
+      Self::Internal(node) => Err(Some(node.next(key, cmp))),
+      Self::Leaf(node) => match node.find(key, cmp) {
         Some(i) => Ok(i),
         None => Err(None),
       },
     }
   }
+
+  /// Same as `find_or_next`, searching from the slot of the previous probe
+  /// if any. Returns the slot found with the result, as the next hint.
+  pub fn find_or_next_from(
+    &self,
+    key: &Vec<u8>,
+    cmp: KeyComparator,
+    hint: Option<usize>,
+  ) -> (usize, Result<usize, Option<usize>>) {
+    match self {
+      Self::Internal(node) => {
+        let found = search(&node.keys, hint, |k| cmp.compare(k, key));
+        let i = found.map(|i| i.add(1)).unwrap_or_else(|i| i);
+        (i, Err(Some(node.children[i])))
+      }
+      Self::Leaf(node) => match search(&node.keys, hint, |(k, _)| cmp.compare(k, key)) {
+        Ok(i) => (i, Ok(node.keys[i].1)),
+        Err(i) => (i, Err(None)),
+      },
+    }
+  }
+}
+
+fn search<T, F>(items: &[T], hint: Option<usize>, f: F) -> Result<usize, usize>
+where
+  F: FnMut(&T) -> std::cmp::Ordering,
+{
+  match hint {
+    Some(hint) => gallop_by(items, hint, f),
+    None => items.binary_search_by(f),
+  }
+}
+
+fn display_key(key: &[u8]) -> String {
+  String::from_utf8_lossy(key).to_string()
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct InternalNode {
   pub keys: Vec<Vec<u8>>,
   pub children: Vec<usize>,
@@ -54,8 +125,8 @@ impl InternalNode {
     (CursorEntry::Internal(InternalNode { keys, children }), m)
   }
 
-  pub fn add(&mut self, key: Vec<u8>, index: usize) {
-    if let Err(i) = self.keys.binary_search_by(|k| k.cmp(&key)) {
+  pub fn add(&mut self, key: Vec<u8>, index: usize, cmp: KeyComparator) {
+    if let Err(i) = self.keys.binary_search_by(|k| cmp.compare(k, &key)) {
       let mut keys = self.keys.split_off(i);
       self.keys.push(key);
       self.keys.append(keys.as_mut());
@@ -69,10 +140,10 @@ impl InternalNode {
     self.keys.len()
   }
 
-  pub fn next(&self, key: &Vec<u8>) -> usize {
+  pub fn next(&self, key: &Vec<u8>, cmp: KeyComparator) -> usize {
     let i = self
       .keys
-      .binary_search_by(|k| k.cmp(key))
+      .binary_search_by(|k| cmp.compare(k, key))
       .map(|i| i.add(1))
       .unwrap_or_else(|i| i);
     self.children[i]
@@ -82,7 +153,13 @@ impl InternalNode {
 impl Serializable for InternalNode {
   fn serialize(&self) -> Result<Page, Error> {
     let mut p = Page::new();
-    let mut wt = p.writer();
+    self.serialize_into(&mut p)?;
+    Ok(p)
+  }
+
+  fn serialize_into(&self, page: &mut Page) -> Result<(), Error> {
+    page.reset();
+    let mut wt = page.writer();
     wt.write(&[2])?;
     wt.write(&[self.keys.len() as u8])?;
     for k in &self.keys {
@@ -92,7 +169,7 @@ impl Serializable for InternalNode {
     for &i in &self.children {
       wt.write(&i.to_be_bytes())?;
     }
-    Ok(p)
+    Ok(())
   }
 
   fn deserialize(value: &Page) -> Result<Self, Error> {
@@ -113,7 +190,7 @@ impl Serializable for InternalNode {
   }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct LeafNode {
   pub keys: Vec<(Vec<u8>, usize)>,
   pub next: Option<usize>,
@@ -144,8 +221,13 @@ impl LeafNode {
     )
   }
 
-  pub fn add(&mut self, key: Vec<u8>, index: usize) -> Option<Vec<u8>> {
-    if let Err(i) = self.keys.binary_search_by(|(k, _)| k.cmp(&key)) {
+  pub fn add(
+    &mut self,
+    key: Vec<u8>,
+    index: usize,
+    cmp: KeyComparator,
+  ) -> Option<Vec<u8>> {
+    if let Err(i) = self.keys.binary_search_by(|(k, _)| cmp.compare(k, &key)) {
       let mut keys = self.keys.split_off(i);
       self.keys.push((key.to_owned(), index));
       self.keys.append(keys.as_mut());
@@ -154,14 +236,22 @@ impl LeafNode {
     None
   }
 
+  pub fn remove(&mut self, key: &Vec<u8>, cmp: KeyComparator) -> Option<usize> {
+    self
+      .keys
+      .binary_search_by(|(k, _)| cmp.compare(k, key))
+      .ok()
+      .map(|i| self.keys.remove(i).1)
+  }
+
   pub fn len(&self) -> usize {
     self.keys.len()
   }
 
-  pub fn find(&self, key: &Vec<u8>) -> Option<usize> {
+  pub fn find(&self, key: &Vec<u8>, cmp: KeyComparator) -> Option<usize> {
     self
       .keys
-      .binary_search_by(|(k, _)| k.cmp(key))
+      .binary_search_by(|(k, _)| cmp.compare(k, key))
       .ok()
       .map(|i| self.keys[i].1)
   }
@@ -169,7 +259,13 @@ impl LeafNode {
 impl Serializable for LeafNode {
   fn serialize(&self) -> Result<Page, Error> {
     let mut p = Page::new();
-    let mut wt = p.writer();
+    self.serialize_into(&mut p)?;
+    Ok(p)
+  }
+
+  fn serialize_into(&self, page: &mut Page) -> Result<(), Error> {
+    page.reset();
+    let mut wt = page.writer();
     wt.write(&[1])?;
     wt.write(&[self.keys.len() as u8])?;
     for (k, i) in &self.keys {
@@ -181,7 +277,7 @@ impl Serializable for LeafNode {
     wt.write(&prev.to_be_bytes())?;
     let next = self.next.unwrap_or(0);
     wt.write(&next.to_be_bytes())?;
-    Ok(p)
+    Ok(())
   }
 
   fn deserialize(value: &Page) -> Result<Self, Error> {
@@ -202,3 +298,70 @@ impl Serializable for LeafNode {
     Ok(Self { keys, prev, next })
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use crate::{Page, Serializable};
+
+  use super::{CursorEntry, InternalNode, LeafNode};
+
+  #[test]
+  fn _1() {
+    let mut out = vec![];
+    CursorEntry::Internal(InternalNode {
+      keys: vec![b"m".to_vec()],
+      children: vec![2, 3],
+    })
+    .dump(1, 0, &mut out)
+    .unwrap();
+    CursorEntry::Leaf(LeafNode {
+      keys: vec![(b"a".to_vec(), 4), (b"b".to_vec(), 5)],
+      prev: None,
+      next: Some(3),
+    })
+    .dump(2, 1, &mut out)
+    .unwrap();
+
+    let out = String::from_utf8(out).unwrap();
+    assert_eq!(
+      out,
+      "[1] internal keys=[\"m\"] children=[2, 3]\n  [2] leaf keys=[\"a->4\", \"b->5\"] prev=None next=Some(3)\n"
+    );
+  }
+
+  #[test]
+  fn _2() {
+    let leaf = LeafNode {
+      keys: (0..10u8).map(|i| (vec![i; 40], i as usize)).collect(),
+      prev: Some(7),
+      next: None,
+    };
+    let small = LeafNode {
+      keys: vec![(b"a".to_vec(), 4)],
+      prev: None,
+      next: Some(3),
+    };
+
+    let mut page = Page::new();
+    leaf.serialize_into(&mut page).unwrap();
+    let decoded: LeafNode = page.deserialize().unwrap();
+    assert_eq!(decoded.keys, leaf.keys);
+    assert_eq!(decoded.prev, Some(7));
+
+    small.serialize_into(&mut page).unwrap();
+    assert_eq!(page, small.serialize().unwrap());
+    let decoded: LeafNode = page.deserialize().unwrap();
+    assert_eq!(decoded.keys, small.keys);
+    assert_eq!((decoded.prev, decoded.next), (None, Some(3)));
+
+    let internal = CursorEntry::Internal(InternalNode {
+      keys: vec![b"m".to_vec()],
+      children: vec![2, 3],
+    });
+    internal.serialize_into(&mut page).unwrap();
+    match page.deserialize().unwrap() {
+      CursorEntry::Internal(node) => assert_eq!(node.children, vec![2, 3]),
+      CursorEntry::Leaf(_) => panic!("expected an internal node"),
+    }
+  }
+}
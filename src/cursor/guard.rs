@@ -0,0 +1,20 @@
+use std::{ops::Deref, sync::Arc};
+
+use crate::Page;
+
+/// Value borrowed from the buffer pool without copying the page.
+/// The page stays pinned in memory until the guard is dropped,
+/// so holding many guards pins as many pages.
+pub struct ValueGuard(Arc<Page>);
+impl ValueGuard {
+  pub fn new(page: Arc<Page>) -> Self {
+    Self(page)
+  }
+}
+impl Deref for ValueGuard {
+  type Target = [u8];
+
+  fn deref(&self) -> &Self::Target {
+    self.0.as_ref().as_ref()
+  }
+}
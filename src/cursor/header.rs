@@ -1,24 +1,44 @@
-use std::ops::Add;
+use std::{collections::BTreeMap, ops::Add};
 
 use crate::{
   disk::{Page, Serializable},
   error::Error,
 };
 
+use super::KeyComparator;
+
 pub static HEADER_INDEX: usize = 0;
 
-#[derive(Debug)]
+/// Name of the keyspace which is rooted at the header root.
+pub const DEFAULT_KEYSPACE: &[u8] = b"";
+
+const FORMAT_MAGIC: &[u8] = b"lfkv";
+/// Version of the on-disk format, raised whenever the layout of pages,
+/// blocks or undo records changes. Headers without the magic are read as 0.
+pub const FORMAT_VERSION: u8 = 1;
+
+#[derive(Debug, PartialEq, Eq)]
 pub struct TreeHeader {
   root: usize,
+  keyspaces: BTreeMap<Vec<u8>, usize>,
+  key_count: usize,
+  comparator: KeyComparator,
 }
 
 impl TreeHeader {
-  pub fn initial_state() -> Self {
+  pub fn initial_state(comparator: KeyComparator) -> Self {
     Self {
       root: HEADER_INDEX.add(1),
+      keyspaces: Default::default(),
+      key_count: 0,
+      comparator,
     }
   }
 
+  pub fn get_comparator(&self) -> KeyComparator {
+    self.comparator
+  }
+
   pub fn get_root(&self) -> usize {
     self.root
   }
@@ -26,20 +46,149 @@ impl TreeHeader {
   pub fn set_root(&mut self, index: usize) {
     self.root = index
   }
+
+  pub fn get_key_count(&self) -> usize {
+    self.key_count
+  }
+
+  pub fn set_key_count(&mut self, count: usize) {
+    self.key_count = count
+  }
+
+  pub fn get_keyspace_root(&self, name: &[u8]) -> Option<usize> {
+    if name.eq(DEFAULT_KEYSPACE) {
+      return Some(self.root);
+    }
+    self.keyspaces.get(name).copied()
+  }
+
+  /// Returns the roots of the default keyspace and every named keyspace.
+  pub fn get_roots(&self) -> Vec<usize> {
+    let mut roots = vec![self.root];
+    roots.extend(self.keyspaces.values());
+    roots
+  }
+
+  pub fn set_keyspace_root(&mut self, name: &[u8], index: usize) {
+    if name.eq(DEFAULT_KEYSPACE) {
+      return self.set_root(index);
+    }
+    self.keyspaces.insert(name.to_vec(), index);
+  }
+
+  /// Returns the names and roots of the named keyspaces.
+  pub fn get_keyspaces(&self) -> Vec<(Vec<u8>, usize)> {
+    self
+      .keyspaces
+      .iter()
+      .map(|(name, root)| (name.clone(), *root))
+      .collect()
+  }
+
+  /// Forgets the named keyspace and returns its root.
+  /// The default keyspace can not be removed.
+  pub fn remove_keyspace(&mut self, name: &[u8]) -> Option<usize> {
+    self.keyspaces.remove(name)
+  }
 }
 
 impl Serializable for TreeHeader {
   fn serialize(&self) -> Result<Page, Error> {
     let mut p = Page::new();
     let mut wt = p.writer();
+    wt.write(FORMAT_MAGIC)?;
+    wt.write(&[FORMAT_VERSION])?;
     wt.write(&self.root.to_be_bytes())?;
+    wt.write(&self.keyspaces.len().to_be_bytes())?;
+    for (name, root) in &self.keyspaces {
+      wt.write(&name.len().to_be_bytes())?;
+      wt.write(name)?;
+      wt.write(&root.to_be_bytes())?;
+    }
+    wt.write(&self.key_count.to_be_bytes())?;
+    wt.write(&[self.comparator.to_byte()])?;
     Ok(p)
   }
 
   fn deserialize(value: &Page) -> Result<Self, Error> {
     let mut s = value.scanner();
+    if s.read_n(FORMAT_MAGIC.len())?.ne(FORMAT_MAGIC) {
+      return Err(Error::UnsupportedFormat(0));
+    }
+    let version = s.read()?;
+    if version.ne(&FORMAT_VERSION) {
+      return Err(Error::UnsupportedFormat(version));
+    }
     let root = s.read_usize()?;
+    let mut keyspaces = BTreeMap::new();
+    for _ in 0..s.read_usize()? {
+      let len = s.read_usize()?;
+      let name = s.read_n(len)?.to_vec();
+      keyspaces.insert(name, s.read_usize()?);
+    }
+    let key_count = s.read_usize()?;
+    let comparator = KeyComparator::from_byte(s.read()?)?;
+
+    Ok(TreeHeader {
+      root,
+      keyspaces,
+      key_count,
+      comparator,
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::{cursor::KeyComparator, Error, Page, Serializable};
+
+  use super::{TreeHeader, DEFAULT_KEYSPACE, FORMAT_VERSION};
+
+  #[test]
+  fn _1() {
+    let mut header = TreeHeader::initial_state(KeyComparator::SignedBigEndianI64);
+    header.set_keyspace_root(b"users", 10);
+    header.set_keyspace_root(b"orders", 20);
+    header.set_keyspace_root(DEFAULT_KEYSPACE, 30);
+    header.set_key_count(42);
+
+    let decoded = TreeHeader::deserialize(&header.serialize().unwrap()).unwrap();
+    assert_eq!(decoded, header);
+    assert_eq!(decoded.get_root(), 30);
+    assert_eq!(decoded.get_keyspace_root(b"users"), Some(10));
+    assert_eq!(decoded.get_keyspace_root(b"orders"), Some(20));
+    assert_eq!(decoded.get_keyspace_root(b"unknown"), None);
+    assert_eq!(decoded.get_key_count(), 42);
+    assert_eq!(decoded.get_comparator(), KeyComparator::SignedBigEndianI64);
+    assert_eq!(decoded.get_roots(), vec![30, 20, 10]);
+
+    let mut header = decoded;
+    assert_eq!(header.remove_keyspace(b"orders"), Some(20));
+    assert_eq!(header.remove_keyspace(b"orders"), None);
+    assert_eq!(header.remove_keyspace(DEFAULT_KEYSPACE), None);
+    assert_eq!(header.get_keyspaces(), vec![(b"users".to_vec(), 10)]);
+  }
+
+  #[test]
+  fn _2() {
+    let header = TreeHeader::initial_state(KeyComparator::Lexicographic);
+    let page = header.serialize().unwrap();
+    assert_eq!(TreeHeader::deserialize(&page).unwrap(), header);
+
+    let mut newer = page.copy();
+    // the marker byte and the magic come first.
+    newer.as_mut()[5] = FORMAT_VERSION + 1;
+    assert!(matches!(
+      TreeHeader::deserialize(&newer),
+      Err(Error::UnsupportedFormat(v)) if v.eq(&(FORMAT_VERSION + 1))
+    ));
 
-    Ok(TreeHeader { root })
+    // a header written before the format was versioned starts with the root.
+    let mut older = Page::new();
+    older.writer().write(&1usize.to_be_bytes()).unwrap();
+    assert!(matches!(
+      TreeHeader::deserialize(&older),
+      Err(Error::UnsupportedFormat(0))
+    ));
   }
 }
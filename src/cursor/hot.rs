@@ -0,0 +1,156 @@
+use std::{
+  collections::{hash_map::RandomState, BTreeMap},
+  hash::{BuildHasher, Hash},
+  ops::AddAssign,
+  sync::Mutex,
+};
+
+use crate::ShortenedMutex;
+
+struct CountMinSketch {
+  table: Vec<Vec<usize>>,
+  hasher: Vec<RandomState>,
+}
+impl CountMinSketch {
+  fn new(width: usize, depth: usize) -> Self {
+    Self {
+      table: (0..depth).map(|_| vec![0; width]).collect(),
+      hasher: (0..depth).map(|_| RandomState::new()).collect(),
+    }
+  }
+
+  fn increment<K: Hash>(&mut self, k: K) -> usize {
+    let mut estimate = usize::MAX;
+    for (row, builder) in self.table.iter_mut().zip(self.hasher.iter()) {
+      let h = (builder.hash_one(&k) as usize).rem_euclid(row.len());
+      row[h].add_assign(1);
+      estimate = estimate.min(row[h]);
+    }
+    estimate
+  }
+}
+
+/// Keys are spread over the shards by hash, so writers of different keys
+/// rarely wait on the same lock. A key always lands in the same shard,
+/// which keeps its estimate as accurate as with a single sketch.
+const SHARD_COUNT: usize = 16;
+const SHARD_WIDTH: usize = 1 << 6;
+
+struct HotKeysShard {
+  sketch: CountMinSketch,
+  top: BTreeMap<Vec<u8>, usize>,
+  capacity: usize,
+}
+impl HotKeysShard {
+  fn record(&mut self, key: &Vec<u8>) {
+    let estimate = self.sketch.increment(key);
+    if self.top.contains_key(key) || self.top.len().lt(&self.capacity) {
+      self.top.insert(key.clone(), estimate);
+      return;
+    }
+
+    let coldest = self
+      .top
+      .iter()
+      .min_by_key(|(_, &count)| count)
+      .map(|(k, &count)| (k.clone(), count));
+    if let Some((k, count)) = coldest {
+      if count.lt(&estimate) {
+        self.top.remove(&k);
+        self.top.insert(key.clone(), estimate);
+      }
+    }
+  }
+}
+
+pub struct HotKeys {
+  shards: Vec<Mutex<HotKeysShard>>,
+  hasher: RandomState,
+  capacity: usize,
+}
+impl HotKeys {
+  pub fn new(capacity: usize) -> Self {
+    let shards = (0..SHARD_COUNT)
+      .map(|_| {
+        Mutex::new(HotKeysShard {
+          sketch: CountMinSketch::new(SHARD_WIDTH, 4),
+          top: Default::default(),
+          capacity,
+        })
+      })
+      .collect();
+    Self {
+      shards,
+      hasher: RandomState::new(),
+      capacity,
+    }
+  }
+
+  pub fn record(&self, key: &Vec<u8>) {
+    let shard = (self.hasher.hash_one(key) as usize).rem_euclid(SHARD_COUNT);
+    self.shards[shard].l().record(key);
+  }
+
+  pub fn report(&self) -> Vec<(Vec<u8>, usize)> {
+    let mut report: Vec<_> = self
+      .shards
+      .iter()
+      .flat_map(|shard| {
+        shard
+          .l()
+          .top
+          .iter()
+          .map(|(k, &count)| (k.clone(), count))
+          .collect::<Vec<_>>()
+      })
+      .collect();
+    report.sort_by(|(_, a), (_, b)| b.cmp(a));
+    report.truncate(self.capacity);
+    report
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{sync::Arc, thread};
+
+  use super::HotKeys;
+
+  #[test]
+  fn _1() {
+    let hot = HotKeys::new(4);
+    for i in 0..100usize {
+      hot.record(&i.to_be_bytes().to_vec());
+      hot.record(&b"hot".to_vec());
+    }
+
+    let report = hot.report();
+    assert_eq!(report.len(), 4);
+    assert_eq!(report[0].0, b"hot".to_vec());
+    assert!(report[0].1.ge(&100));
+  }
+
+  #[test]
+  fn _2() {
+    let hot = Arc::new(HotKeys::new(4));
+    let handles = (0..4usize)
+      .map(|t| {
+        let hot = hot.clone();
+        thread::spawn(move || {
+          for i in 0..200usize {
+            hot.record(&(t * 1000 + i).to_be_bytes().to_vec());
+            hot.record(&b"hot".to_vec());
+          }
+        })
+      })
+      .collect::<Vec<_>>();
+    for handle in handles {
+      handle.join().unwrap();
+    }
+
+    let report = hot.report();
+    assert_eq!(report.len(), 4);
+    assert_eq!(report[0].0, b"hot".to_vec());
+    assert!(report[0].1.ge(&800));
+  }
+}
@@ -0,0 +1,52 @@
+use crate::{Result, Serializable};
+
+use super::{Cursor, ScanIter};
+
+/// Handle to a named keyspace with its own tree root.
+/// Writes through different keyspaces of a cursor commit together.
+pub struct Keyspace<'a> {
+  cursor: &'a Cursor,
+  name: Vec<u8>,
+}
+impl<'a> Keyspace<'a> {
+  pub fn new(cursor: &'a Cursor, name: &[u8]) -> Self {
+    Self {
+      cursor,
+      name: name.to_vec(),
+    }
+  }
+
+  pub fn get<T>(&self, key: &Vec<u8>) -> Result<T>
+  where
+    T: Serializable,
+  {
+    self.cursor.get_in(&self.name, key)
+  }
+
+  pub fn insert<T>(&self, key: Vec<u8>, value: T) -> Result
+  where
+    T: Serializable,
+  {
+    self.cursor.insert_in(&self.name, key, value)
+  }
+
+  pub fn remove(&self, key: &Vec<u8>) -> Result {
+    self.cursor.remove_in(&self.name, key)
+  }
+
+  pub fn scan<T>(&self, start: &Vec<u8>, end: &[u8]) -> Result<ScanIter<'a, T>>
+  where
+    T: Serializable,
+  {
+    self.cursor.scan_in(&self.name, start, end)
+  }
+}
+
+/// Summary of a keyspace reported by `Cursor::keyspaces`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyspaceInfo {
+  /// Empty for the default keyspace.
+  pub name: Vec<u8>,
+  pub root: usize,
+  pub key_count: usize,
+}
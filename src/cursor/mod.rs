@@ -4,8 +4,50 @@ use header::*;
 mod entry;
 use entry::*;
 
+mod probe;
+use probe::*;
+
+mod order;
+pub use order::*;
+
 mod writer;
 use writer::*;
 
 mod cursor;
 pub use cursor::*;
+
+mod ttl;
+pub use ttl::*;
+
+mod batch;
+pub use batch::*;
+
+mod scan;
+pub use scan::*;
+
+mod prefetch;
+use prefetch::*;
+
+mod hot;
+pub use hot::*;
+
+mod dirty;
+pub use dirty::*;
+
+mod keyspace;
+pub use keyspace::*;
+
+mod collate;
+pub use collate::*;
+
+mod verify;
+pub use verify::*;
+
+mod guard;
+pub use guard::*;
+
+mod timeout;
+pub use timeout::*;
+
+mod snapshot;
+pub use snapshot::*;
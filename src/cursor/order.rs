@@ -0,0 +1,114 @@
+use std::cmp::Ordering;
+
+use crate::{Error, Result};
+
+/// Order of the keys in the trees, recorded in the tree header when the
+/// database is created. The empty key is the minimum for every comparator,
+/// so an empty scan start still means the beginning of the range.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum KeyComparator {
+  #[default]
+  Lexicographic,
+  /// 8 byte keys are ordered as big endian `i64`,
+  /// and keys of any other length follow them in byte order.
+  SignedBigEndianI64,
+  ReverseLexicographic,
+}
+impl KeyComparator {
+  pub fn compare<A, B>(&self, a: A, b: B) -> Ordering
+  where
+    A: AsRef<[u8]>,
+    B: AsRef<[u8]>,
+  {
+    let (a, b) = (a.as_ref(), b.as_ref());
+    if a.is_empty() || b.is_empty() {
+      return a.len().min(1).cmp(&b.len().min(1));
+    }
+    match self {
+      KeyComparator::Lexicographic => a.cmp(b),
+      KeyComparator::SignedBigEndianI64 => match (as_i64(a), as_i64(b)) {
+        (Some(a), Some(b)) => a.cmp(&b),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => a.cmp(b),
+      },
+      KeyComparator::ReverseLexicographic => b.cmp(a),
+    }
+  }
+
+  pub fn lt<A, B>(&self, a: A, b: B) -> bool
+  where
+    A: AsRef<[u8]>,
+    B: AsRef<[u8]>,
+  {
+    self.compare(a, b).is_lt()
+  }
+
+  pub fn ge<A, B>(&self, a: A, b: B) -> bool
+  where
+    A: AsRef<[u8]>,
+    B: AsRef<[u8]>,
+  {
+    self.compare(a, b).is_ge()
+  }
+
+  pub fn to_byte(&self) -> u8 {
+    match self {
+      KeyComparator::Lexicographic => 0,
+      KeyComparator::SignedBigEndianI64 => 1,
+      KeyComparator::ReverseLexicographic => 2,
+    }
+  }
+
+  pub fn from_byte(byte: u8) -> Result<Self> {
+    match byte {
+      0 => Ok(KeyComparator::Lexicographic),
+      1 => Ok(KeyComparator::SignedBigEndianI64),
+      2 => Ok(KeyComparator::ReverseLexicographic),
+      _ => Err(Error::Invalid),
+    }
+  }
+}
+
+fn as_i64(key: &[u8]) -> Option<i64> {
+  key.try_into().ok().map(i64::from_be_bytes)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::cmp::Ordering;
+
+  use super::KeyComparator;
+
+  #[test]
+  fn _1() {
+    let mut keys = [5i64, -1, 0, i64::MIN, 300]
+      .iter()
+      .map(|i| i.to_be_bytes().to_vec())
+      .collect::<Vec<_>>();
+    keys.push(b"tail".to_vec());
+    keys.push(vec![]);
+    let cmp = KeyComparator::SignedBigEndianI64;
+    keys.sort_by(|a, b| cmp.compare(a, b));
+
+    let mut expected = vec![vec![]];
+    expected.extend(
+      [i64::MIN, -1, 0, 5, 300]
+        .iter()
+        .map(|i| i.to_be_bytes().to_vec()),
+    );
+    expected.push(b"tail".to_vec());
+    assert_eq!(keys, expected);
+
+    let reverse = KeyComparator::ReverseLexicographic;
+    assert_eq!(reverse.compare(b"a", b"b"), Ordering::Greater);
+    assert_eq!(reverse.compare(b"", b"b"), Ordering::Less);
+    for cmp in [
+      KeyComparator::Lexicographic,
+      KeyComparator::SignedBigEndianI64,
+      KeyComparator::ReverseLexicographic,
+    ] {
+      assert_eq!(KeyComparator::from_byte(cmp.to_byte()).unwrap(), cmp);
+    }
+  }
+}
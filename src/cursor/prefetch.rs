@@ -0,0 +1,58 @@
+use crossbeam::channel::{bounded, Receiver};
+
+use crate::{Error, Result};
+
+use super::{CursorEntry, CursorWriter, KeyComparator};
+
+/// Walks the leaf chain from the leaf on a background thread, reading each
+/// leaf and the values of its keys before the end, so they are in the buffer
+/// pool when the scan reaches them. The index of each warmed leaf is sent in
+/// chain order, and the bounded channel keeps the worker at most `depth`
+/// leaves ahead of the scan. The worker stops at the end of the range,
+/// at the first page it can not read, or once the receiver is dropped.
+pub fn spawn_prefetch(
+  writer: CursorWriter,
+  first: Option<usize>,
+  end: Vec<u8>,
+  cmp: KeyComparator,
+  depth: usize,
+) -> Result<Receiver<usize>> {
+  let (tx, rx) = bounded(depth);
+  std::thread::Builder::new()
+    .name(format!("cursor {} prefetch", writer.get_id()))
+    .spawn(move || {
+      let mut next = first;
+      while let Some(index) = next {
+        let node = match writer.get(index) {
+          Ok(CursorEntry::Leaf(node)) => node,
+          _ => return,
+        };
+        for (key, value) in &node.keys {
+          if cmp.ge(key, &end) {
+            tx.send(index).ok();
+            return;
+          }
+          if writer.get_ref(*value).is_err() {
+            return;
+          }
+        }
+        if tx.send(index).is_err() {
+          return;
+        }
+        next = node.next;
+      }
+    })
+    .map_err(Error::IO)?;
+  Ok(rx)
+}
+
+/// Waits until the worker has warmed the leaf. Warmed leaves before it are
+/// skipped, and a leaf the worker never reaches ends the wait when
+/// the worker stops, so the scan keeps its own order either way.
+pub fn await_leaf(warmed: &Receiver<usize>, index: usize) {
+  for leaf in warmed.iter() {
+    if leaf.eq(&index) {
+      return;
+    }
+  }
+}
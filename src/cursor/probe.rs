@@ -0,0 +1,100 @@
+use std::{cmp::Ordering, ops::Add};
+
+/// Searches sorted items like `binary_search_by`, starting from the slot
+/// found by the previous probe. Ascending probes gallop forward from the hint,
+/// so nearby keys take a few comparisons instead of a full binary search.
+/// A probe behind the hint falls back to a binary search before it.
+pub fn gallop_by<T, F>(items: &[T], hint: usize, mut f: F) -> Result<usize, usize>
+where
+  F: FnMut(&T) -> Ordering,
+{
+  if items.is_empty() {
+    return Err(0);
+  }
+  let hint = hint.min(items.len() - 1);
+  match f(&items[hint]) {
+    Ordering::Equal => return Ok(hint),
+    Ordering::Greater => return items[..hint].binary_search_by(f),
+    Ordering::Less => {}
+  }
+
+  // items[hint + bound / 2] is known to be less than the key.
+  let mut bound = 1;
+  while hint.add(bound).lt(&items.len()) && f(&items[hint + bound]).is_lt() {
+    bound *= 2;
+  }
+  let start = hint + bound / 2 + 1;
+  let end = hint.add(bound).add(1).min(items.len());
+  items[start..end]
+    .binary_search_by(f)
+    .map(|i| start + i)
+    .map_err(|i| start + i)
+}
+
+/// Node and slot found at each depth by the previous probe of a batch,
+/// so the next probe reuses the node and searches from the slot.
+pub struct ProbeHints<E>(Vec<(usize, E, usize)>);
+impl<E> ProbeHints<E> {
+  pub fn new() -> Self {
+    Self(vec![])
+  }
+
+  /// Returns the cached node at the depth if it is the node of the index.
+  pub fn get(&self, depth: usize, index: usize) -> Option<(&E, usize)> {
+    self
+      .0
+      .get(depth)
+      .filter(|(i, _, _)| i.eq(&index))
+      .map(|(_, entry, slot)| (entry, *slot))
+  }
+
+  /// Records the node and slot at the depth and forgets the deeper ones,
+  /// which belonged to the previous path.
+  pub fn set(&mut self, depth: usize, index: usize, entry: E, slot: usize) {
+    self.0.truncate(depth);
+    self.0.push((index, entry, slot));
+  }
+
+  pub fn update_slot(&mut self, depth: usize, slot: usize) {
+    if let Some(hint) = self.0.get_mut(depth) {
+      hint.2 = slot;
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{cell::Cell, ops::Mul};
+
+  use super::gallop_by;
+
+  #[test]
+  fn _1() {
+    let items = (0..4096u32).map(|i| i * 2).collect::<Vec<_>>();
+    let probes = (0..8192u32).step_by(7).collect::<Vec<_>>();
+
+    let naive = Cell::new(0usize);
+    let galloped = Cell::new(0usize);
+    let mut hint = 0;
+    for probe in &probes {
+      let expected = items.binary_search_by(|v| {
+        naive.set(naive.get() + 1);
+        v.cmp(probe)
+      });
+      let found = gallop_by(&items, hint, |v| {
+        galloped.set(galloped.get() + 1);
+        v.cmp(probe)
+      });
+      assert_eq!(found, expected);
+      hint = found.unwrap_or_else(|i| i);
+    }
+    assert!(galloped.get().mul(2).lt(&naive.get()));
+
+    // probes behind the hint and past the end.
+    assert_eq!(gallop_by(&items, 100, |v| v.cmp(&10)), Ok(5));
+    assert_eq!(gallop_by(&items, 100, |v| v.cmp(&3)), Err(2));
+    assert_eq!(gallop_by(&items, 4095, |v| v.cmp(&9000)), Err(4096));
+    assert_eq!(gallop_by(&items, 9999, |v| v.cmp(&0)), Ok(0));
+    assert_eq!(gallop_by::<u32, _>(&[], 3, |v| v.cmp(&0)), Err(0));
+  }
+}
@@ -0,0 +1,663 @@
+use std::{
+  cmp::Ordering, collections::VecDeque, marker::PhantomData, ops::AddAssign,
+  time::Instant,
+};
+
+use crate::{Error, Page, Result, Serializable};
+
+use super::{
+  await_leaf, spawn_prefetch, CursorEntry, CursorWriter, KeyComparator, LeafNode,
+  TreeHeader, HEADER_INDEX,
+};
+
+pub type ScanItem<T> = Result<(Vec<u8>, T)>;
+
+type SizeFilter<'a> = Box<dyn Fn(usize) -> bool + 'a>;
+type KeyFilter<'a> = Box<dyn Fn(&[u8]) -> bool + 'a>;
+type LeafHook<'a> = Box<dyn FnMut(usize) + 'a>;
+
+/// Streams the range one leaf at a time and reads each value only when it is
+/// yielded, so the peak memory is O(one leaf's keys + one value)
+/// regardless of the size of the range.
+pub struct ScanIter<'a, T> {
+  writer: &'a CursorWriter,
+  cmp: KeyComparator,
+  node: LeafNode,
+  position: usize,
+  start: Vec<u8>,
+  /// The start is excluded once a key is yielded, since it is that key.
+  exclusive: bool,
+  end: Vec<u8>,
+  size_filter: Option<SizeFilter<'a>>,
+  key_filter: Option<KeyFilter<'a>>,
+  deadline: Option<Instant>,
+  /// Called with the index of each leaf after the first before it is read.
+  on_leaf: Option<LeafHook<'a>>,
+  _value: PhantomData<T>,
+}
+impl<'a, T> ScanIter<'a, T>
+where
+  T: Serializable,
+{
+  pub fn new(
+    writer: &'a CursorWriter,
+    cmp: KeyComparator,
+    node: LeafNode,
+    start: Vec<u8>,
+    exclusive: bool,
+    end: Vec<u8>,
+  ) -> Self {
+    let position = first_unyielded(&node, &start, exclusive, cmp);
+    Self {
+      writer,
+      cmp,
+      node,
+      position,
+      start,
+      exclusive,
+      end,
+      size_filter: None,
+      key_filter: None,
+      deadline: None,
+      on_leaf: None,
+      _value: PhantomData,
+    }
+  }
+
+  /// Skips entries whose stored value size is rejected by the filter.
+  /// The size is read from the block header, so skipped values are not read.
+  pub fn with_size_filter<F>(mut self, filter: F) -> Self
+  where
+    F: Fn(usize) -> bool + 'a,
+  {
+    self.size_filter = Some(Box::new(filter));
+    self
+  }
+
+  /// Skips entries whose key is rejected by the filter.
+  /// Keys are in the leaf, so skipped values are not read.
+  pub fn with_key_filter<F>(mut self, filter: F) -> Self
+  where
+    F: Fn(&[u8]) -> bool + 'a,
+  {
+    self.key_filter = Some(Box::new(filter));
+    self
+  }
+
+  /// Fails the scan with `Error::Timeout` before reading the next leaf
+  /// once the deadline has passed.
+  pub fn with_deadline(mut self, deadline: Instant) -> Self {
+    self.deadline = Some(deadline);
+    self
+  }
+
+  /// Reads up to `depth` leaves ahead of the scan with their values on
+  /// a background thread using the writer. At least one leaf is read ahead,
+  /// and the entries are still read and yielded by this iterator in order.
+  pub fn with_prefetch(mut self, writer: CursorWriter, depth: usize) -> Result<Self> {
+    if self.node.next.is_none() {
+      return Ok(self);
+    }
+    let warmed =
+      spawn_prefetch(writer, self.node.next, self.end.clone(), self.cmp, depth)?;
+    self.on_leaf = Some(Box::new(move |index| await_leaf(&warmed, index)));
+    Ok(self)
+  }
+
+  /// Descends to the leaf which contains the start key.
+  pub fn seek(
+    writer: &'a CursorWriter,
+    keyspace: &[u8],
+    start: &Vec<u8>,
+    end: &[u8],
+  ) -> Result<Self> {
+    Self::seek_from(writer, keyspace, start, false, end)
+  }
+
+  /// Descends to the leaf which contains the start key,
+  /// excluding the start key itself if it is exclusive.
+  pub fn seek_from(
+    writer: &'a CursorWriter,
+    keyspace: &[u8],
+    start: &Vec<u8>,
+    exclusive: bool,
+    end: &[u8],
+  ) -> Result<Self> {
+    let header: TreeHeader = writer.get(HEADER_INDEX)?;
+    let cmp = header.get_comparator();
+    if is_empty_range(start, end, cmp) {
+      return Ok(Self::empty(writer));
+    }
+
+    let mut index = match header.get_keyspace_root(keyspace) {
+      Some(root) => root,
+      None => return Ok(Self::empty(writer)),
+    };
+    loop {
+      match writer.get(index)? {
+        CursorEntry::Internal(node) => index = node.next(start, cmp),
+        CursorEntry::Leaf(node) => {
+          return Ok(Self::new(
+            writer,
+            cmp,
+            node,
+            start.clone(),
+            exclusive,
+            end.to_vec(),
+          ));
+        }
+      }
+    }
+  }
+
+  pub fn empty(writer: &'a CursorWriter) -> Self {
+    Self::new(
+      writer,
+      KeyComparator::default(),
+      LeafNode::empty(),
+      vec![],
+      false,
+      vec![],
+    )
+  }
+
+  pub fn get_comparator(&self) -> KeyComparator {
+    self.cmp
+  }
+
+  /// A leaf of the chain which can not be read fails the scan
+  /// with `Error::ScanCorruption` positioned after the last yielded key.
+  pub fn try_next(&mut self) -> Result<Option<(Vec<u8>, T)>> {
+    self.advance(|writer, key, index| Ok((key.clone(), writer.get(index)?)))
+  }
+
+  /// Yields the key with the index of its value, leaving the value unread.
+  pub fn try_next_index(&mut self) -> Result<Option<(Vec<u8>, usize)>> {
+    self.advance(|_, key, index| Ok((key.clone(), index)))
+  }
+
+  /// Applies the projection to the key and the raw page of each value,
+  /// borrowed from the buffer pool only for the call.
+  pub fn try_next_map<R, F>(&mut self, f: &mut F) -> Result<Option<R>>
+  where
+    F: FnMut(&[u8], &[u8]) -> R,
+  {
+    self.advance(|writer, key, index| {
+      let page = writer.get_ref(index)?;
+      Ok(f(key, page.as_ref().as_ref()))
+    })
+  }
+
+  /// Moves to the next key in the range and reads it with the reader.
+  fn advance<R, F>(&mut self, mut read: F) -> Result<Option<R>>
+  where
+    F: FnMut(&CursorWriter, &Vec<u8>, usize) -> Result<R>,
+  {
+    loop {
+      while self.position.ge(&self.node.len()) {
+        let next = match self.node.next {
+          Some(i) => i,
+          None => return Ok(self.finish()),
+        };
+        if self.deadline.is_some_and(|d| Instant::now().ge(&d)) {
+          return Err(Error::Timeout);
+        }
+        if let Some(on_leaf) = self.on_leaf.as_mut() {
+          on_leaf(next);
+        }
+        self.node = match self.writer.get(next) {
+          Ok(CursorEntry::Leaf(node)) => node,
+          Ok(CursorEntry::Internal(_)) => return Ok(self.finish()),
+          Err(Error::Invalid | Error::EOF) => {
+            return Err(Error::ScanCorruption {
+              index: next,
+              last_key: self.exclusive.then(|| self.start.clone()),
+            })
+          }
+          Err(err) => return Err(err),
+        };
+        self.position =
+          first_unyielded(&self.node, &self.start, self.exclusive, self.cmp);
+      }
+
+      let (key, index) = &self.node.keys[self.position];
+      if self.cmp.ge(key, &self.end) {
+        return Ok(self.finish());
+      }
+      let skipped = match (&self.key_filter, &self.size_filter) {
+        (Some(filter), _) if !filter(key) => true,
+        (_, Some(filter)) => !filter(self.writer.get_len(*index)?),
+        _ => false,
+      };
+      let value = match skipped {
+        true => None,
+        false => Some(read(self.writer, key, *index)?),
+      };
+      if self.exclusive {
+        debug_assert_after(&self.start, key, self.cmp);
+      }
+      self.position.add_assign(1);
+      self.start = key.clone();
+      self.exclusive = true;
+      if let Some(value) = value {
+        return Ok(Some(value));
+      }
+    }
+  }
+
+  /// Drops the current leaf once the range is exhausted,
+  /// so a finished iterator holds no keys.
+  fn finish<R>(&mut self) -> Option<R> {
+    self.node = LeafNode::empty();
+    self.position = 0;
+    None
+  }
+
+  /// Returns the token to continue this scan after the last yielded key
+  /// on the same snapshot.
+  pub fn resume_token(&self) -> ResumeToken {
+    ResumeToken {
+      snapshot: self.writer.get_snapshot(),
+      start: self.start.clone(),
+      exclusive: self.exclusive,
+      end: self.end.clone(),
+    }
+  }
+
+  /// Consumes the scan as an `Iterator`.
+  /// An error is yielded once and the iterator is finished after it.
+  pub fn items(self) -> ScanItems<'a, T> {
+    ScanItems { inner: Some(self) }
+  }
+}
+
+/// Scan ranges are half-open, so the range is empty if `start >= end`.
+/// An empty `start` is the minimum key and an empty `end` makes the range empty.
+pub fn is_empty_range(start: &[u8], end: &[u8], cmp: KeyComparator) -> bool {
+  cmp.ge(start, end)
+}
+
+/// Panics in debug builds if the key does not follow the previous key
+/// of the scan, which means the tree is out of order.
+fn debug_assert_after(previous: &[u8], key: &[u8], cmp: KeyComparator) {
+  debug_assert!(
+    cmp.lt(previous, key),
+    "scan out of order: {key:?} after {previous:?}"
+  );
+}
+
+/// Position of the first key not yielded yet in the leaf.
+/// A leaf reached through the next link can repeat keys of the previous leaf
+/// if it was split by a writer during the scan, so those are skipped
+/// to yield each key once.
+fn first_unyielded(
+  node: &LeafNode,
+  start: &[u8],
+  exclusive: bool,
+  cmp: KeyComparator,
+) -> usize {
+  node
+    .keys
+    .partition_point(|(k, _)| match cmp.compare(k, start) {
+      Ordering::Less => true,
+      Ordering::Equal => exclusive,
+      Ordering::Greater => false,
+    })
+}
+
+/// Opaque position of a paginated scan, pinned to the snapshot of the scan.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ResumeToken {
+  snapshot: usize,
+  start: Vec<u8>,
+  /// True if the start is the last key already yielded.
+  exclusive: bool,
+  end: Vec<u8>,
+}
+impl ResumeToken {
+  pub fn get_snapshot(&self) -> usize {
+    self.snapshot
+  }
+
+  pub fn get_start(&self) -> &Vec<u8> {
+    &self.start
+  }
+
+  pub fn is_exclusive(&self) -> bool {
+    self.exclusive
+  }
+
+  pub fn get_end(&self) -> &[u8] {
+    &self.end
+  }
+
+  pub fn to_bytes(&self) -> Vec<u8> {
+    let mut bytes = vec![];
+    bytes.extend_from_slice(&(self.snapshot as u64).to_be_bytes());
+    for key in [&self.start, &self.end] {
+      bytes.extend_from_slice(&(key.len() as u64).to_be_bytes());
+      bytes.extend_from_slice(key);
+    }
+    bytes.push(self.exclusive as u8);
+    bytes
+  }
+
+  pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+    let mut offset = 0;
+    let read_u64 = |offset: &mut usize| -> Result<usize> {
+      let b = bytes.get(*offset..*offset + 8).ok_or(Error::Invalid)?;
+      *offset += 8;
+      Ok(u64::from_be_bytes(b.try_into().map_err(|_| Error::Invalid)?) as usize)
+    };
+    let snapshot = read_u64(&mut offset)?;
+    let mut keys = vec![];
+    for _ in 0..2 {
+      let len = read_u64(&mut offset)?;
+      let key = bytes
+        .get(offset..offset.saturating_add(len))
+        .ok_or(Error::Invalid)?;
+      offset += len;
+      keys.push(key.to_vec());
+    }
+    let exclusive = match bytes.get(offset..) {
+      Some([0]) => false,
+      Some([1]) => true,
+      _ => return Err(Error::Invalid),
+    };
+    let end = keys.pop().unwrap_or_default();
+    let start = keys.pop().unwrap_or_default();
+
+    Ok(Self {
+      snapshot,
+      start,
+      exclusive,
+      end,
+    })
+  }
+}
+
+pub type ScanPage<T> = (Vec<(Vec<u8>, T)>, Option<ResumeToken>);
+
+pub struct ScanItems<'a, T> {
+  inner: Option<ScanIter<'a, T>>,
+}
+impl<'a, T> Iterator for ScanItems<'a, T>
+where
+  T: Serializable,
+{
+  type Item = ScanItem<T>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    let result = self.inner.as_mut()?.try_next();
+    match result {
+      Ok(Some(item)) => Some(Ok(item)),
+      Ok(None) => {
+        self.inner = None;
+        None
+      }
+      Err(err) => {
+        self.inner = None;
+        Some(Err(err))
+      }
+    }
+  }
+}
+
+/// Scan yielding the projection of each entry, see `Cursor::scan_map`.
+/// An error is yielded once and the iterator is finished after it.
+pub struct ScanMap<'a, F> {
+  inner: Option<ScanIter<'a, Page>>,
+  f: F,
+}
+impl<'a, F> ScanMap<'a, F> {
+  pub fn new(inner: ScanIter<'a, Page>, f: F) -> Self {
+    Self {
+      inner: Some(inner),
+      f,
+    }
+  }
+}
+impl<'a, R, F> Iterator for ScanMap<'a, F>
+where
+  F: FnMut(&[u8], &[u8]) -> R,
+{
+  type Item = Result<R>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    let result = self.inner.as_mut()?.try_next_map(&mut self.f);
+    match result {
+      Ok(Some(item)) => Some(Ok(item)),
+      Ok(None) => {
+        self.inner = None;
+        None
+      }
+      Err(err) => {
+        self.inner = None;
+        Some(Err(err))
+      }
+    }
+  }
+}
+
+/// Range of a multi range scan with its position in the given ranges.
+type TaggedRange = (usize, Vec<u8>, Vec<u8>);
+
+/// Scans several ranges in one descent and one walk of the leaf chain,
+/// from the first start to the last end. Keys between the ranges are
+/// skipped without reading their values, and a key in overlapping ranges is
+/// yielded once per range, with each value read when it is yielded.
+/// An error is yielded once and the iterator is finished after it.
+pub struct MultiScan<'a, T> {
+  inner: Option<ScanIter<'a, T>>,
+  writer: &'a CursorWriter,
+  cmp: KeyComparator,
+  /// Non empty ranges sorted by their start.
+  ranges: Vec<TaggedRange>,
+  current: Option<(Vec<u8>, usize)>,
+  /// Positions of the ranges the current key is still to be yielded for.
+  pending: VecDeque<usize>,
+}
+impl<'a, T> MultiScan<'a, T>
+where
+  T: Serializable,
+{
+  pub fn seek(
+    writer: &'a CursorWriter,
+    keyspace: &[u8],
+    ranges: Vec<(Vec<u8>, Vec<u8>)>,
+  ) -> Result<Self> {
+    let header: TreeHeader = writer.get(HEADER_INDEX)?;
+    let cmp = header.get_comparator();
+    let mut ranges = ranges
+      .into_iter()
+      .enumerate()
+      .filter(|(_, (start, end))| !is_empty_range(start, end, cmp))
+      .map(|(i, (start, end))| (i, start, end))
+      .collect::<Vec<_>>();
+    ranges.sort_by(|(_, a, _), (_, b, _)| cmp.compare(a, b));
+
+    let start = ranges
+      .first()
+      .map(|(_, start, _)| start.clone())
+      .unwrap_or_default();
+    let end = ranges
+      .iter()
+      .map(|(_, _, end)| end)
+      .max_by(|a, b| cmp.compare(a, b))
+      .cloned()
+      .unwrap_or_default();
+    let matched = ranges.clone();
+    let inner = ScanIter::seek(writer, keyspace, &start, &end)?
+      .with_key_filter(move |key| matched.iter().any(|range| contains(range, key, cmp)));
+    Ok(Self {
+      inner: Some(inner),
+      writer,
+      cmp,
+      ranges,
+      current: None,
+      pending: VecDeque::new(),
+    })
+  }
+
+  /// Returns the next entry with the position of its range in the given ranges.
+  pub fn try_next(&mut self) -> Result<Option<(usize, Vec<u8>, T)>> {
+    loop {
+      if let Some((key, index)) = &self.current {
+        if let Some(range) = self.pending.pop_front() {
+          return Ok(Some((range, key.clone(), self.writer.get(*index)?)));
+        }
+      }
+      let inner = match self.inner.as_mut() {
+        Some(inner) => inner,
+        None => return Ok(None),
+      };
+      let (key, index) = match inner.try_next_index()? {
+        Some(entry) => entry,
+        None => {
+          self.inner = None;
+          return Ok(None);
+        }
+      };
+      let mut pending = self
+        .ranges
+        .iter()
+        .filter(|range| contains(range, &key, self.cmp))
+        .map(|(i, _, _)| *i)
+        .collect::<Vec<_>>();
+      pending.sort();
+      self.pending = pending.into();
+      self.current = Some((key, index));
+    }
+  }
+}
+impl<'a, T> Iterator for MultiScan<'a, T>
+where
+  T: Serializable,
+{
+  type Item = Result<(usize, Vec<u8>, T)>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    match self.try_next() {
+      Ok(entry) => entry.map(Ok),
+      Err(err) => {
+        self.inner = None;
+        self.pending.clear();
+        Some(Err(err))
+      }
+    }
+  }
+}
+
+fn contains((_, start, end): &TaggedRange, key: &[u8], cmp: KeyComparator) -> bool {
+  cmp.ge(key, start) && cmp.lt(key, end)
+}
+
+/// Merges the live keys of a scan with the deleted keys in the range,
+/// yielding `None` as the value of a deleted key.
+pub struct TombstoneScan<'a, T> {
+  live: ScanIter<'a, T>,
+  peeked: Option<(Vec<u8>, T)>,
+  exhausted: bool,
+  tombstones: VecDeque<Vec<u8>>,
+}
+impl<'a, T> TombstoneScan<'a, T>
+where
+  T: Serializable,
+{
+  pub fn new(live: ScanIter<'a, T>, mut tombstones: Vec<Vec<u8>>) -> Self {
+    tombstones.sort_by(|a, b| live.cmp.compare(a, b));
+    tombstones.dedup();
+    Self {
+      live,
+      peeked: None,
+      exhausted: false,
+      tombstones: tombstones.into(),
+    }
+  }
+
+  pub fn try_next(&mut self) -> Result<Option<(Vec<u8>, Option<T>)>> {
+    if self.peeked.is_none() && !self.exhausted {
+      self.peeked = self.live.try_next()?;
+      self.exhausted = self.peeked.is_none();
+    }
+    let tombstone = self.tombstones.front();
+    let live_first = match (&self.peeked, tombstone) {
+      (Some((key, _)), Some(deleted)) => self.live.cmp.compare(key, deleted).is_le(),
+      (Some(_), None) => true,
+      (None, Some(_)) => false,
+      (None, None) => return Ok(None),
+    };
+    if !live_first {
+      return Ok(self.tombstones.pop_front().map(|key| (key, None)));
+    }
+    let (key, value) = self.peeked.take().unwrap();
+    // a key deleted and written again by the transaction is live.
+    if self.tombstones.front().eq(&Some(&key)) {
+      self.tombstones.pop_front();
+    }
+    Ok(Some((key, Some(value))))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::cursor::{KeyComparator, LeafNode};
+
+  use super::{first_unyielded, is_empty_range, ResumeToken};
+
+  const BYTES: KeyComparator = KeyComparator::Lexicographic;
+
+  #[test]
+  fn _1() {
+    assert!(is_empty_range(b"b", b"a", BYTES));
+    assert!(is_empty_range(b"a", b"a", BYTES));
+    assert!(!is_empty_range(b"a", b"b", BYTES));
+    assert!(!is_empty_range(b"a", b"a\0", BYTES));
+    assert!(!is_empty_range(
+      b"b",
+      b"a",
+      KeyComparator::ReverseLexicographic
+    ));
+  }
+
+  #[test]
+  fn _2() {
+    assert!(!is_empty_range(b"", b"a", BYTES));
+    assert!(is_empty_range(b"", b"", BYTES));
+    assert!(is_empty_range(b"a", b"", BYTES));
+  }
+  #[test]
+  fn _3() {
+    let token = ResumeToken {
+      snapshot: 7,
+      start: b"key-10".to_vec(),
+      exclusive: true,
+      end: b"key-99".to_vec(),
+    };
+    let bytes = token.to_bytes();
+    assert_eq!(ResumeToken::from_bytes(&bytes).unwrap(), token);
+    assert!(ResumeToken::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+    assert!(ResumeToken::from_bytes(&[]).is_err());
+
+    assert_eq!(token.get_start(), b"key-10");
+    assert!(token.is_exclusive());
+  }
+
+  #[test]
+  fn _4() {
+    let node = LeafNode {
+      keys: [b"b", b"c", b"d", b"e"]
+        .iter()
+        .enumerate()
+        .map(|(i, k)| (k.to_vec(), i))
+        .collect(),
+      prev: None,
+      next: None,
+    };
+    assert_eq!(first_unyielded(&node, b"", false, BYTES), 0);
+    assert_eq!(first_unyielded(&node, b"a", true, BYTES), 0);
+    assert_eq!(first_unyielded(&node, b"c", true, BYTES), 2);
+    assert_eq!(first_unyielded(&node, b"c", false, BYTES), 1);
+    assert_eq!(first_unyielded(&node, b"e", true, BYTES), 4);
+  }
+}
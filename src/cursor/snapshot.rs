@@ -0,0 +1,170 @@
+use std::{
+  collections::BTreeSet,
+  mem::take,
+  sync::{Arc, Mutex},
+};
+
+use crate::{
+  buffer::{BufferPool, BLOCK_SIZE},
+  disk::FreeList,
+  wal::WriteAheadLog,
+  Error, Result, Serializable, ShortenedMutex,
+};
+
+use super::{find_index, verify_pages, CursorWriter, ScanIter, DEFAULT_KEYSPACE};
+
+#[derive(Default)]
+struct SnapshotsCore {
+  last_id: usize,
+  live: BTreeSet<usize>,
+  /// Pages released while snapshots were live,
+  /// with the last snapshot id issued at the release.
+  deferred: Vec<(usize, Vec<usize>)>,
+}
+
+/// Live snapshots, which hold back the pages released after them
+/// so the free list does not hand out a page a snapshot can still read.
+#[derive(Default)]
+pub struct Snapshots(Mutex<SnapshotsCore>);
+impl Snapshots {
+  pub fn pin(&self) -> usize {
+    let mut core = self.0.l();
+    core.last_id += 1;
+    let id = core.last_id;
+    core.live.insert(id);
+    id
+  }
+
+  /// Returns the pages which can be freed now that the snapshot is gone.
+  pub fn unpin(&self, id: usize) -> Vec<usize> {
+    let mut core = self.0.l();
+    core.live.remove(&id);
+    let oldest = core.live.first().copied().unwrap_or(usize::MAX);
+    let (freed, deferred) = take(&mut core.deferred)
+      .into_iter()
+      .partition::<Vec<_>, _>(|(last_id, _)| last_id.lt(&oldest));
+    core.deferred = deferred;
+    freed.into_iter().flat_map(|(_, pages)| pages).collect()
+  }
+
+  /// Returns the released pages which can be freed now,
+  /// and holds the rest until the live snapshots are released.
+  pub fn release(&self, pages: Vec<usize>) -> Vec<usize> {
+    let mut core = self.0.l();
+    if core.live.is_empty() || pages.is_empty() {
+      return pages;
+    }
+    let last_id = core.last_id;
+    core.deferred.push((last_id, pages));
+    vec![]
+  }
+}
+
+/// Read only view of the tree pinned at a commit index, without a
+/// transaction. Pages released by later commits stay out of the free list
+/// until the snapshot is released, which happens on drop at the latest.
+/// Old versions live in the undo ring, so reads of a snapshot held across
+/// a wrap of the undo file can fail with `Error::SnapshotExpired`.
+///
+/// Only snapshots hold pages back. A transaction from
+/// `Engine::new_transaction` and a page read by `Engine::scan_resume` pin
+/// nothing, so a page released and reused by a later commit can change
+/// under them. Long reads which race with writes should use a snapshot.
+pub struct Snapshot {
+  id: usize,
+  writer: CursorWriter,
+  snapshots: Arc<Snapshots>,
+  freelist: Arc<FreeList<BLOCK_SIZE>>,
+  released: bool,
+}
+impl Snapshot {
+  pub fn new(
+    wal: Arc<WriteAheadLog>,
+    buffer: Arc<BufferPool>,
+    snapshots: Arc<Snapshots>,
+    freelist: Arc<FreeList<BLOCK_SIZE>>,
+  ) -> Self {
+    // pinned before the commit index is read,
+    // so no page released after the index is freed.
+    let id = snapshots.pin();
+    let commit_index = wal.last_index();
+    Self {
+      id,
+      writer: CursorWriter::new(usize::MAX, commit_index, wal, buffer),
+      snapshots,
+      freelist,
+      released: false,
+    }
+  }
+
+  pub fn get_commit_index(&self) -> usize {
+    self.writer.get_snapshot()
+  }
+
+  pub fn get<T>(&self, key: &Vec<u8>) -> Result<Option<T>>
+  where
+    T: Serializable,
+  {
+    match find_index(&self.writer, DEFAULT_KEYSPACE, key) {
+      Ok(index) => self.writer.get(index).map(Some),
+      Err(Error::NotFound) => Ok(None),
+      Err(err) => Err(err),
+    }
+  }
+
+  pub fn scan<T>(&self, start: &Vec<u8>, end: &[u8]) -> Result<ScanIter<'_, T>>
+  where
+    T: Serializable,
+  {
+    ScanIter::seek(&self.writer, DEFAULT_KEYSPACE, start, end)
+  }
+
+  /// Checks the structure of every keyspace tree as of the snapshot
+  /// and returns the indexes of the pages in use, including the header.
+  pub fn verify(&self) -> Result<BTreeSet<usize>> {
+    verify_pages(&self.writer)
+  }
+
+  /// Unpins the snapshot and frees the pages it held back.
+  pub fn release(mut self) {
+    self.unpin();
+  }
+
+  fn unpin(&mut self) {
+    if self.released {
+      return;
+    }
+    self.released = true;
+    self.freelist.insert_many(&self.snapshots.unpin(self.id));
+  }
+}
+impl Drop for Snapshot {
+  fn drop(&mut self) {
+    self.unpin();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::Snapshots;
+
+  #[test]
+  fn _1() {
+    let snapshots = Snapshots::default();
+    assert_eq!(snapshots.release(vec![1, 2]), vec![1, 2]);
+
+    let a = snapshots.pin();
+    assert!(snapshots.release(vec![3]).is_empty());
+    let b = snapshots.pin();
+    assert!(snapshots.release(vec![4]).is_empty());
+
+    // 4 was released after b, which is still live.
+    assert_eq!(snapshots.unpin(a), vec![3]);
+    assert!(snapshots.release(vec![5]).is_empty());
+    let mut freed = snapshots.unpin(b);
+    freed.sort();
+    assert_eq!(freed, vec![4, 5]);
+    assert!(snapshots.unpin(b).is_empty());
+    assert_eq!(snapshots.release(vec![6]), vec![6]);
+  }
+}
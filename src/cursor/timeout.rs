@@ -0,0 +1,106 @@
+use std::{
+  collections::BTreeMap,
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex, RwLock,
+  },
+  time::{Duration, Instant},
+};
+
+use crate::{buffer::BLOCK_SIZE, disk::FreeList, logger, Result, ShortenedMutex};
+
+use super::CursorWriter;
+
+/// State of an open transaction shared with its cursor,
+/// so it can be aborted while the cursor is still held by the user.
+pub struct TransactionHandle {
+  started: Instant,
+  closed: Arc<RwLock<bool>>,
+  timed_out: Arc<AtomicBool>,
+  writer: Arc<CursorWriter>,
+  freelist: Arc<FreeList<BLOCK_SIZE>>,
+}
+impl TransactionHandle {
+  pub fn new(
+    closed: Arc<RwLock<bool>>,
+    timed_out: Arc<AtomicBool>,
+    writer: Arc<CursorWriter>,
+    freelist: Arc<FreeList<BLOCK_SIZE>>,
+  ) -> Self {
+    Self {
+      started: Instant::now(),
+      closed,
+      timed_out,
+      writer,
+      freelist,
+    }
+  }
+
+  /// Aborts unless the cursor is closed or in the middle of an operation.
+  /// The cursor holds the read lock during operations and the write lock
+  /// during commit, so a busy transaction is left for the next sweep.
+  fn try_abort(&self) -> Option<Result> {
+    let mut closed = self.closed.try_write().ok()?;
+    if closed.eq(&true) {
+      return None;
+    }
+    *closed = true;
+    self.timed_out.store(true, Ordering::SeqCst);
+    Some(abort_writer(&self.writer, &self.freelist))
+  }
+}
+
+/// Transactions open on the engine by id.
+#[derive(Default)]
+pub struct ActiveTransactions(Mutex<BTreeMap<usize, Arc<TransactionHandle>>>);
+impl ActiveTransactions {
+  pub fn register(&self, handle: TransactionHandle) {
+    self.0.l().insert(handle.writer.get_id(), Arc::new(handle));
+  }
+
+  pub fn remove(&self, tx_id: usize) {
+    self.0.l().remove(&tx_id);
+  }
+
+  pub fn len(&self) -> usize {
+    self.0.l().len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.0.l().is_empty()
+  }
+
+  /// Aborts the transactions open longer than the timeout
+  /// and returns the number of aborted transactions.
+  pub fn abort_expired(&self, timeout: Duration) -> usize {
+    let expired = self
+      .0
+      .l()
+      .values()
+      .filter(|handle| handle.started.elapsed().gt(&timeout))
+      .cloned()
+      .collect::<Vec<_>>();
+
+    let mut aborted = 0;
+    for handle in expired {
+      match handle.try_abort() {
+        Some(Ok(())) => aborted += 1,
+        Some(Err(err)) => logger::error(format!(
+          "failed to abort timed out transaction {} {:?}",
+          handle.writer.get_id(),
+          err
+        )),
+        None => continue,
+      }
+      self.remove(handle.writer.get_id());
+    }
+    aborted
+  }
+}
+
+/// Rolls back the writes of the transaction and frees the pages it allocated.
+pub fn abort_writer(writer: &CursorWriter, freelist: &FreeList<BLOCK_SIZE>) -> Result {
+  let freed = writer.abort()?;
+  freelist.insert_many(&freed);
+  Ok(())
+}
@@ -0,0 +1,139 @@
+use std::{
+  ops::Add,
+  time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{Error, Page, Result, Serializable, PAGE_SIZE};
+
+use super::ScanIter;
+
+pub trait Clock: Send + Sync {
+  fn now(&self) -> Duration;
+}
+
+pub struct SystemClock;
+impl Clock for SystemClock {
+  fn now(&self) -> Duration {
+    SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .unwrap_or_default()
+  }
+}
+
+const INNER_BYTES: usize = PAGE_SIZE - 10;
+
+#[derive(Debug)]
+pub struct Expirable<T> {
+  expire_at: usize,
+  value: T,
+}
+impl<T> Expirable<T> {
+  pub fn new(value: T, now: Duration, ttl: Duration) -> Self {
+    Self {
+      expire_at: now.add(ttl).as_millis() as usize,
+      value,
+    }
+  }
+
+  pub fn is_expired(&self, now: Duration) -> bool {
+    self.expire_at.le(&(now.as_millis() as usize))
+  }
+
+  pub fn into_inner(self) -> T {
+    self.value
+  }
+}
+impl<T> Serializable for Expirable<T>
+where
+  T: Serializable,
+{
+  /// Fails with `Error::EOF` if the value does not fit after the expiry.
+  fn serialize(&self) -> Result<Page> {
+    let inner = self.value.serialize()?;
+    if inner.used_len().gt(&INNER_BYTES) {
+      return Err(Error::EOF);
+    }
+    let mut p = Page::new();
+    let mut wt = p.writer();
+    wt.write(&self.expire_at.to_be_bytes())?;
+    wt.write(&inner.as_ref()[1..INNER_BYTES.add(1)])?;
+    Ok(p)
+  }
+
+  fn deserialize(value: &Page) -> Result<Self> {
+    let mut sc = value.scanner();
+    let expire_at = sc.read_usize()?;
+    let mut inner = Page::new();
+    inner.writer().write(sc.read_n(INNER_BYTES)?)?;
+    Ok(Self {
+      expire_at,
+      value: inner.deserialize()?,
+    })
+  }
+}
+
+/// Scan of values written by `Cursor::set_ttl`, skipping the expired ones.
+pub struct UnexpiredScan<'a, T> {
+  inner: ScanIter<'a, Expirable<T>>,
+  now: Duration,
+}
+impl<'a, T> UnexpiredScan<'a, T>
+where
+  T: Serializable,
+{
+  pub fn new(inner: ScanIter<'a, Expirable<T>>, now: Duration) -> Self {
+    Self { inner, now }
+  }
+
+  pub fn try_next(&mut self) -> Result<Option<(Vec<u8>, T)>> {
+    while let Some((key, value)) = self.inner.try_next()? {
+      if !value.is_expired(self.now) {
+        return Ok(Some((key, value.into_inner())));
+      }
+    }
+    Ok(None)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+
+  use crate::{Error, Page, Serializable, PAGE_SIZE};
+
+  use super::Expirable;
+
+  #[test]
+  fn _1() {
+    let mut page = Page::new();
+    page.writer().write(&[7, 8, 9]).unwrap();
+
+    let now = Duration::from_secs(100);
+    let e = Expirable::new(page, now, Duration::from_millis(50));
+    let e: Expirable<Page> = e.serialize().unwrap().deserialize().unwrap();
+
+    assert!(!e.is_expired(now));
+    assert!(!e.is_expired(now + Duration::from_millis(49)));
+    assert!(e.is_expired(now + Duration::from_millis(50)));
+
+    let page = e.into_inner();
+    let mut sc = page.scanner();
+    assert_eq!(sc.read().unwrap(), 7);
+    assert_eq!(sc.read().unwrap(), 8);
+    assert_eq!(sc.read().unwrap(), 9);
+    assert_eq!(sc.read().unwrap(), 0);
+  }
+
+  #[test]
+  fn _2() {
+    let now = Duration::from_secs(100);
+    let fits = |len: usize| {
+      let mut page = Page::new();
+      page.writer().write(&vec![1; len]).unwrap();
+      Expirable::new(page, now, Duration::from_secs(1)).serialize()
+    };
+    let e: Expirable<Page> = fits(PAGE_SIZE - 10).unwrap().deserialize().unwrap();
+    assert_eq!(e.into_inner().used_len(), PAGE_SIZE - 10);
+    assert!(matches!(fits(PAGE_SIZE - 9), Err(Error::EOF)));
+  }
+}
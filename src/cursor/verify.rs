@@ -0,0 +1,169 @@
+use std::collections::BTreeSet;
+
+use crate::{Error, Result};
+
+use super::{CursorEntry, CursorWriter, KeyComparator, TreeHeader, HEADER_INDEX};
+
+/// Page index to visit with the lower and upper bounds of its keys.
+type Bounded = (usize, Option<Vec<u8>>, Option<Vec<u8>>);
+
+/// Walks the trees from the roots and checks that every node is readable,
+/// keys are ascending within the separators of the parent,
+/// and no page is referenced twice.
+/// Returns the indexes of every node and data page referenced by the trees.
+pub fn verify_tree<F>(
+  read: F,
+  roots: &[usize],
+  cmp: KeyComparator,
+) -> Result<BTreeSet<usize>>
+where
+  F: Fn(usize) -> Result<CursorEntry>,
+{
+  let mut referenced = BTreeSet::new();
+  let mut stack: Vec<Bounded> = roots.iter().map(|&root| (root, None, None)).collect();
+  while let Some((index, lower, upper)) = stack.pop() {
+    if !referenced.insert(index) {
+      return Err(corruption(index, "referenced twice"));
+    }
+    let entry = read(index).map_err(|err| corruption(index, err))?;
+    let keys: Vec<&Vec<u8>> = match &entry {
+      CursorEntry::Internal(node) => node.keys.iter().collect(),
+      CursorEntry::Leaf(node) => node.keys.iter().map(|(k, _)| k).collect(),
+    };
+    if keys.windows(2).any(|w| cmp.ge(w[0], w[1])) {
+      return Err(corruption(index, "keys are not ascending"));
+    }
+    let out_of_bounds = keys.iter().any(|k| {
+      lower.as_ref().map(|l| cmp.lt(k, l)).unwrap_or(false)
+        || upper.as_ref().map(|u| cmp.ge(k, u)).unwrap_or(false)
+    });
+    if out_of_bounds {
+      return Err(corruption(index, "keys are out of the parent bounds"));
+    }
+
+    match entry {
+      CursorEntry::Internal(node) => {
+        if node.children.len().ne(&node.keys.len().saturating_add(1)) {
+          return Err(corruption(index, "children do not match keys"));
+        }
+        for (i, &child) in node.children.iter().enumerate() {
+          let l = match i {
+            0 => lower.clone(),
+            _ => Some(node.keys[i - 1].clone()),
+          };
+          let u = node.keys.get(i).cloned().or(upper.clone());
+          stack.push((child, l, u));
+        }
+      }
+      CursorEntry::Leaf(node) => {
+        for (_, data) in node.keys {
+          if !referenced.insert(data) {
+            return Err(corruption(data, "referenced twice"));
+          }
+        }
+      }
+    }
+  }
+  Ok(referenced)
+}
+
+/// Checks the structure of every keyspace tree read by the writer and returns
+/// the indexes of the pages in use, including the header.
+pub(super) fn verify_pages(writer: &CursorWriter) -> Result<BTreeSet<usize>> {
+  let header: TreeHeader = writer
+    .get(HEADER_INDEX)
+    .map_err(|err| corruption(HEADER_INDEX, err))?;
+  let mut referenced = verify_tree(
+    |i| writer.get(i),
+    &header.get_roots(),
+    header.get_comparator(),
+  )?;
+  if !referenced.insert(HEADER_INDEX) {
+    return Err(corruption(HEADER_INDEX, "referenced by the tree"));
+  }
+  Ok(referenced)
+}
+
+pub fn corruption<T: ToString>(index: usize, reason: T) -> Error {
+  Error::Corruption(format!("page {index} {}", reason.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::BTreeMap;
+
+  use crate::{
+    cursor::{InternalNode, KeyComparator, LeafNode},
+    Error,
+  };
+
+  use super::{verify_tree, CursorEntry};
+
+  fn leaf(keys: &[(&[u8], usize)]) -> CursorEntry {
+    CursorEntry::Leaf(LeafNode {
+      keys: keys.iter().map(|(k, i)| (k.to_vec(), *i)).collect(),
+      prev: None,
+      next: None,
+    })
+  }
+
+  fn internal(keys: &[&[u8]], children: &[usize]) -> CursorEntry {
+    CursorEntry::Internal(InternalNode {
+      keys: keys.iter().map(|k| k.to_vec()).collect(),
+      children: children.to_vec(),
+    })
+  }
+
+  fn verify(pages: BTreeMap<usize, CursorEntry>) -> crate::Result<usize> {
+    verify_with(pages, KeyComparator::Lexicographic)
+  }
+
+  fn verify_with(
+    pages: BTreeMap<usize, CursorEntry>,
+    cmp: KeyComparator,
+  ) -> crate::Result<usize> {
+    verify_tree(|i| pages.get(&i).cloned().ok_or(Error::NotFound), &[1], cmp)
+      .map(|referenced| referenced.len())
+  }
+
+  #[test]
+  fn _1() {
+    let pages = BTreeMap::from([
+      (1, internal(&[b"m"], &[2, 3])),
+      (2, leaf(&[(b"a", 10), (b"b", 11)])),
+      (3, leaf(&[(b"m", 12), (b"z", 13)])),
+    ]);
+    assert_eq!(verify(pages.clone()).unwrap(), 7);
+    assert!(matches!(
+      verify_with(pages, KeyComparator::ReverseLexicographic),
+      Err(Error::Corruption(_))
+    ));
+  }
+
+  #[test]
+  fn _2() {
+    let unsorted = BTreeMap::from([
+      (1, internal(&[b"m"], &[2, 3])),
+      (2, leaf(&[(b"b", 10), (b"a", 11)])),
+      (3, leaf(&[(b"m", 12)])),
+    ]);
+    assert!(matches!(verify(unsorted), Err(Error::Corruption(_))));
+
+    let out_of_bounds = BTreeMap::from([
+      (1, internal(&[b"m"], &[2, 3])),
+      (2, leaf(&[(b"a", 10), (b"n", 11)])),
+      (3, leaf(&[(b"m", 12)])),
+    ]);
+    assert!(matches!(verify(out_of_bounds), Err(Error::Corruption(_))));
+
+    let dangling = BTreeMap::from([(1, internal(&[b"m"], &[2, 3])), (2, leaf(&[]))]);
+    assert!(matches!(verify(dangling), Err(Error::Corruption(_))));
+
+    let shared = BTreeMap::from([
+      (1, internal(&[b"m"], &[2, 3])),
+      (2, leaf(&[(b"a", 10)])),
+      (3, leaf(&[(b"m", 10)])),
+    ]);
+    assert!(matches!(verify(shared), Err(Error::Corruption(_))));
+  }
+}
@@ -1,14 +1,20 @@
-use std::sync::Arc;
+use std::sync::{
+  atomic::{AtomicUsize, Ordering},
+  Arc, Mutex,
+};
 
 use crate::{
-  buffer::BufferPool, wal::WriteAheadLog, Error, Result, Serializable, PAGE_SIZE,
+  buffer::BufferPool,
+  wal::{CommitAck, LogMode, LogicalLog, WriteAheadLog},
+  Error, Page, Result, Serializable, ShortenedMutex, PAGE_SIZE,
 };
 
 pub struct CursorWriter {
   tx_id: usize,
-  last_commit_index: usize,
+  last_commit_index: AtomicUsize,
   wal: Arc<WriteAheadLog>,
   buffer: Arc<BufferPool>,
+  released: Mutex<Vec<usize>>,
 }
 impl CursorWriter {
   pub fn new(
@@ -19,12 +25,52 @@ impl CursorWriter {
   ) -> Self {
     Self {
       tx_id,
-      last_commit_index,
+      last_commit_index: AtomicUsize::new(last_commit_index),
       wal,
       buffer,
+      released: Default::default(),
     }
   }
 
+  /// Returns a writer reading the same snapshot with nothing released.
+  pub fn snapshot(&self) -> Self {
+    Self::new(
+      self.tx_id,
+      self.get_snapshot(),
+      self.wal.clone(),
+      self.buffer.clone(),
+    )
+  }
+
+  /// Returns a reader of the latest committed tree,
+  /// which sees none of the pending writes of this transaction.
+  pub fn committed(&self) -> Self {
+    Self::new(
+      usize::MAX,
+      usize::MAX,
+      self.wal.clone(),
+      self.buffer.clone(),
+    )
+  }
+
+  pub fn get_snapshot(&self) -> usize {
+    self.last_commit_index.load(Ordering::SeqCst)
+  }
+
+  /// Reads the tree as of the commit index instead of the latest one.
+  pub fn set_snapshot(&mut self, commit_index: usize) {
+    *self.last_commit_index.get_mut() = commit_index
+  }
+
+  /// Moves the snapshot to the latest commit and returns true if it moved.
+  pub fn refresh_snapshot(&self) -> bool {
+    let latest = self.wal.last_index();
+    self
+      .last_commit_index
+      .swap(latest, Ordering::SeqCst)
+      .ne(&latest)
+  }
+
   pub fn get_id(&self) -> usize {
     self.tx_id
   }
@@ -33,20 +79,98 @@ impl CursorWriter {
   where
     T: Serializable<Error, PAGE_SIZE>,
   {
-    let page = self.buffer.get(self.last_commit_index, index)?;
+    let page = self.buffer.get(self.tx_id, self.get_snapshot(), index)?;
     page.deserialize()
   }
 
+  /// Returns the page shared with the buffer pool without copying it.
+  pub fn get_ref(&self, index: usize) -> Result<Arc<Page>> {
+    self.buffer.get_ref(self.tx_id, self.get_snapshot(), index)
+  }
+
+  /// Returns the stored size of the page without deserializing it.
+  pub fn get_len(&self, index: usize) -> Result<usize> {
+    self.buffer.get_len(self.tx_id, self.get_snapshot(), index)
+  }
+
+  /// Returns the other transaction with an uncommitted write to the page.
+  pub fn get_holder(&self, index: usize) -> Result<Option<usize>> {
+    self.buffer.get_holder(self.tx_id, index)
+  }
+
+  pub fn get_with_version<T>(&self, index: usize) -> Result<(usize, T)>
+  where
+    T: Serializable<Error, PAGE_SIZE>,
+  {
+    let (version, page) =
+      self
+        .buffer
+        .get_with_version(self.tx_id, self.get_snapshot(), index)?;
+    Ok((version, page.deserialize()?))
+  }
+
+  /// Fails with `Error::WriteConflict` if another transaction has written
+  /// the page and is still pending, or committed it after the snapshot.
   pub fn insert<T>(&self, index: usize, value: T) -> Result
   where
     T: Serializable<Error, PAGE_SIZE>,
   {
+    self.wal.check_writable()?;
     let page = value.serialize()?;
-    self.buffer.insert(self.tx_id, index, page.copy())?;
+    self
+      .buffer
+      .insert_checked(self.tx_id, self.get_snapshot(), index, page.copy())?;
     self.wal.append(self.tx_id, index, page)
   }
 
+  /// Records the change of the key for logical logging.
+  /// The value is none for a delete, and copied only in logical mode.
+  pub fn log_change(&self, keyspace: &[u8], key: &[u8], value: Option<&Page>) -> Result {
+    if self.wal.log_mode().eq(&LogMode::Physical) {
+      return Ok(());
+    }
+    self.wal.append_logical(
+      self.tx_id,
+      LogicalLog {
+        keyspace: keyspace.to_vec(),
+        key: key.to_vec(),
+        value: value.map(Page::copy),
+      },
+    )
+  }
+
+  /// Released pages are handed to the free list together at commit.
+  pub fn release(&self, index: usize) {
+    self.released.l().push(index);
+  }
+
+  pub fn take_released(&self) -> Vec<usize> {
+    std::mem::take(&mut self.released.l())
+  }
+
+  /// A transaction which wrote nothing ends without a commit record while
+  /// the wal writes are suspended, so reads keep working on a full disk.
   pub fn commit(&self) -> Result {
+    if self.wal.is_disk_full() && !self.buffer.is_pending(self.tx_id) {
+      self.wal.discard(self.tx_id);
+      return Ok(());
+    }
     self.wal.commit(self.tx_id)
   }
+
+  pub fn commit_with_ack(&self, ack: CommitAck) -> Result {
+    if self.wal.is_disk_full() && !self.buffer.is_pending(self.tx_id) {
+      self.wal.discard_with_ack(self.tx_id, ack);
+      return Ok(());
+    }
+    self.wal.commit_with_ack(self.tx_id, ack)
+  }
+
+  /// Rolls back the pages written by the transaction and returns the pages
+  /// allocated by it. Pages released by it are still referenced and kept.
+  pub fn abort(&self) -> Result<Vec<usize>> {
+    self.released.l().clear();
+    self.wal.abort(self.tx_id)?;
+    self.buffer.abort(self.tx_id)
+  }
 }
@@ -0,0 +1,115 @@
+use std::sync::{
+  atomic::{AtomicUsize, Ordering},
+  Arc, Condvar, Mutex,
+};
+
+use crate::ShortenedMutex;
+
+/// Limit on the bytes of page buffers in flight across every disk sharing it,
+/// so a burst of io in one file waits instead of growing the total memory.
+#[derive(Debug)]
+pub struct IoBudget {
+  limit: usize,
+  used: Mutex<usize>,
+  released: Condvar,
+  peak: AtomicUsize,
+}
+impl IoBudget {
+  pub fn new(limit: usize) -> Self {
+    Self {
+      limit,
+      used: Mutex::new(0),
+      released: Condvar::new(),
+      peak: AtomicUsize::new(0),
+    }
+  }
+
+  pub fn limit(&self) -> usize {
+    self.limit
+  }
+
+  /// Returns the highest number of bytes in flight at once.
+  pub fn peak(&self) -> usize {
+    self.peak.load(Ordering::Relaxed)
+  }
+
+  /// Waits until the bytes fit in the budget.
+  /// A page larger than the whole budget is let through alone.
+  pub fn acquire(self: &Arc<Self>, bytes: usize) -> IoPermit {
+    let mut used = self.used.l();
+    while used.gt(&0) && used.saturating_add(bytes).gt(&self.limit) {
+      used = self.released.wait(used).unwrap();
+    }
+    *used += bytes;
+    self.peak.fetch_max(*used, Ordering::Relaxed);
+    IoPermit {
+      budget: self.clone(),
+      bytes,
+    }
+  }
+}
+
+/// Bytes taken from the budget, given back when dropped.
+pub struct IoPermit {
+  budget: Arc<IoBudget>,
+  bytes: usize,
+}
+impl Drop for IoPermit {
+  fn drop(&mut self) {
+    *self.budget.used.l() -= self.bytes;
+    self.budget.released.notify_all();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{sync::Arc, time::Duration};
+
+  use crate::{
+    buffer::BLOCK_SIZE,
+    disk::{Finder, FinderConfig},
+    wal::WAL_PAGE_SIZE,
+    Page,
+  };
+
+  use super::IoBudget;
+
+  #[test]
+  fn _1() {
+    let dir = std::env::temp_dir().join(format!("lfkv-io-budget-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let budget = Arc::new(IoBudget::new(WAL_PAGE_SIZE + BLOCK_SIZE * 2));
+    let config = |name: &str| FinderConfig {
+      path: dir.join(name),
+      batch_delay: Duration::from_millis(5),
+      batch_size: 100,
+      file_mode: None,
+      sync_mode: Default::default(),
+      io_budget: Some(budget.clone()),
+      in_memory: false,
+    };
+    let data = Finder::<BLOCK_SIZE>::open(config("data.db")).unwrap();
+    let wal = Finder::<WAL_PAGE_SIZE>::open(config("wal.db")).unwrap();
+
+    std::thread::scope(|scope| {
+      for t in 0..4 {
+        let (data, wal) = (&data, &wal);
+        scope.spawn(move || {
+          for i in 0..20 {
+            let index = t * 20 + i;
+            data.batch_write(index, Page::new()).unwrap();
+            data.read(index).unwrap();
+            wal.write(index, Page::new()).unwrap();
+            wal.read(index).unwrap();
+          }
+        });
+      }
+    });
+    assert!(budget.peak().gt(&0));
+    assert!(budget.peak().le(&budget.limit()));
+
+    data.close();
+    wal.close();
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+}
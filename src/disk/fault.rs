@@ -0,0 +1,119 @@
+use std::{
+  io::{self, Write},
+  sync::Mutex,
+  time::Duration,
+};
+
+use crate::ShortenedMutex;
+
+/// Injected failure of the disk io, only for durability tests.
+#[derive(Debug, Clone)]
+pub enum FaultSpec {
+  /// The next n reads fail.
+  FailReads(usize),
+  /// The next n writes fail without writing anything.
+  FailWrites(usize),
+  /// The next write only writes the first n bytes and fails.
+  TornWrite(usize),
+  /// The next fsync fails.
+  FailFsync,
+  /// The next n reads or writes fail as interrupted by a signal.
+  Interrupted(usize),
+  /// Every read and write is delayed until the fault is replaced.
+  SlowIo(Duration),
+  /// Every write fails with a full disk until the fault is replaced.
+  DiskFull,
+}
+
+#[derive(Default)]
+pub struct FaultState(Mutex<Option<FaultSpec>>);
+impl FaultState {
+  pub fn set(&self, spec: FaultSpec) {
+    *self.0.l() = Some(spec);
+  }
+
+  /// Returns the error of the read if a read fault is set.
+  pub fn on_read(&self) -> Option<io::Result<()>> {
+    let mut state = self.0.l();
+    match state.take() {
+      Some(FaultSpec::FailReads(n)) => {
+        if n.gt(&1) {
+          *state = Some(FaultSpec::FailReads(n - 1));
+        }
+        Some(Err(injected()))
+      }
+      Some(FaultSpec::Interrupted(n)) => {
+        if n.gt(&1) {
+          *state = Some(FaultSpec::Interrupted(n - 1));
+        }
+        Some(Err(io::ErrorKind::Interrupted.into()))
+      }
+      Some(FaultSpec::SlowIo(delay)) => {
+        *state = Some(FaultSpec::SlowIo(delay));
+        drop(state);
+        std::thread::sleep(delay);
+        None
+      }
+      spec => {
+        *state = spec;
+        None
+      }
+    }
+  }
+
+  /// Returns the error of the write if a write fault is set.
+  /// A torn write writes the prefix of the bytes before failing.
+  pub fn on_write<W: Write>(&self, file: &mut W, bytes: &[u8]) -> Option<io::Result<()>> {
+    let mut state = self.0.l();
+    match state.take() {
+      Some(FaultSpec::FailWrites(n)) => {
+        if n.gt(&1) {
+          *state = Some(FaultSpec::FailWrites(n - 1));
+        }
+        Some(Err(injected()))
+      }
+      Some(FaultSpec::Interrupted(n)) => {
+        if n.gt(&1) {
+          *state = Some(FaultSpec::Interrupted(n - 1));
+        }
+        Some(Err(io::ErrorKind::Interrupted.into()))
+      }
+      Some(FaultSpec::DiskFull) => {
+        *state = Some(FaultSpec::DiskFull);
+        Some(Err(io::ErrorKind::StorageFull.into()))
+      }
+      Some(FaultSpec::TornWrite(n)) => {
+        let n = n.min(bytes.len());
+        if let Err(err) = file.write_all(&bytes[..n]) {
+          return Some(Err(err));
+        }
+        Some(Err(injected()))
+      }
+      Some(FaultSpec::SlowIo(delay)) => {
+        *state = Some(FaultSpec::SlowIo(delay));
+        drop(state);
+        std::thread::sleep(delay);
+        None
+      }
+      spec => {
+        *state = spec;
+        None
+      }
+    }
+  }
+
+  pub fn on_fsync(&self) -> Option<io::Result<()>> {
+    let mut state = self.0.l();
+    match state.take() {
+      Some(FaultSpec::FailFsync) => Some(Err(injected())),
+      spec => {
+        *state = spec;
+        None
+      }
+    }
+  }
+}
+
+fn injected() -> io::Error {
+  io::Error::other("injected fault")
+}
@@ -1,35 +1,138 @@
 use std::{
-  fs::{File, Metadata, OpenOptions},
+  fs::{File, OpenOptions},
   io::{self, Read, Seek, SeekFrom, Write},
   ops::Mul,
   path::PathBuf,
   sync::Arc,
-  time::Duration,
+  time::{Duration, Instant},
 };
 
+use crossbeam::channel::Receiver;
+
 use crate::{
-  BackgroundThread, BackgroundWork, Error, Page, Result, Serializable, UnwrappedSender,
+  logger, BackgroundThread, BackgroundWork, DiskOp, Error, Page, Result, Serializable,
 };
 
+use super::{DiskLatency, DiskStats, IoBudget, IoPermit, MemFile};
+#[cfg(any(test, feature = "fault-injection"))]
+use super::{FaultSpec, FaultState};
+
+/// How the file is synced to the disk on flush.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SyncMode {
+  /// `fdatasync`, which skips metadata not needed to read the data back.
+  #[default]
+  Data,
+  /// `fsync`, which flushes all metadata too.
+  All,
+}
+impl SyncMode {
+  fn sync<F: FileLike>(&self, file: &F) -> io::Result<()> {
+    match self {
+      SyncMode::Data => file.sync_data(),
+      SyncMode::All => file.sync_all(),
+    }
+  }
+}
+
+/// Storage under a finder, a file on the disk or a buffer in memory.
+pub trait FileLike: Read + Write + Seek + Send + 'static {
+  fn sync_data(&self) -> io::Result<()>;
+  fn sync_all(&self) -> io::Result<()>;
+  fn len(&self) -> io::Result<u64>;
+  /// Deallocates the range without changing the size,
+  /// so the range reads back as zeros.
+  fn punch_hole(&mut self, offset: usize, len: usize) -> io::Result<()>;
+}
+impl FileLike for File {
+  fn sync_data(&self) -> io::Result<()> {
+    File::sync_data(self)
+  }
+  fn sync_all(&self) -> io::Result<()> {
+    File::sync_all(self)
+  }
+  fn len(&self) -> io::Result<u64> {
+    self.metadata().map(|m| m.len())
+  }
+  fn punch_hole(&mut self, offset: usize, len: usize) -> io::Result<()> {
+    punch_hole(self, offset, len)
+  }
+}
+
 enum Command<const N: usize> {
   Read(usize),
   Write(usize, Page<N>),
-  Flush,
-  Metadata,
+  Flush(SyncMode),
+  Len,
+  PunchHole(usize),
 }
 impl<const N: usize> Command<N> {
-  fn exec(&self, file: &mut File) -> Result<(Option<Page<N>>, Option<Metadata>)>
+  /// Runs the command picked up from the queue at the time it was sent,
+  /// recording the wait in the queue and the duration of reads and writes.
+  fn timed<F>(self, sent: Instant, latency: &DiskLatency, exec: F) -> Executed<N>
   where
-    File: IndexedFile<N>,
+    F: FnOnce(&Self) -> Executed<N>,
   {
+    let start = Instant::now();
+    latency.queue_wait.record(start.duration_since(sent));
+    let result = exec(&self);
+    if let Command::Read(_) | Command::Write(..) = self {
+      latency.io.record(start.elapsed());
+    }
+    result
+  }
+
+  /// Runs the command again while it fails with a transient io error,
+  /// up to a few times, and returns any other error at once.
+  /// Every command seeks to its page first, so running it again is safe.
+  fn retry_transient<F>(&self, mut exec: F) -> Executed<N>
+  where
+    F: FnMut(&Self) -> Executed<N>,
+  {
+    let mut retries = 0;
+    loop {
+      match exec(self) {
+        Err(err) if err.is_transient_io() && retries.lt(&MAX_TRANSIENT_RETRIES) => {
+          retries += 1;
+          logger::warn(format!("retrying io after transient error: {err}"));
+        }
+        result => return result,
+      }
+    }
+  }
+
+  #[cfg(any(test, feature = "fault-injection"))]
+  fn exec_with<F: FileLike>(&self, file: &mut F, fault: &FaultState) -> Executed<N> {
+    let injected = match self {
+      Command::Read(index) => fault
+        .on_read()
+        .map(|r| r.map_err(Error::disk(DiskOp::Read, *index))),
+      Command::Write(index, page) => {
+        IndexedFile::<N>::seek_index(file, *index)
+          .map_err(Error::disk(DiskOp::Write, *index))?;
+        fault
+          .on_write(file, page.as_ref())
+          .map(|r| r.map_err(Error::disk(DiskOp::Write, *index)))
+      }
+      Command::Flush(_) => fault.on_fsync().map(|r| r.map_err(Error::IO)),
+      _ => None,
+    };
+    match injected {
+      Some(r) => r.map(|_| (None, None)),
+      None => self.exec(file),
+    }
+  }
+
+  fn exec<F: FileLike>(&self, file: &mut F) -> Executed<N> {
     match self {
       Command::Read(index) => {
-        file.seek_index(*index)?;
+        IndexedFile::<N>::seek_index(file, *index)
+          .map_err(Error::disk(DiskOp::Read, *index))?;
         let mut page = Page::new_empty();
         if let Err(err) = file.read_exact(page.as_mut()) {
           match err.kind() {
             io::ErrorKind::UnexpectedEof => return Err(Error::NotFound),
-            _ => return Err(Error::IO(err)),
+            _ => return Err(Error::disk(DiskOp::Read, *index)(err)),
           }
         };
         if page.is_empty() {
@@ -39,47 +142,107 @@ impl<const N: usize> Command<N> {
         Ok((Some(page), None))
       }
       Command::Write(index, page) => {
-        file.seek_index(*index)?;
-        file.write_all(page.as_ref()).map_err(Error::IO)?;
+        IndexedFile::<N>::seek_index(file, *index)
+          .map_err(Error::disk(DiskOp::Write, *index))?;
+        file
+          .write_all(page.as_ref())
+          .map_err(Error::disk(DiskOp::Write, *index))?;
         Ok((None, None))
       }
-      Command::Flush => file.sync_all().map(|_| (None, None)).map_err(Error::IO),
-      Command::Metadata => file.metadata().map(|m| (None, Some(m))).map_err(Error::IO),
+      Command::Flush(mode) => mode.sync(file).map(|_| (None, None)).map_err(Error::IO),
+      Command::Len => file.len().map(|len| (None, Some(len))).map_err(Error::IO),
+      Command::PunchHole(index) => file
+        .punch_hole(index.mul(N), N)
+        .map(|_| (None, None))
+        .map_err(Error::disk(DiskOp::Write, *index)),
     }
   }
 }
 
+/// Deallocates the range of the file without changing its size,
+/// so the range reads back as zeros.
+#[cfg(target_os = "linux")]
+fn punch_hole(file: &File, offset: usize, len: usize) -> io::Result<()> {
+  use std::os::fd::AsRawFd;
+
+  const FALLOC_FL_KEEP_SIZE: i32 = 0x01;
+  const FALLOC_FL_PUNCH_HOLE: i32 = 0x02;
+  extern "C" {
+    fn fallocate(fd: i32, mode: i32, offset: i64, len: i64) -> i32;
+  }
+
+  let mode = FALLOC_FL_PUNCH_HOLE | FALLOC_FL_KEEP_SIZE;
+  match unsafe { fallocate(file.as_raw_fd(), mode, offset as i64, len as i64) } {
+    0 => Ok(()),
+    _ => Err(io::Error::last_os_error()),
+  }
+}
+#[cfg(not(target_os = "linux"))]
+fn punch_hole(_: &File, _: usize, _: usize) -> io::Result<()> {
+  Ok(())
+}
+
 pub struct FinderConfig {
   pub path: PathBuf,
   pub batch_delay: Duration,
   pub batch_size: usize,
+  pub file_mode: Option<u32>,
+  pub sync_mode: SyncMode,
+  /// Shared limit on the page buffers in flight, unbounded if none.
+  pub io_budget: Option<Arc<IoBudget>>,
+  /// Keeps the pages in memory instead of the file of the path.
+  pub in_memory: bool,
 }
 
+/// Permission bits for newly created files when no mode is configured.
+pub const DEFAULT_FILE_MODE: u32 = 0o600;
+
 trait IndexedFile<const N: usize> {
-  fn seek_index(&mut self, i: usize) -> Result<usize>;
+  fn seek_index(&mut self, i: usize) -> io::Result<usize>;
 }
-impl<const N: usize> IndexedFile<N> for File {
-  fn seek_index(&mut self, i: usize) -> Result<usize> {
+impl<const N: usize, F: Seek> IndexedFile<N> for F {
+  fn seek_index(&mut self, i: usize) -> io::Result<usize> {
     self
       .seek(SeekFrom::Start(i.mul(N) as u64))
-      .map_err(Error::IO)
       .map(|c| c as usize)
   }
 }
 
+/// Times a command is run again after a transient io error before it fails.
+const MAX_TRANSIENT_RETRIES: usize = 3;
+
+/// Page read or length of the file returned by a command.
+type Executed<const N: usize> = Result<(Option<Page<N>>, Option<u64>)>;
+
 pub struct Finder<const N: usize> {
-  io_c: Arc<BackgroundThread<Command<N>, Result<(Option<Page<N>>, Option<Metadata>)>>>,
+  io_c: Arc<BackgroundThread<(Instant, Command<N>), Executed<N>>>,
   batch_c: BackgroundThread<(usize, Page<N>), Result>,
+  sync_mode: SyncMode,
+  latency: Arc<DiskLatency>,
+  budget: Option<Arc<IoBudget>>,
+  #[cfg(any(test, feature = "fault-injection"))]
+  fault: Arc<FaultState>,
 }
 impl<const N: usize> Finder<N> {
+  /// Opens the file of the path, or an empty buffer in memory
+  /// if the config is in memory.
   pub fn open(config: FinderConfig) -> Result<Self> {
-    let mut file = OpenOptions::new()
-      .create(true)
-      .read(true)
-      .write(true)
-      .open(&config.path)
-      .map_err(Error::IO)?;
+    if config.in_memory {
+      return Ok(Self::start(MemFile::default(), config));
+    }
 
+    let mut options = OpenOptions::new();
+    options.create(true).read(true).write(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(
+      &mut options,
+      config.file_mode.unwrap_or(DEFAULT_FILE_MODE),
+    );
+    let file = options.open(&config.path).map_err(Error::IO)?;
+    Ok(Self::start(file, config))
+  }
+
+  fn start<F: FileLike>(mut file: F, config: FinderConfig) -> Self {
     let file_name = config
       .path
       .file_name()
@@ -88,13 +251,33 @@ impl<const N: usize> Finder<N> {
       .to_string();
 
     let io_name = format!("{} finder io", file_name);
-    let io_c = Arc::new(BackgroundThread::new(
-      &io_name,
-      N.mul(1000),
-      BackgroundWork::no_timeout(move |cmd: Command<N>| cmd.exec(&mut file)),
-    ));
+    let latency = Arc::new(DiskLatency::default());
+    #[cfg(any(test, feature = "fault-injection"))]
+    let fault = Arc::new(FaultState::default());
+    #[cfg(any(test, feature = "fault-injection"))]
+    let work = {
+      let fault = fault.clone();
+      let latency = latency.clone();
+      BackgroundWork::no_timeout(move |(sent, cmd): (Instant, Command<N>)| {
+        cmd.timed(sent, &latency, |cmd| {
+          cmd.retry_transient(|cmd| cmd.exec_with(&mut file, &fault))
+        })
+      })
+    };
+    #[cfg(not(any(test, feature = "fault-injection")))]
+    let work = {
+      let latency = latency.clone();
+      BackgroundWork::no_timeout(move |(sent, cmd): (Instant, Command<N>)| {
+        cmd.timed(sent, &latency, |cmd| {
+          cmd.retry_transient(|cmd| cmd.exec(&mut file))
+        })
+      })
+    };
+    let io_c = Arc::new(BackgroundThread::new(&io_name, N.mul(1000), work));
 
     let cloned_c = io_c.clone();
+    let cloned_latency = latency.clone();
+    let sync_mode = config.sync_mode;
     let mut wait = vec![];
 
     let batch_name = format!("{} finder batch", file_name);
@@ -103,8 +286,10 @@ impl<const N: usize> Finder<N> {
       N.mul(2).mul(config.batch_size),
       BackgroundWork::with_timer(config.batch_delay, move |v| {
         if let Some(((index, page), done)) = v {
-          if let Err(err) = cloned_c.send_await(Command::Write(index, page)) {
-            done.must_send(Err(err));
+          if let Err(err) =
+            cloned_c.send_await((Instant::now(), Command::Write(index, page)))
+          {
+            done.send(Err(err)).ok();
             return false;
           }
 
@@ -114,43 +299,99 @@ impl<const N: usize> Finder<N> {
           }
         }
 
-        if let Err(_) = cloned_c.send_await(Command::Flush) {
+        if let Err(_) = cloned_c.send_await((Instant::now(), Command::Flush(sync_mode))) {
           return false;
         }
 
-        wait.drain(..).for_each(|done| done.must_send(Ok(())));
+        if !wait.is_empty() {
+          cloned_latency.record_fsync(wait.len());
+        }
+        // a queued write is not waited for if its writer failed on another page.
+        wait.drain(..).for_each(|done| {
+          done.send(Ok(())).ok();
+        });
         true
       }),
     );
 
-    Ok(Self { io_c, batch_c })
+    Self {
+      io_c,
+      batch_c,
+      sync_mode,
+      latency,
+      budget: config.io_budget,
+      #[cfg(any(test, feature = "fault-injection"))]
+      fault,
+    }
+  }
+
+  #[cfg(any(test, feature = "fault-injection"))]
+  pub fn set_fault(&self, spec: FaultSpec) {
+    self.fault.set(spec);
+  }
+
+  pub fn stats(&self) -> DiskStats {
+    self.latency.snapshot()
+  }
+
+  fn exec(&self, cmd: Command<N>) -> Executed<N> {
+    self.io_c.send_await((Instant::now(), cmd))
+  }
+
+  /// Takes a page from the shared budget for a read or write in flight.
+  fn reserve(&self) -> Option<IoPermit> {
+    self.budget.as_ref().map(|budget| budget.acquire(N))
   }
 }
 impl<const N: usize> Finder<N> {
   pub fn read(&self, index: usize) -> Result<Page<N>> {
-    let r = self.io_c.send_await(Command::Read(index))?;
+    let _permit = self.reserve();
+    let r = self.exec(Command::Read(index))?;
     Ok(r.0.unwrap())
   }
 
   pub fn write(&self, index: usize, page: Page<N>) -> Result {
-    self.io_c.send_await(Command::Write(index, page))?;
+    let _permit = self.reserve();
+    self.exec(Command::Write(index, page))?;
     Ok(())
   }
 
   pub fn fsync(&self) -> Result {
-    self.io_c.send_await(Command::Flush)?;
+    self.exec(Command::Flush(self.sync_mode))?;
     Ok(())
   }
 
+  /// Writes and fsyncs the page on the io thread without waiting
+  /// for the batch delay, for latency critical pages like metadata.
+  pub fn write_sync(&self, index: usize, page: Page<N>) -> Result {
+    self.write(index, page)?;
+    self.fsync()
+  }
+
   pub fn batch_write(&self, index: usize, page: Page<N>) -> Result {
+    let _permit = self.reserve();
     self.batch_c.send_await((index, page))
   }
 
+  /// Queues the page for the next batch without waiting for it,
+  /// and returns the receiver of the result once the batch is fsynced.
+  pub fn batch_write_queued(&self, index: usize, page: Page<N>) -> Receiver<Result> {
+    self.batch_c.send((index, page))
+  }
+
+  /// Frees the disk blocks of the page, which then reads as not found.
+  /// A no-op on platforms other than linux.
+  pub fn punch_hole(&self, index: usize) -> Result {
+    self.exec(Command::PunchHole(index))?;
+    Ok(())
+  }
+
   pub fn len(&self) -> Result<usize> {
-    let r = self.io_c.send_await(Command::Metadata)?;
-    Ok((r.1.unwrap().len() as usize).div_ceil(N))
+    let r = self.exec(Command::Len)?;
+    Ok((r.1.unwrap() as usize).div_ceil(N))
   }
 
+  /// Pending batched writes are flushed and fsynced before the batch thread exits.
   pub fn close(&self) {
     self.batch_c.close();
     self.io_c.close();
@@ -181,3 +422,271 @@ impl<const N: usize> Finder<N> {
     self.batch_write(index, page)
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use std::{
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::PathBuf,
+    sync::{
+      atomic::{AtomicUsize, Ordering},
+      Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+  };
+
+  use crate::{
+    disk::FaultSpec,
+    wal::{LogEntry, LogRecord, WAL_PAGE_SIZE},
+    DiskOp, Error, Page,
+  };
+
+  use super::{FileLike, Finder, FinderConfig, MemFile, SyncMode, MAX_TRANSIENT_RETRIES};
+
+  fn config(path: PathBuf) -> FinderConfig {
+    FinderConfig {
+      path,
+      batch_delay: Duration::from_millis(10),
+      batch_size: 100,
+      file_mode: None,
+      sync_mode: Default::default(),
+      io_budget: None,
+      in_memory: false,
+    }
+  }
+
+  #[test]
+  fn _1() {
+    let path = std::env::temp_dir().join(format!("lfkv-finder-{}", std::process::id()));
+    let slow = || FinderConfig {
+      batch_delay: Duration::from_secs(10),
+      ..config(path.clone())
+    };
+
+    let finder = Arc::new(Finder::<64>::open(slow()).unwrap());
+    let mut page = Page::<64>::new();
+    page.writer().write(&[1, 2, 3]).unwrap();
+
+    let cloned = finder.clone();
+    let written = page.copy();
+    let t = thread::spawn(move || cloned.batch_write(0, written));
+    thread::sleep(Duration::from_millis(100));
+    finder.close();
+    t.join().unwrap().unwrap();
+
+    let finder = Finder::<64>::open(slow()).unwrap();
+    assert_eq!(finder.read(0).unwrap(), page);
+    finder.close();
+    std::fs::remove_file(&path).unwrap();
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn _2() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path =
+      std::env::temp_dir().join(format!("lfkv-finder-mode-{}", std::process::id()));
+    let finder = Finder::<64>::open(FinderConfig {
+      batch_size: 10,
+      file_mode: Some(0o600),
+      ..config(path.clone())
+    })
+    .unwrap();
+    finder.close();
+
+    let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+    std::fs::remove_file(&path).unwrap();
+  }
+  #[test]
+  fn _3() {
+    let path =
+      std::env::temp_dir().join(format!("lfkv-finder-torn-{}", std::process::id()));
+    let mut entry = LogEntry::new();
+    for tx_id in 1..4 {
+      let mut record = LogRecord::new_start(tx_id);
+      record.assign_id(tx_id);
+      entry.append(record);
+    }
+
+    let finder = Finder::<WAL_PAGE_SIZE>::open(config(path.clone())).unwrap();
+    finder.write_from(0, &entry).unwrap();
+    finder.set_fault(FaultSpec::TornWrite(5));
+    assert!(finder.write_from(1, &entry).is_err());
+    finder.set_fault(FaultSpec::FailFsync);
+    assert!(finder.fsync().is_err());
+    finder.fsync().unwrap();
+    finder.close();
+
+    let finder = Finder::<WAL_PAGE_SIZE>::open(config(path.clone())).unwrap();
+    let recovered: LogEntry = finder.read_to(0).unwrap();
+    assert_eq!(recovered.records.len(), 3);
+    // the page cut short by the torn write reads as not found.
+    assert!(matches!(
+      finder.read_to::<LogEntry>(1),
+      Err(Error::NotFound)
+    ));
+    finder.close();
+    std::fs::remove_file(&path).unwrap();
+  }
+  #[derive(Default)]
+  struct CountingFile {
+    file: MemFile,
+    data: AtomicUsize,
+    all: AtomicUsize,
+  }
+  impl Read for CountingFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+      self.file.read(buf)
+    }
+  }
+  impl Write for CountingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+      self.file.write(buf)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+      self.file.flush()
+    }
+  }
+  impl Seek for CountingFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+      self.file.seek(pos)
+    }
+  }
+  impl FileLike for CountingFile {
+    fn sync_data(&self) -> io::Result<()> {
+      self.data.fetch_add(1, Ordering::SeqCst);
+      Ok(())
+    }
+    fn sync_all(&self) -> io::Result<()> {
+      self.all.fetch_add(1, Ordering::SeqCst);
+      Ok(())
+    }
+    fn len(&self) -> io::Result<u64> {
+      self.file.len()
+    }
+    fn punch_hole(&mut self, offset: usize, len: usize) -> io::Result<()> {
+      self.file.punch_hole(offset, len)
+    }
+  }
+
+  #[test]
+  fn _4() {
+    let file = CountingFile::default();
+    SyncMode::default().sync(&file).unwrap();
+    SyncMode::Data.sync(&file).unwrap();
+    SyncMode::All.sync(&file).unwrap();
+    assert_eq!(file.data.load(Ordering::SeqCst), 2);
+    assert_eq!(file.all.load(Ordering::SeqCst), 1);
+  }
+  #[test]
+  fn _5() {
+    let path =
+      std::env::temp_dir().join(format!("lfkv-finder-ctx-{}", std::process::id()));
+    let finder = Finder::<64>::open(config(path.clone())).unwrap();
+    finder.write(3, Page::new()).unwrap();
+
+    finder.set_fault(FaultSpec::FailReads(1));
+    match finder.read(3) {
+      Err(Error::Disk { op, index, .. }) => {
+        assert_eq!(op, DiskOp::Read);
+        assert_eq!(index, 3);
+      }
+      r => panic!("unexpected {:?}", r.map(|_| ())),
+    }
+    finder.set_fault(FaultSpec::FailWrites(1));
+    assert!(matches!(
+      finder.write(5, Page::new()),
+      Err(Error::Disk {
+        op: DiskOp::Write,
+        index: 5,
+        ..
+      })
+    ));
+    assert!(finder.read(3).is_ok());
+
+    finder.close();
+    std::fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn _6() {
+    let path =
+      std::env::temp_dir().join(format!("lfkv-finder-latency-{}", std::process::id()));
+    let finder = Finder::<64>::open(config(path.clone())).unwrap();
+
+    finder.set_fault(FaultSpec::SlowIo(Duration::from_millis(20)));
+    for i in 0..5 {
+      finder.write(i, Page::new()).unwrap();
+      finder.read(i).unwrap();
+    }
+    finder.fsync().unwrap();
+
+    let stats = finder.stats();
+    assert_eq!(stats.io.count(), 10);
+    assert!(stats.io.quantile(0.5).ge(&Duration::from_millis(20)));
+    assert_eq!(stats.queue_wait.count(), 11);
+    assert!(stats
+      .queue_wait
+      .quantile(0.5)
+      .lt(&Duration::from_millis(20)));
+
+    finder.close();
+    std::fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn _7() {
+    let path =
+      std::env::temp_dir().join(format!("lfkv-finder-sync-{}", std::process::id()));
+    let finder = Finder::<64>::open(FinderConfig {
+      batch_delay: Duration::from_secs(10),
+      ..config(path.clone())
+    })
+    .unwrap();
+
+    let started = Instant::now();
+    let mut page = Page::<64>::new();
+    page.writer().write(&[1, 2, 3]).unwrap();
+    let expected = page.as_ref().to_vec();
+    finder.write_sync(1, page).unwrap();
+    assert!(started.elapsed().lt(&Duration::from_secs(1)));
+
+    let persisted = std::fs::read(&path).unwrap();
+    assert_eq!(&persisted[64..], &expected[..]);
+    assert_eq!(finder.read(1).unwrap().as_ref(), &expected[..]);
+
+    finder.close();
+    std::fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn _8() {
+    let finder = Finder::<64>::open(FinderConfig {
+      in_memory: true,
+      ..config(PathBuf::from("interrupted"))
+    })
+    .unwrap();
+    let mut page = Page::<64>::new();
+    page.writer().write(&[7]).unwrap();
+    finder.write(1, page).unwrap();
+
+    finder.set_fault(FaultSpec::Interrupted(1));
+    assert_eq!(finder.read(1).unwrap().scanner().read().unwrap(), 7);
+    finder.set_fault(FaultSpec::Interrupted(1));
+    finder.write(2, Page::new()).unwrap();
+    assert!(finder.read(2).is_ok());
+
+    // gives up once the retries are used up.
+    finder.set_fault(FaultSpec::Interrupted(MAX_TRANSIENT_RETRIES + 1));
+    match finder.read(1) {
+      Err(err) => assert!(err.is_transient_io()),
+      Ok(_) => panic!("read should fail"),
+    }
+    assert!(finder.read(1).is_ok());
+
+    finder.close();
+  }
+}
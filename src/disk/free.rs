@@ -8,15 +8,68 @@ use std::{
   time::Duration,
 };
 
-use crate::{BackgroundThread, BackgroundWork, Result, ShortenedMutex};
+use crate::{logger, BackgroundThread, BackgroundWork, Error, Result, ShortenedMutex};
 
 use super::Finder;
 
+/// Which free page `FreeList::acquire` hands out first.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AllocStrategy {
+  /// Fills the holes at the front of the file first,
+  /// which keeps the file compact but scatters pages written together.
+  #[default]
+  LowestIndex,
+  /// Reuses the page freed last, which is likely still in the buffer pool,
+  /// at the cost of leaving holes near the front of the file.
+  MostRecentlyFreed,
+}
+
+/// Free indexes, with the order they were freed in
+/// if the most recently freed one is acquired first.
+#[derive(Default)]
+struct FreePages {
+  indexes: BTreeSet<usize>,
+  freed: Vec<usize>,
+  strategy: AllocStrategy,
+}
+impl FreePages {
+  fn insert(&mut self, i: usize) -> bool {
+    if !self.indexes.insert(i) {
+      return false;
+    }
+    if self.strategy.eq(&AllocStrategy::MostRecentlyFreed) {
+      self.freed.push(i);
+    }
+    true
+  }
+
+  fn pop(&mut self) -> Option<usize> {
+    match self.strategy {
+      AllocStrategy::LowestIndex => self.indexes.pop_first(),
+      AllocStrategy::MostRecentlyFreed => {
+        let i = self.freed.pop()?;
+        self.indexes.remove(&i);
+        Some(i)
+      }
+    }
+  }
+
+  /// Pages already free are handed out lowest index first.
+  fn set_strategy(&mut self, strategy: AllocStrategy) {
+    self.strategy = strategy;
+    self.freed = match strategy {
+      AllocStrategy::LowestIndex => vec![],
+      AllocStrategy::MostRecentlyFreed => self.indexes.iter().rev().copied().collect(),
+    };
+  }
+}
+
 pub struct FreeList<const N: usize> {
-  list: Arc<Mutex<BTreeSet<usize>>>,
+  list: Arc<Mutex<FreePages>>,
   file: Arc<Finder<N>>,
   chan: BackgroundThread<(), Result>,
   last_index: AtomicUsize,
+  punch_holes: bool,
 }
 impl<const N: usize> FreeList<N> {
   pub fn new(interval: Duration, file: Arc<Finder<N>>) -> Result<Self> {
@@ -39,11 +92,34 @@ impl<const N: usize> FreeList<N> {
       file,
       chan,
       last_index: AtomicUsize::new(last_index),
+      punch_holes: false,
     })
   }
 
+  /// Frees the disk blocks of pages returned to the list.
+  /// A page punched out of the file reads as not found,
+  /// so readers of snapshots older than the release can not see it anymore.
+  pub fn punch_holes(mut self, enabled: bool) -> Self {
+    self.punch_holes = enabled;
+    self
+  }
+
+  pub fn strategy(self, strategy: AllocStrategy) -> Self {
+    self.list.l().set_strategy(strategy);
+    self
+  }
+
+  fn punch(&self, i: usize) {
+    if !self.punch_holes {
+      return;
+    }
+    if let Err(err) = self.file.punch_hole(i) {
+      logger::warn(format!("failed to punch hole at page {i} {:?}", err));
+    }
+  }
+
   pub fn acquire(&self) -> usize {
-    if let Some(i) = self.list.l().pop_first() {
+    if let Some(i) = self.list.l().pop() {
       return i;
     }
 
@@ -54,8 +130,76 @@ impl<const N: usize> FreeList<N> {
     self.last_index.store(i, Ordering::SeqCst)
   }
 
+  /// Panics in debug builds if the index is already free.
   pub fn insert(&self, i: usize) {
-    self.list.l().insert(i);
+    let inserted = self.try_insert(i);
+    debug_assert!(inserted, "double free of page {i}");
+  }
+
+  /// Returns false without any change if the index is already free.
+  pub fn try_insert(&self, i: usize) -> bool {
+    if self.list.l().insert(i) {
+      self.punch(i);
+      return true;
+    }
+    logger::error(format!("page {i} is already in the free list"));
+    false
+  }
+
+  pub fn insert_many(&self, indexes: &[usize]) {
+    if indexes.is_empty() {
+      return;
+    }
+    let mut inserted = vec![];
+    {
+      let mut list = self.list.l();
+      for &i in indexes {
+        if !list.insert(i) {
+          logger::error(format!("page {i} is already in the free list"));
+          debug_assert!(false, "double free of page {i}");
+          continue;
+        }
+        inserted.push(i);
+      }
+    }
+    inserted.into_iter().for_each(|i| self.punch(i));
+  }
+
+  /// Returns pages allocated by transactions which did not commit before a crash
+  /// to the free list, unless the page is referenced or beyond the file.
+  /// Returns the number of reclaimed pages.
+  pub fn reclaim(&self, candidates: &[usize], referenced: &BTreeSet<usize>) -> usize {
+    let last_index = self.last_index.load(Ordering::SeqCst);
+    let mut list = self.list.l();
+    candidates
+      .iter()
+      .filter(|&&i| i.lt(&last_index) && !referenced.contains(&i))
+      .filter(|&&i| list.insert(i))
+      .count()
+  }
+
+  /// Checks that every free page is allocated from the file
+  /// and none of them is still referenced.
+  pub fn verify(&self, referenced: &BTreeSet<usize>) -> Result {
+    let last_index = self.last_index.load(Ordering::SeqCst);
+    for &i in self.list.l().indexes.iter() {
+      if i.ge(&last_index) {
+        return Err(Error::Corruption(format!(
+          "free page {i} is beyond the last page {last_index}"
+        )));
+      }
+      if referenced.contains(&i) {
+        return Err(Error::Corruption(format!(
+          "free page {i} is still referenced"
+        )));
+      }
+    }
+    Ok(())
+  }
+
+  #[cfg(test)]
+  pub fn len(&self) -> usize {
+    self.list.l().indexes.len()
   }
 
   pub fn before_shutdown(&self) {
@@ -63,3 +207,179 @@ impl<const N: usize> FreeList<N> {
     self.file.close();
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use std::{collections::BTreeSet, path::PathBuf, sync::Arc, time::Duration};
+
+  use crate::disk::{Finder, FinderConfig};
+
+  use super::{AllocStrategy, FreeList};
+
+  #[test]
+  fn _1() {
+    let (freelist, path) = open("lfkv-freelist");
+    freelist.fetch(1000);
+
+    let released: Vec<usize> = (0..500).map(|i| i * 2).collect();
+    freelist.insert_many(&released);
+
+    let acquired: BTreeSet<usize> = (0..500).map(|_| freelist.acquire()).collect();
+    assert_eq!(acquired, released.into_iter().collect());
+    assert_eq!(freelist.acquire(), 1000);
+
+    freelist.before_shutdown();
+    std::fs::remove_file(&path).unwrap();
+  }
+
+  fn open(name: &str) -> (FreeList<64>, PathBuf) {
+    let path = std::env::temp_dir().join(format!("{name}-{}", std::process::id()));
+    let file = Arc::new(
+      Finder::<64>::open(FinderConfig {
+        path: path.clone(),
+        batch_delay: Duration::from_millis(10),
+        batch_size: 100,
+        file_mode: None,
+        sync_mode: Default::default(),
+        io_budget: None,
+        in_memory: false,
+      })
+      .unwrap(),
+    );
+    (FreeList::new(Duration::from_secs(60), file).unwrap(), path)
+  }
+
+  #[test]
+  fn _2() {
+    let (freelist, path) = open("lfkv-freelist-double");
+    freelist.fetch(10);
+    assert!(freelist.try_insert(3));
+    assert!(!freelist.try_insert(3));
+    assert_eq!(freelist.acquire(), 3);
+    assert_eq!(freelist.acquire(), 10);
+
+    freelist.before_shutdown();
+    std::fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn _4() {
+    let (freelist, path) = open("lfkv-freelist-verify");
+    let a = freelist.acquire();
+    let b = freelist.acquire();
+    freelist.insert(a);
+    assert!(freelist.verify(&BTreeSet::from([b])).is_ok());
+    assert!(freelist.verify(&BTreeSet::from([a, b])).is_err());
+
+    freelist.fetch(a);
+    assert!(freelist.verify(&BTreeSet::new()).is_err());
+
+    freelist.before_shutdown();
+    std::fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn _5() {
+    let (freelist, path) = open("lfkv-freelist-reclaim");
+    let pages = (0..4).map(|_| freelist.acquire()).collect::<Vec<_>>();
+    let referenced = BTreeSet::from([pages[0]]);
+
+    let candidates = [pages[0], pages[1], pages[2], pages[2], 100];
+    assert_eq!(freelist.reclaim(&candidates, &referenced), 2);
+    assert_eq!(freelist.reclaim(&candidates, &referenced), 0);
+    assert!(freelist.verify(&referenced).is_ok());
+
+    freelist.before_shutdown();
+    std::fs::remove_file(&path).unwrap();
+  }
+
+  #[cfg(debug_assertions)]
+  #[test]
+  #[should_panic(expected = "double free of page 3")]
+  fn _3() {
+    let (freelist, path) = open("lfkv-freelist-panic");
+    std::fs::remove_file(&path).unwrap();
+    freelist.insert(3);
+    freelist.insert(3);
+  }
+
+  #[cfg(target_os = "linux")]
+  #[test]
+  fn _6() {
+    use std::os::unix::fs::MetadataExt;
+
+    use crate::{buffer::BLOCK_SIZE, Error, Page};
+
+    let path =
+      std::env::temp_dir().join(format!("lfkv-freelist-punch-{}", std::process::id()));
+    let file = Arc::new(
+      Finder::<BLOCK_SIZE>::open(FinderConfig {
+        path: path.clone(),
+        batch_delay: Duration::from_millis(10),
+        batch_size: 100,
+        file_mode: None,
+        sync_mode: Default::default(),
+        io_budget: None,
+        in_memory: false,
+      })
+      .unwrap(),
+    );
+    for i in 0..8 {
+      let mut page = Page::<BLOCK_SIZE>::new();
+      page.writer().write(&[0xff; 64]).unwrap();
+      file.write(i, page).unwrap();
+    }
+    file.fsync().unwrap();
+    let before = std::fs::metadata(&path).unwrap();
+
+    let freelist = FreeList::new(Duration::from_secs(60), file.clone())
+      .unwrap()
+      .punch_holes(true);
+    freelist.insert_many(&[3, 4]);
+    file.fsync().unwrap();
+    let after = std::fs::metadata(&path).unwrap();
+
+    assert_eq!(after.len(), before.len());
+    assert!(after.blocks().lt(&before.blocks()));
+    assert!(matches!(file.read(3), Err(Error::NotFound)));
+    assert!(file.read(5).is_ok());
+
+    freelist.before_shutdown();
+    std::fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn _7() {
+    let (freelist, path) = open("lfkv-freelist-lowest");
+    freelist.fetch(10);
+    freelist.insert_many(&[7, 2]);
+    freelist.insert(5);
+    assert_eq!(freelist.acquire(), 2);
+    assert_eq!(freelist.acquire(), 5);
+    assert_eq!(freelist.acquire(), 7);
+    assert_eq!(freelist.acquire(), 10);
+
+    freelist.before_shutdown();
+    std::fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn _8() {
+    let (freelist, path) = open("lfkv-freelist-recent");
+    freelist.fetch(10);
+    freelist.insert_many(&[4, 1]);
+    let freelist = freelist.strategy(AllocStrategy::MostRecentlyFreed);
+    freelist.insert_many(&[7, 2]);
+    freelist.insert(5);
+    assert!(!freelist.try_insert(2));
+    assert_eq!(freelist.reclaim(&[8], &BTreeSet::new()), 1);
+
+    let acquired = (0..7).map(|_| freelist.acquire()).collect::<Vec<_>>();
+    // pages free before the switch follow the later ones, lowest first.
+    assert_eq!(acquired, vec![8, 5, 2, 7, 1, 4, 10]);
+    assert!(freelist.verify(&BTreeSet::new()).is_ok());
+
+    freelist.before_shutdown();
+    std::fs::remove_file(&path).unwrap();
+  }
+}
@@ -0,0 +1,151 @@
+use std::{
+  sync::atomic::{AtomicUsize, Ordering},
+  time::Duration,
+};
+
+/// Bucket i counts durations below `2^i` microseconds,
+/// and the last bucket counts everything longer.
+const BUCKET_COUNT: usize = 32;
+
+/// Lock free histogram with power of two buckets in microseconds,
+/// so recording is a couple of atomic increments.
+pub struct LatencyHistogram {
+  buckets: [AtomicUsize; BUCKET_COUNT],
+  total_micros: AtomicUsize,
+}
+impl LatencyHistogram {
+  pub fn new() -> Self {
+    Self {
+      buckets: std::array::from_fn(|_| AtomicUsize::new(0)),
+      total_micros: AtomicUsize::new(0),
+    }
+  }
+
+  pub fn record(&self, elapsed: Duration) {
+    let micros = elapsed.as_micros().min(usize::MAX as u128) as usize;
+    let bucket = (usize::BITS - micros.leading_zeros()) as usize;
+    self.buckets[bucket.min(BUCKET_COUNT - 1)].fetch_add(1, Ordering::Relaxed);
+    self.total_micros.fetch_add(micros, Ordering::Relaxed);
+  }
+
+  pub fn snapshot(&self) -> LatencySnapshot {
+    LatencySnapshot {
+      buckets: self
+        .buckets
+        .iter()
+        .map(|b| b.load(Ordering::Relaxed))
+        .collect(),
+      total: Duration::from_micros(self.total_micros.load(Ordering::Relaxed) as u64),
+    }
+  }
+}
+impl Default for LatencyHistogram {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct LatencySnapshot {
+  buckets: Vec<usize>,
+  total: Duration,
+}
+impl LatencySnapshot {
+  pub fn count(&self) -> usize {
+    self.buckets.iter().sum()
+  }
+
+  pub fn mean(&self) -> Duration {
+    match self.count() {
+      0 => Duration::ZERO,
+      count => self.total.div_f64(count as f64),
+    }
+  }
+
+  /// Returns the upper bound of the bucket holding the quantile,
+  /// so the result is at most twice the real value.
+  pub fn quantile(&self, q: f64) -> Duration {
+    let count = self.count();
+    if count.eq(&0) {
+      return Duration::ZERO;
+    }
+    let rank = ((count as f64) * q.clamp(0.0, 1.0)).ceil().max(1.0) as usize;
+    let mut seen = 0;
+    for (i, &n) in self.buckets.iter().enumerate() {
+      seen += n;
+      if seen.ge(&rank) {
+        return Duration::from_micros(1 << i);
+      }
+    }
+    Duration::from_micros(1 << (BUCKET_COUNT - 1))
+  }
+}
+
+/// Time spent by disk commands waiting for the io thread,
+/// and time spent by the reads and writes themselves.
+#[derive(Default)]
+pub struct DiskLatency {
+  pub queue_wait: LatencyHistogram,
+  pub io: LatencyHistogram,
+  batch_fsyncs: AtomicUsize,
+  batch_waiters: AtomicUsize,
+}
+impl DiskLatency {
+  /// Records a batch fsync which completed the writes of the waiters.
+  pub fn record_fsync(&self, waiters: usize) {
+    self.batch_fsyncs.fetch_add(1, Ordering::Relaxed);
+    self.batch_waiters.fetch_add(waiters, Ordering::Relaxed);
+  }
+
+  pub fn snapshot(&self) -> DiskStats {
+    DiskStats {
+      queue_wait: self.queue_wait.snapshot(),
+      io: self.io.snapshot(),
+      batch_fsyncs: self.batch_fsyncs.load(Ordering::Relaxed),
+      batch_waiters: self.batch_waiters.load(Ordering::Relaxed),
+    }
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct DiskStats {
+  pub queue_wait: LatencySnapshot,
+  pub io: LatencySnapshot,
+  /// Fsyncs of batched writes which had at least one waiter.
+  pub batch_fsyncs: usize,
+  /// Batched writes completed by those fsyncs.
+  pub batch_waiters: usize,
+}
+impl DiskStats {
+  /// Average number of batched writes completed by one fsync.
+  /// Near 1 means the batch is not amortizing the fsync.
+  pub fn waiters_per_fsync(&self) -> f64 {
+    match self.batch_fsyncs {
+      0 => 0.0,
+      fsyncs => self.batch_waiters as f64 / fsyncs as f64,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+
+  use super::LatencyHistogram;
+
+  #[test]
+  fn _1() {
+    let histogram = LatencyHistogram::new();
+    assert_eq!(histogram.snapshot().quantile(0.5), Duration::ZERO);
+    for _ in 0..9 {
+      histogram.record(Duration::from_micros(100));
+    }
+    histogram.record(Duration::from_millis(50));
+
+    let snapshot = histogram.snapshot();
+    assert_eq!(snapshot.count(), 10);
+    assert_eq!(snapshot.quantile(0.5), Duration::from_micros(128));
+    assert_eq!(snapshot.quantile(1.0), Duration::from_micros(65536));
+    assert_eq!(snapshot.mean(), Duration::from_micros(5090));
+  }
+}
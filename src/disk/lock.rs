@@ -0,0 +1,49 @@
+use std::{
+  fs::{File, OpenOptions, TryLockError},
+  path::Path,
+};
+
+use crate::{Error, Result};
+
+/// Exclusive advisory lock on a file, released when dropped.
+pub struct FileLock {
+  _file: File,
+}
+impl FileLock {
+  pub fn acquire<P: AsRef<Path>>(path: P) -> Result<Self> {
+    let file = OpenOptions::new()
+      .create(true)
+      .truncate(false)
+      .read(true)
+      .write(true)
+      .open(path)
+      .map_err(Error::IO)?;
+    match file.try_lock() {
+      Ok(_) => Ok(Self { _file: file }),
+      Err(TryLockError::WouldBlock) => Err(Error::AlreadyLocked),
+      Err(TryLockError::Error(err)) => Err(Error::IO(err)),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::Error;
+
+  use super::FileLock;
+
+  #[test]
+  fn _1() {
+    let path = std::env::temp_dir().join(format!("lfkv-lock-{}", std::process::id()));
+    let lock = FileLock::acquire(&path).unwrap();
+    assert!(matches!(
+      FileLock::acquire(&path),
+      Err(Error::AlreadyLocked)
+    ));
+    drop(lock);
+
+    let lock = FileLock::acquire(&path).unwrap();
+    drop(lock);
+    std::fs::remove_file(&path).unwrap();
+  }
+}
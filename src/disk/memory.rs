@@ -0,0 +1,68 @@
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+
+use super::FileLike;
+
+/// File kept in a buffer in memory, so an engine can run without the disk.
+/// Syncs do nothing and every byte is lost when it is dropped.
+#[derive(Debug, Default)]
+pub struct MemFile(Cursor<Vec<u8>>);
+impl Read for MemFile {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    self.0.read(buf)
+  }
+}
+impl Write for MemFile {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    self.0.write(buf)
+  }
+  fn flush(&mut self) -> io::Result<()> {
+    Ok(())
+  }
+}
+impl Seek for MemFile {
+  fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+    self.0.seek(pos)
+  }
+}
+impl FileLike for MemFile {
+  fn sync_data(&self) -> io::Result<()> {
+    Ok(())
+  }
+  fn sync_all(&self) -> io::Result<()> {
+    Ok(())
+  }
+  fn len(&self) -> io::Result<u64> {
+    Ok(self.0.get_ref().len() as u64)
+  }
+  fn punch_hole(&mut self, offset: usize, len: usize) -> io::Result<()> {
+    let buf = self.0.get_mut();
+    let end = offset.saturating_add(len).min(buf.len());
+    if offset.lt(&end) {
+      buf[offset..end].fill(0);
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::{Read, Seek, SeekFrom, Write};
+
+  use super::{FileLike, MemFile};
+
+  #[test]
+  fn _1() {
+    let mut file = MemFile::default();
+    file.seek(SeekFrom::Start(4)).unwrap();
+    file.write_all(&[1, 2, 3, 4]).unwrap();
+    assert_eq!(file.len().unwrap(), 8);
+
+    file.punch_hole(5, 2).unwrap();
+    file.punch_hole(100, 2).unwrap();
+    let mut buf = vec![0; 8];
+    file.seek(SeekFrom::Start(0)).unwrap();
+    file.read_exact(&mut buf).unwrap();
+    assert_eq!(buf, vec![0, 0, 0, 0, 1, 0, 0, 4]);
+    assert_eq!(file.len().unwrap(), 8);
+  }
+}
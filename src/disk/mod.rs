@@ -7,8 +7,25 @@ pub use serialize::*;
 mod finder;
 pub use finder::*;
 
+mod memory;
+pub use memory::*;
+
 mod free;
 pub use free::*;
 
+mod budget;
+pub use budget::*;
+
+mod latency;
+pub use latency::*;
+
+#[cfg(any(test, feature = "fault-injection"))]
+mod fault;
+#[cfg(any(test, feature = "fault-injection"))]
+pub use fault::*;
+
+mod lock;
+pub use lock::*;
+
 mod bloom_filter;
 pub use bloom_filter::*;
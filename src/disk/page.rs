@@ -1,4 +1,4 @@
-use std::ops::{Add, AddAssign, Index, IndexMut};
+use std::ops::{Add, AddAssign, Index, IndexMut, Range};
 
 use crate::utils::size;
 
@@ -6,7 +6,7 @@ use crate::error::{Error, Result};
 
 use super::Serializable;
 
-pub const PAGE_SIZE: usize = size::kb(4) - 24;
+pub const PAGE_SIZE: usize = size::kb(4) - 32;
 
 #[derive(Debug, PartialEq, Eq)]
 pub struct Page<const T: usize = PAGE_SIZE> {
@@ -25,6 +25,12 @@ impl<const T: usize> Page<T> {
     Self { bytes }
   }
 
+  /// Clears the page to the state of `Page::new`.
+  pub fn reset(&mut self) {
+    self.bytes.fill(0);
+    self.bytes[0] = 1;
+  }
+
   fn range_mut(&mut self, start: usize, end: usize) -> &mut [u8] {
     let end = end.min(self.bytes.len());
     self.bytes.index_mut(start..end)
@@ -36,6 +42,29 @@ impl<const T: usize> Page<T> {
     p
   }
 
+  /// Copies the range of the source page into this page at the offset.
+  /// Nothing is copied if either range is out of the page bounds.
+  pub fn copy_within_from(
+    &mut self,
+    src: &Page<T>,
+    src_range: Range<usize>,
+    dst_offset: usize,
+  ) -> Result<()> {
+    if src_range.start.gt(&src_range.end) || src_range.end.gt(&T) {
+      return Err(Error::EOF);
+    }
+    let dst_end = dst_offset.add(src_range.len());
+    if dst_end.gt(&T) {
+      return Err(Error::EOF);
+    }
+
+    self
+      .bytes
+      .index_mut(dst_offset..dst_end)
+      .copy_from_slice(src.bytes.index(src_range));
+    Ok(())
+  }
+
   pub fn scanner(&self) -> PageScanner<'_, T> {
     PageScanner::new(&self.bytes)
   }
@@ -44,6 +73,16 @@ impl<const T: usize> Page<T> {
     PageWriter::new(&mut self.bytes)
   }
 
+  /// Number of bytes written after the marker byte, up to the last non zero byte.
+  /// Trailing zero bytes of a value are not counted.
+  pub fn used_len(&self) -> usize {
+    self.bytes[1..]
+      .iter()
+      .rposition(|b| b.ne(&0))
+      .map(|i| i.add(1))
+      .unwrap_or(0)
+  }
+
   pub fn is_empty(&self) -> bool {
     self.bytes[0].eq(&0)
   }
@@ -134,6 +173,24 @@ impl<'a, const T: usize> PageScanner<'a, T> {
     Ok(usize::from_be_bytes(b))
   }
 
+  pub fn read_u16(&mut self) -> Result<u16> {
+    let mut b = [0; 2];
+    b.copy_from_slice(self.read_n(2)?);
+    Ok(u16::from_be_bytes(b))
+  }
+
+  pub fn read_u32(&mut self) -> Result<u32> {
+    let mut b = [0; 4];
+    b.copy_from_slice(self.read_n(4)?);
+    Ok(u32::from_be_bytes(b))
+  }
+
+  pub fn read_u64(&mut self) -> Result<u64> {
+    let mut b = [0; 8];
+    b.copy_from_slice(self.read_n(8)?);
+    Ok(u64::from_be_bytes(b))
+  }
+
   pub fn is_eof(&self) -> bool {
     self.inner.len().le(&self.offset)
   }
@@ -160,6 +217,30 @@ impl<'a, const T: usize> PageWriter<'a, T> {
     self.offset = end;
     Ok(())
   }
+
+  /// Overwrites the bytes at the offset from the start of the page,
+  /// marker byte included, without moving the offset of sequential writes.
+  /// Nothing is written if the bytes do not fit in the page.
+  pub fn write_at_offset(&mut self, offset: usize, bytes: &[u8]) -> Result<()> {
+    let end = offset.checked_add(bytes.len()).ok_or(Error::EOF)?;
+    if end.gt(&T) {
+      return Err(Error::EOF);
+    }
+    self.inner.index_mut(offset..end).copy_from_slice(bytes);
+    Ok(())
+  }
+
+  pub fn write_u16(&mut self, v: u16) -> Result<()> {
+    self.write(&v.to_be_bytes())
+  }
+
+  pub fn write_u32(&mut self, v: u32) -> Result<()> {
+    self.write(&v.to_be_bytes())
+  }
+
+  pub fn write_u64(&mut self, v: u64) -> Result<()> {
+    self.write(&v.to_be_bytes())
+  }
 }
 
 #[cfg(test)]
@@ -180,4 +261,85 @@ mod tests {
     assert_eq!(page.bytes[5], 6);
     assert_eq!(page.bytes[6], 0);
   }
+  #[test]
+  fn _2() {
+    let mut src = Page::<16>::new();
+    src.writer().write(&[1, 2, 3, 4]).unwrap();
+
+    let mut dst = Page::<16>::new();
+    dst.copy_within_from(&src, 2..4, 10).unwrap();
+    assert_eq!(dst.bytes[10], 2);
+    assert_eq!(dst.bytes[11], 3);
+    assert_eq!(dst.bytes[12], 0);
+
+    dst.copy_within_from(&src, 0..16, 0).unwrap();
+    assert_eq!(dst, src);
+  }
+
+  #[test]
+  fn _3() {
+    let src = Page::<16>::new();
+    let mut dst = Page::<16>::new();
+    assert!(dst.copy_within_from(&src, 8..17, 0).is_err());
+    assert!(dst.copy_within_from(&src, 0..4, 13).is_err());
+    assert!(dst.copy_within_from(&src, 0..16, 1).is_err());
+    assert_eq!(dst, Page::<16>::new());
+  }
+  #[test]
+  fn _4() {
+    let mut page = Page::<64>::new();
+    let mut wt = page.writer();
+    for v in [0, 1, u16::MAX] {
+      wt.write_u16(v).unwrap();
+    }
+    for v in [0, 1, u32::MAX] {
+      wt.write_u32(v).unwrap();
+    }
+    for v in [0, 1, u64::MAX] {
+      wt.write_u64(v).unwrap();
+    }
+
+    let mut sc = page.scanner();
+    for v in [0, 1, u16::MAX] {
+      assert_eq!(sc.read_u16().unwrap(), v);
+    }
+    for v in [0, 1, u32::MAX] {
+      assert_eq!(sc.read_u32().unwrap(), v);
+    }
+    for v in [0, 1, u64::MAX] {
+      assert_eq!(sc.read_u64().unwrap(), v);
+    }
+  }
+
+  #[test]
+  fn _5() {
+    let mut page = Page::<8>::new();
+    let mut wt = page.writer();
+    wt.write_u32(u32::MAX).unwrap();
+    assert!(wt.write_u32(1).is_err());
+    assert!(wt.write_u16(1).is_ok());
+    assert!(wt.write_u64(1).is_err());
+
+    let mut sc = page.scanner();
+    assert_eq!(sc.read_u32().unwrap(), u32::MAX);
+    assert!(sc.read_u64().is_err());
+  }
+
+  #[test]
+  fn _6() {
+    let mut page = Page::<16>::new();
+    let mut wt = page.writer();
+    wt.write(&[1, 2, 3, 4, 5, 6]).unwrap();
+    wt.write_at_offset(3, &[9, 9]).unwrap();
+    wt.write(&[7]).unwrap();
+    assert_eq!(&page.bytes[..9], &[1, 1, 2, 9, 9, 5, 6, 7, 0]);
+
+    let mut wt = page.writer();
+    wt.write_at_offset(14, &[8, 8]).unwrap();
+    assert!(wt.write_at_offset(15, &[1, 1]).is_err());
+    assert!(wt.write_at_offset(16, &[1]).is_err());
+    assert!(wt.write_at_offset(usize::MAX, &[1]).is_err());
+    assert_eq!(&page.bytes[13..], &[0, 8, 8]);
+    assert_eq!(&page.bytes[..9], &[1, 1, 2, 9, 9, 5, 6, 7, 0]);
+  }
 }
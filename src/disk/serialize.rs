@@ -1,10 +1,17 @@
 use crate::{error::Error, PAGE_SIZE};
 
-use super::Page;
+use super::{Page, PageScanner, PageWriter};
 
 pub trait Serializable<T = Error, const N: usize = PAGE_SIZE>: Sized {
   fn serialize(&self) -> Result<Page<N>, T>;
   fn deserialize(value: &Page<N>) -> Result<Self, T>;
+
+  /// Serializes into a page which may hold stale bytes, clearing it first,
+  /// so a recycled page can be reused instead of allocating a new one.
+  fn serialize_into(&self, page: &mut Page<N>) -> Result<(), T> {
+    *page = self.serialize()?;
+    Ok(())
+  }
 }
 impl<const N: usize> Page<N> {
   pub fn deserialize<T, E>(&self) -> Result<T, E>
@@ -14,3 +21,101 @@ impl<const N: usize> Page<N> {
     T::deserialize(self)
   }
 }
+
+/// Prefix shared by records which carry their type, the version of their
+/// format and flags. It is written first after the marker byte, so a page
+/// of another type is rejected before the rest of it is read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageHeader {
+  pub type_tag: u8,
+  pub version: u8,
+  pub flags: u16,
+}
+impl PageHeader {
+  pub const BYTES: usize = 4;
+
+  pub fn new(type_tag: u8, version: u8) -> Self {
+    Self {
+      type_tag,
+      version,
+      flags: 0,
+    }
+  }
+
+  pub fn with_flags(mut self, flags: u16) -> Self {
+    self.flags = flags;
+    self
+  }
+
+  pub fn write<const N: usize>(&self, wt: &mut PageWriter<'_, N>) -> Result<(), Error> {
+    wt.write(&[self.type_tag, self.version])?;
+    wt.write(&self.flags.to_be_bytes())
+  }
+
+  /// Fails with `Error::Invalid` if the page holds a record of another type.
+  pub fn read<const N: usize>(
+    sc: &mut PageScanner<'_, N>,
+    type_tag: u8,
+  ) -> Result<Self, Error> {
+    let tag = sc.read()?;
+    if tag.ne(&type_tag) {
+      return Err(Error::Invalid);
+    }
+    Ok(Self {
+      type_tag,
+      version: sc.read()?,
+      flags: sc.read_u16()?,
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::{Error, Page, Serializable};
+
+  use super::PageHeader;
+
+  #[derive(Debug, PartialEq, Eq)]
+  struct Record {
+    header: PageHeader,
+    value: usize,
+  }
+  impl Serializable for Record {
+    fn serialize(&self) -> Result<Page, Error> {
+      let mut p = Page::new();
+      let mut wt = p.writer();
+      self.header.write(&mut wt)?;
+      wt.write(&self.value.to_be_bytes())?;
+      Ok(p)
+    }
+
+    fn deserialize(value: &Page) -> Result<Self, Error> {
+      let mut sc = value.scanner();
+      let header = PageHeader::read(&mut sc, 7)?;
+      Ok(Self {
+        header,
+        value: sc.read_usize()?,
+      })
+    }
+  }
+
+  #[test]
+  fn _1() {
+    let record = Record {
+      header: PageHeader::new(7, 2).with_flags(0x0102),
+      value: 42,
+    };
+    let page = record.serialize().unwrap();
+    assert_eq!(&page.as_ref()[1..1 + PageHeader::BYTES], &[7, 2, 1, 2]);
+    assert_eq!(page.deserialize::<Record, Error>().unwrap(), record);
+
+    let other = Record {
+      header: PageHeader::new(8, 2),
+      value: 42,
+    };
+    assert!(matches!(
+      other.serialize().unwrap().deserialize::<Record, Error>(),
+      Err(Error::Invalid)
+    ));
+  }
+}
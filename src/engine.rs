@@ -1,9 +1,10 @@
 use std::{
+  collections::BTreeMap,
   fs,
-  ops::Mul,
-  path::Path,
+  ops::{AddAssign, Mul},
+  path::{Path, PathBuf},
   sync::{
-    atomic::{AtomicBool, Ordering},
+    atomic::{AtomicBool, AtomicUsize, Ordering},
     Arc,
   },
   time::Duration,
@@ -12,11 +13,19 @@ use std::{
 use sysinfo::System;
 
 use crate::{
-  buffer::{BufferPool, RollbackStorage, RollbackStorageConfig, BLOCK_SIZE},
-  disk::{Finder, FinderConfig, FreeList},
+  buffer::{
+    BufferPool, EvictionPolicy, RollbackStorage, RollbackStorageConfig, BLOCK_SIZE,
+  },
+  dirty_read,
+  disk::{
+    AllocStrategy, DiskStats, FileLock, Finder, FinderConfig, FreeList, IoBudget,
+    SyncMode,
+  },
   logger,
-  wal::{WriteAheadLog, WriteAheadLogConfig},
-  Cursor, Error, Result,
+  wal::{LogMode, LogicalLog, WriteAheadLog, WriteAheadLogConfig},
+  ActiveTransactions, BackgroundThread, BackgroundWork, Clock, Cursor, Error, HotKeys,
+  KeyComparator, KeyspaceInfo, Page, Result, ResumeToken, ScanPage, Scoped, Serializable,
+  Snapshot, Snapshots, SystemClock, WriteBatch,
 };
 
 pub struct EngineConfig<T>
@@ -35,17 +44,158 @@ where
   pub checkpoint_count: usize,
   pub group_commit_delay: Duration,
   pub group_commit_count: usize,
+  pub repair: bool,
+  pub file_mode: Option<u32>,
+  pub sync_mode: SyncMode,
+  pub log_mode: LogMode,
+  pub verify_on_open: VerifyLevel,
+  /// Threads applying the redo of the WAL at bootstrap, serial if 1 or less.
+  pub recovery_threads: usize,
+  /// Transactions open longer than this are aborted in the background.
+  pub transaction_timeout: Option<Duration>,
+  /// Bytes of page buffers in flight shared by the data, undo and wal files,
+  /// unbounded if none.
+  pub io_buffer_budget: Option<usize>,
+  /// Frees the disk blocks of released pages, see `FreeList::punch_holes`.
+  pub punch_holes: bool,
+  /// Order free pages are reused in, see `AllocStrategy`.
+  pub alloc_strategy: AllocStrategy,
+  /// Order of the keys, recorded when the database is created.
+  /// Opening an existing database with another comparator fails.
+  pub key_comparator: KeyComparator,
+  /// Files of the data, the wal and the undo log, which are placed
+  /// in the base path if none, so the wal can live on another device.
+  pub data_path: Option<PathBuf>,
+  pub wal_path: Option<PathBuf>,
+  pub undo_path: Option<PathBuf>,
+  /// Keeps the data, the wal and the undo log in memory, for tests.
+  /// Nothing is created in the paths and everything is lost on shutdown.
+  pub in_memory: bool,
+  /// Page replacement of the buffer pool cache.
+  pub eviction_policy: EvictionPolicy,
+  /// Transactions which can be open at once, unbounded if none.
+  /// A leaked transaction holds back the gc, so the limit stops
+  /// a leak from growing without bound.
+  pub max_open_transactions: Option<usize>,
+  /// Bytes of wal records buffered in memory before they are written,
+  /// a tenth of the system memory if none.
+  pub wal_buffer_bytes: Option<usize>,
+}
+
+/// Integrity check run at bootstrap before the engine is available.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum VerifyLevel {
+  #[default]
+  None,
+  /// Checks the tree header and the consistency of the free list.
+  Quick,
+  /// Checks the structure of every tree and the free list against it.
+  Full,
 }
 
+pub type CheckpointId = usize;
+
+#[derive(Debug)]
+pub struct EngineStats {
+  pub hot_keys: Vec<(Vec<u8>, usize)>,
+  pub data_disk: DiskStats,
+  pub wal_disk: DiskStats,
+  /// Average wal writes made durable by one fsync, for tuning group commit.
+  /// Near 1 means commits are not batched, because the delay is too short
+  /// or the load too light.
+  pub commits_per_fsync: f64,
+}
+
+const MAX_HOT_KEYS: usize = 16;
+
+const LOCK_PATH: &str = "LOCK";
 const WAL_PATH: &str = "wal.db";
 const UNDO_PATH: &str = "undo.db";
 const DISK_PATH: &str = "data.db";
 
+/// Returns the paths of the data, wal and undo files,
+/// creating their directories and checking that they are distinct files.
+fn resolve_paths<T>(config: &EngineConfig<T>) -> Result<(PathBuf, PathBuf, PathBuf)>
+where
+  T: AsRef<Path>,
+{
+  let base = config.base_path.as_ref();
+  let paths = [
+    (&config.data_path, DISK_PATH),
+    (&config.wal_path, WAL_PATH),
+    (&config.undo_path, UNDO_PATH),
+  ]
+  .map(|(path, default)| path.clone().unwrap_or_else(|| base.join(default)));
+  if config.in_memory {
+    let [disk, wal, undo] = paths;
+    return Ok((disk, wal, undo));
+  }
+
+  let mut resolved = Vec::with_capacity(paths.len());
+  for path in &paths {
+    let dir = match path.parent() {
+      Some(dir) if !dir.as_os_str().is_empty() => dir,
+      _ => Path::new("."),
+    };
+    fs::create_dir_all(dir).map_err(Error::IO)?;
+    let file = path.file_name().ok_or_else(|| invalid_path(path))?;
+    let file = fs::canonicalize(dir).map_err(Error::IO)?.join(file);
+    if resolved.contains(&file) {
+      return Err(invalid_path(path));
+    }
+    resolved.push(file);
+  }
+  let [disk, wal, undo] = paths;
+  Ok((disk, wal, undo))
+}
+
+/// Writes the last of the changes of each key where the tree differs from it,
+/// and returns the number of keys written.
+fn apply_changes(cursor: &Cursor, changes: Vec<LogicalLog>) -> Result<usize> {
+  let mut last = BTreeMap::new();
+  for change in changes {
+    last.insert((change.keyspace, change.key), change.value);
+  }
+  let mut applied = 0;
+  for ((name, key), value) in last {
+    let keyspace = cursor.keyspace(&name);
+    let current = match keyspace.get::<Page>(&key) {
+      Ok(page) => Some(page),
+      Err(Error::NotFound) => None,
+      Err(err) => return Err(err),
+    };
+    if current.eq(&value) {
+      continue;
+    }
+    match value {
+      Some(value) => keyspace.insert(key, value)?,
+      None => keyspace.remove(&key)?,
+    }
+    applied.add_assign(1);
+  }
+  Ok(applied)
+}
+
+fn invalid_path(path: &Path) -> Error {
+  Error::IO(std::io::Error::new(
+    std::io::ErrorKind::InvalidInput,
+    format!("{} can not be used for more than one file", path.display()),
+  ))
+}
+
 pub struct Engine {
   wal: Arc<WriteAheadLog>,
   buffer_pool: Arc<BufferPool>,
   freelist: Arc<FreeList<BLOCK_SIZE>>,
   available: AtomicBool,
+  clock: Arc<dyn Clock>,
+  hot_keys: Arc<HotKeys>,
+  transactions: Arc<ActiveTransactions>,
+  snapshots: Arc<Snapshots>,
+  key_count: Arc<AtomicUsize>,
+  max_open_transactions: Option<usize>,
+  timeout_c: BackgroundThread<()>,
+  _lock: Option<FileLock>,
 }
 impl Engine {
   pub fn bootstrap<T>(config: EngineConfig<T>) -> Result<Self>
@@ -54,19 +204,36 @@ impl Engine {
   {
     let mem_size = System::new_all().total_memory() as usize;
     logger::info(format!("{} system memory", mem_size));
-    fs::create_dir_all(config.base_path.as_ref()).map_err(Error::IO)?;
+    let lock = match config.in_memory {
+      true => None,
+      false => {
+        fs::create_dir_all(config.base_path.as_ref()).map_err(Error::IO)?;
+        Some(FileLock::acquire(
+          config.base_path.as_ref().join(LOCK_PATH),
+        )?)
+      }
+    };
+    let (disk_path, wal_path, undo_path) = resolve_paths(&config)?;
 
+    let io_budget = config
+      .io_buffer_budget
+      .map(|limit| Arc::new(IoBudget::new(limit)));
     let disk = Arc::new(Finder::open(FinderConfig {
-      path: config.base_path.as_ref().join(DISK_PATH),
+      path: disk_path,
       batch_delay: config.disk_batch_delay,
       batch_size: config.disk_batch_size,
+      file_mode: config.file_mode,
+      sync_mode: config.sync_mode,
+      io_budget: io_budget.clone(),
+      in_memory: config.in_memory,
     })?);
     logger::info(format!("disk created"));
 
-    let freelist = Arc::new(FreeList::new(
-      config.defragmentation_interval,
-      disk.clone(),
-    )?);
+    let freelist = Arc::new(
+      FreeList::new(config.defragmentation_interval, disk.clone())?
+        .punch_holes(config.punch_holes)
+        .strategy(config.alloc_strategy),
+    );
     logger::info(format!("freelist created"));
 
     let rollback = Arc::new(RollbackStorage::open(RollbackStorageConfig {
@@ -74,24 +241,41 @@ impl Engine {
       fsync_count: config.undo_batch_size,
       max_cache_size: mem_size.div_ceil(10),
       max_file_size: config.undo_file_size,
-      path: config.base_path.as_ref().join(UNDO_PATH),
+      path: undo_path,
+      file_mode: config.file_mode,
+      sync_mode: config.sync_mode,
+      io_budget: io_budget.clone(),
+      in_memory: config.in_memory,
     })?);
     logger::info(format!("undo log created"));
 
-    let (bp, flush_c, commit_c) =
-      BufferPool::generate(rollback, disk, mem_size.div_ceil(10).mul(3));
+    let (bp, flush_c, commit_c) = BufferPool::generate(
+      rollback,
+      disk,
+      mem_size.div_ceil(10).mul(3),
+      config.eviction_policy,
+    );
     let buffer_pool = Arc::new(bp);
     logger::info(format!("buffer pool created"));
 
     let wal = Arc::new(WriteAheadLog::open(
       WriteAheadLogConfig {
-        path: config.base_path.as_ref().join(WAL_PATH),
-        max_buffer_size: mem_size.div_ceil(10).mul(1),
+        path: wal_path,
+        max_buffer_size: config
+          .wal_buffer_bytes
+          .unwrap_or(mem_size.div_ceil(10).mul(1)),
         checkpoint_interval: config.checkpoint_interval,
         checkpoint_count: config.checkpoint_count,
         group_commit_delay: config.group_commit_delay,
         group_commit_count: config.group_commit_count,
         max_file_size: config.wal_file_size,
+        repair: config.repair,
+        file_mode: config.file_mode,
+        sync_mode: config.sync_mode,
+        log_mode: config.log_mode,
+        recovery_threads: config.recovery_threads,
+        io_budget,
+        in_memory: config.in_memory,
       },
       Arc::new(commit_c),
       flush_c,
@@ -99,15 +283,55 @@ impl Engine {
     )?);
     logger::info("wal created");
 
+    let transactions = Arc::new(ActiveTransactions::default());
+    let timeout_c = BackgroundThread::empty("transaction timeout", BLOCK_SIZE.mul(100));
+    if let Some(timeout) = config.transaction_timeout {
+      let cloned = transactions.clone();
+      timeout_c.set_work(BackgroundWork::with_timeout(
+        timeout.div_f64(4.0),
+        move |_| {
+          let aborted = cloned.abort_expired(timeout);
+          if aborted.gt(&0) {
+            logger::warn(format!("{aborted} timed out transactions aborted"));
+          }
+        },
+      ));
+      timeout_c.send(());
+    }
+
     let engine = Self {
       wal,
       buffer_pool,
       freelist,
       available: AtomicBool::new(true),
+      clock: Arc::new(SystemClock),
+      hot_keys: Arc::new(HotKeys::new(MAX_HOT_KEYS)),
+      transactions,
+      snapshots: Default::default(),
+      key_count: Default::default(),
+      max_open_transactions: config.max_open_transactions,
+      timeout_c,
+      _lock: lock,
     };
 
+    if engine.wal.is_repaired() {
+      if let Err(err) = engine.verify_repair() {
+        // closed without the final checkpoint, which would record the repair.
+        engine.available.store(false, Ordering::SeqCst);
+        return Err(err);
+      }
+    }
     let cursor = engine.new_transaction()?;
-    cursor.initialize()?;
+    cursor.initialize(config.key_comparator)?;
+    engine.verify(&cursor, config.verify_on_open)?;
+    engine.reclaim_orphans(&cursor);
+    engine.apply_replayed_changes(&cursor)?;
+    let key_count = match engine.wal.redone() {
+      // the count in the header is kept up to date only by a clean shutdown.
+      Some(_) => cursor.keyspaces()?.iter().map(|info| info.key_count).sum(),
+      None => cursor.stored_key_count()?,
+    };
+    engine.key_count.store(key_count, Ordering::SeqCst);
     cursor.commit()?;
 
     logger::info("engine initialized");
@@ -118,20 +342,2876 @@ impl Engine {
     if !self.available.load(Ordering::SeqCst) {
       return Err(Error::EngineUnavailable);
     }
+    if let Some(max) = self.max_open_transactions {
+      if self.transactions.len().ge(&max) {
+        return Err(Error::TooManyOpenTransactions(max));
+      }
+    }
+    self.open_cursor()
+  }
 
+  fn open_cursor(&self) -> Result<Cursor> {
     Cursor::new(
       self.freelist.clone(),
       self.wal.clone(),
       self.buffer_pool.clone(),
+      self.clock.clone(),
+      self.hot_keys.clone(),
+      self.transactions.clone(),
+      self.snapshots.clone(),
     )
+    .map(|cursor| cursor.with_key_count(self.key_count.clone()))
+  }
+
+  /// Pins a read only view of the latest commit, which needs no transaction
+  /// and is not aborted by the transaction timeout.
+  pub fn create_snapshot(&self) -> Result<Snapshot> {
+    if !self.available.load(Ordering::SeqCst) {
+      return Err(Error::EngineUnavailable);
+    }
+
+    Ok(Snapshot::new(
+      self.wal.clone(),
+      self.buffer_pool.clone(),
+      self.snapshots.clone(),
+      self.freelist.clone(),
+    ))
+  }
+
+  /// True once a write failed with a full disk. Writes fail with
+  /// `Error::DiskFull` until `resume_writes` is called, and reads still work.
+  pub fn is_disk_full(&self) -> bool {
+    self.wal.is_disk_full()
+  }
+
+  /// Lets writes through again once space is freed on the disk.
+  pub fn resume_writes(&self) {
+    self.wal.resume_writes()
+  }
+
+  /// Number of transactions which are neither committed nor aborted.
+  pub fn open_transaction_count(&self) -> usize {
+    self.transactions.len()
+  }
+
+  /// Forces a checkpoint and returns the log index it covers.
+  /// Records at or below the returned index are durable in the data file.
+  pub fn checkpoint(&self) -> Result<CheckpointId> {
+    if !self.available.load(Ordering::SeqCst) {
+      return Err(Error::EngineUnavailable);
+    }
+
+    self.wal.checkpoint()
+  }
+
+  /// Forces a checkpoint and frees the pages of the wal holding only records
+  /// the data file already has, so the file stays small after quiet periods.
+  /// Returns the bytes reclaimed, which stay allocated on platforms
+  /// other than linux even though the pages are dropped from the log.
+  pub fn compact_wal(&self) -> Result<usize> {
+    if !self.available.load(Ordering::SeqCst) {
+      return Err(Error::EngineUnavailable);
+    }
+
+    self.wal.compact()
+  }
+
+  /// Reads the key in a short lived transaction.
+  pub fn get<T>(&self, key: &Vec<u8>) -> Result<Option<T>>
+  where
+    T: Serializable,
+  {
+    let cursor = self.new_transaction()?;
+    let value = match cursor.get(key) {
+      Ok(value) => Some(value),
+      Err(Error::NotFound) => None,
+      Err(err) => return Err(err),
+    };
+    cursor.commit()?;
+    Ok(value)
+  }
+
+  /// Inserts the value in a short lived transaction,
+  /// retrying once if the first attempt fails with a retryable error.
+  pub fn put<T>(&self, key: Vec<u8>, value: T) -> Result
+  where
+    T: Serializable,
+  {
+    let page = value.serialize()?;
+    let put = |key: Vec<u8>, page: Page| {
+      let cursor = self.new_transaction()?;
+      cursor.insert(key, page)?;
+      cursor.commit()
+    };
+    match put(key.clone(), page.copy()) {
+      Err(err) if err.is_retryable() => {
+        logger::warn(format!("put will be retried {:?}", err));
+        put(key, page)
+      }
+      result => result,
+    }
+  }
+
+  /// Returns a handle which keeps its keys under the prefix.
+  /// Fails if the prefix is empty or only `0xff` bytes.
+  pub fn scoped(&self, prefix: Vec<u8>) -> Result<Scoped<'_>> {
+    Scoped::new(self, prefix)
+  }
+
+  /// Number of committed keys in all keyspaces, kept in memory
+  /// and recorded in the tree header on a clean shutdown.
+  pub fn key_count(&self) -> Result<usize> {
+    if !self.available.load(Ordering::SeqCst) {
+      return Err(Error::EngineUnavailable);
+    }
+    Ok(self.key_count.load(Ordering::SeqCst))
+  }
+
+  /// Lists every keyspace with its root and key count.
+  pub fn iter_keyspaces(&self) -> Result<Vec<KeyspaceInfo>> {
+    let cursor = self.new_transaction()?;
+    let keyspaces = cursor.keyspaces()?;
+    cursor.commit()?;
+    Ok(keyspaces)
+  }
+
+  /// Drops the named keyspace in a transaction of its own, so its keys
+  /// and the release of its pages commit together or not at all.
+  pub fn drop_keyspace(&self, name: &[u8]) -> Result<bool> {
+    let cursor = self.new_transaction()?;
+    let dropped = cursor.drop_keyspace(name)?;
+    cursor.commit()?;
+    Ok(dropped)
+  }
+
+  /// Scans the first page of the range and returns a token for the next page.
+  pub fn scan_page<T>(
+    &self,
+    start: &Vec<u8>,
+    end: &[u8],
+    limit: usize,
+  ) -> Result<ScanPage<T>>
+  where
+    T: Serializable,
+  {
+    let cursor = self.new_transaction()?;
+    let page = cursor.scan_page(start, end, limit);
+    cursor.commit()?;
+    page
+  }
+
+  /// Continues a paginated scan on the snapshot recorded in the token.
+  /// Fails with `Error::Invalid` if the snapshot is newer than the last
+  /// commit, as it can be for a token decoded from arbitrary bytes.
+  ///
+  /// Nothing is pinned between pages, so a token is only good while the
+  /// versions of its snapshot are still in the undo log. Once later commits
+  /// wrap the undo file past them, the scan fails with
+  /// `Error::SnapshotExpired` instead of reading other versions.
+  /// A scan which must outlive many commits should use `create_snapshot`.
+  pub fn scan_resume<T>(&self, token: &ResumeToken, limit: usize) -> Result<ScanPage<T>>
+  where
+    T: Serializable,
+  {
+    if token.get_snapshot().gt(&self.wal.last_index()) {
+      return Err(Error::Invalid);
+    }
+    let cursor = self.new_transaction()?.with_snapshot(token.get_snapshot());
+    let page = cursor.scan_resume(token, limit);
+    cursor.commit()?;
+    page
+  }
+
+  pub fn stats(&self) -> EngineStats {
+    let wal_disk = self.wal.disk_stats();
+    EngineStats {
+      hot_keys: self.hot_keys.report(),
+      data_disk: self.buffer_pool.disk_stats(),
+      commits_per_fsync: wal_disk.waiters_per_fsync(),
+      wal_disk,
+    }
+  }
+
+  pub fn apply_batch(&self, batch: WriteBatch) -> Result {
+    let cursor = self.new_transaction()?;
+    cursor.apply(batch)?;
+    cursor.commit()
+  }
+
+  /// Reads the newest value of the key bypassing snapshot visibility,
+  /// so uncommitted writes of other transactions are returned too.
+  /// It violates isolation and must only be used for diagnostics.
+  pub fn dirty_read<T>(&self, key: &Vec<u8>) -> Result<Option<T>>
+  where
+    T: Serializable,
+  {
+    if !self.available.load(Ordering::SeqCst) {
+      return Err(Error::EngineUnavailable);
+    }
+
+    dirty_read(&self.buffer_pool, key)
+  }
+
+  pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+    self.clock = clock;
+  }
+
+  /// Pages allocated by transactions interrupted by a crash are not referenced
+  /// by the tree, so they are returned to the free list instead of leaking.
+  fn reclaim_orphans(&self, cursor: &Cursor) {
+    let candidates = self.wal.take_rolled_back();
+    if candidates.is_empty() {
+      return;
+    }
+    match cursor.verify() {
+      Ok(referenced) => {
+        let reclaimed = self.freelist.reclaim(&candidates, &referenced);
+        logger::info(format!("{reclaimed} orphaned pages reclaimed"));
+      }
+      Err(err) => logger::warn(format!("orphaned pages are not reclaimed {:?}", err)),
+    }
+  }
+
+  /// The pages are redone from their images, so the key changes replayed
+  /// from a wal written in logical mode are written only where the tree
+  /// does not hold them.
+  fn apply_replayed_changes(&self, cursor: &Cursor) -> Result {
+    let changes = self.wal.take_replayed_changes();
+    if changes.is_empty() {
+      return Ok(());
+    }
+    let replayed = changes.len();
+    let applied = apply_changes(cursor, changes)?;
+    logger::info(format!(
+      "{applied} of {replayed} replayed key changes applied"
+    ));
+    Ok(())
+  }
+
+  /// The data file is all that is left once the wal is lost, so the wal is
+  /// rebuilt on it only if it verifies in full. The snapshot reads it
+  /// without a transaction, so nothing is written to the wal before.
+  fn verify_repair(&self) -> Result {
+    let referenced = self.create_snapshot()?.verify()?;
+    self.freelist.verify(&referenced)?;
+    logger::info("data file verified for the wal repair");
+    Ok(())
+  }
+
+  fn verify(&self, cursor: &Cursor, level: VerifyLevel) -> Result {
+    let referenced = match level {
+      VerifyLevel::None => return Ok(()),
+      VerifyLevel::Quick => cursor.verify_header()?,
+      VerifyLevel::Full => cursor.verify()?,
+    };
+    self.freelist.verify(&referenced)?;
+    logger::info(format!("{:?} verification passed", level));
+    Ok(())
+  }
+
+  /// Checkpoints and closes the subsystems in order, returning the error
+  /// of the final checkpoint. Calling it again after the first call is a no-op.
+  pub fn shutdown(&self) -> Result {
+    if !self.available.swap(false, Ordering::SeqCst) {
+      return Ok(());
+    }
+
+    self.timeout_c.close();
+    let stored = self.transactions.is_empty() && self.store_key_count();
+    let result = self.wal.checkpoint().map(|_| ());
+    self
+      .wal
+      .before_shutdown(result.is_ok() && stored && self.transactions.is_empty());
+    self.buffer_pool.before_shutdown();
+    self.freelist.before_shutdown();
+    logger::info("engine shutdown");
+    result
+  }
+
+  /// Records the key count in the tree header. A shutdown which fails to
+  /// is not clean, so the next open counts the keys again.
+  fn store_key_count(&self) -> bool {
+    let count = self.key_count.load(Ordering::SeqCst);
+    let stored = self
+      .open_cursor()
+      .and_then(|cursor| cursor.store_key_count(count).and_then(|_| cursor.commit()));
+    if let Err(err) = &stored {
+      logger::warn(format!("failed to store the key count {:?}", err));
+    }
+    stored.is_ok()
   }
 }
 
 impl Drop for Engine {
   fn drop(&mut self) {
-    self.available.store(false, Ordering::SeqCst);
-    self.wal.before_shutdown();
-    self.buffer_pool.before_shutdown();
-    self.freelist.before_shutdown();
+    if let Err(err) = self.shutdown() {
+      logger::error(format!("failed to shutdown engine {:?}", err));
+    }
+  }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+  use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::{Read, Seek, SeekFrom, Write},
+    path::PathBuf,
+    sync::{
+      atomic::{AtomicUsize, Ordering},
+      Arc,
+    },
+    time::{Duration, Instant},
+  };
+
+  use crate::{
+    buffer::BLOCK_SIZE,
+    disk::{FaultSpec, Finder, FinderConfig},
+    wal::{LogEntry, LogMode, LogRecord, LogicalLog, WAL_PAGE_SIZE},
+    Clock, Cursor, Error, IsolationLevel, KeyComparator, KeyState, Operation, Page,
+    ResumeToken, Serializable, WalReader, WriteBatch,
+  };
+
+  use super::{
+    apply_changes, Engine, EngineConfig, Scoped, VerifyLevel, DISK_PATH, UNDO_PATH,
+    WAL_PATH,
+  };
+
+  pub(crate) fn config(name: &str) -> EngineConfig<PathBuf> {
+    let dir = std::env::temp_dir().join(format!("{name}-{}", std::process::id()));
+    EngineConfig {
+      base_path: dir,
+      disk_batch_delay: Duration::from_millis(10),
+      disk_batch_size: 100,
+      defragmentation_interval: Duration::from_secs(60),
+      undo_batch_delay: Duration::from_millis(10),
+      undo_batch_size: 100,
+      undo_file_size: 1 << 20,
+      wal_file_size: 1 << 20,
+      checkpoint_interval: Duration::from_secs(60),
+      checkpoint_count: 100,
+      group_commit_delay: Duration::from_millis(10),
+      group_commit_count: 100,
+      repair: false,
+      file_mode: None,
+      sync_mode: Default::default(),
+      log_mode: Default::default(),
+      verify_on_open: VerifyLevel::Full,
+      recovery_threads: 1,
+      transaction_timeout: None,
+      io_buffer_budget: None,
+      punch_holes: false,
+      key_comparator: Default::default(),
+      data_path: None,
+      wal_path: None,
+      undo_path: None,
+      in_memory: false,
+      eviction_policy: Default::default(),
+      max_open_transactions: None,
+      wal_buffer_bytes: None,
+      alloc_strategy: Default::default(),
+    }
+  }
+
+  fn open(name: &str) -> (Engine, PathBuf) {
+    let config = config(name);
+    let dir = config.base_path.clone();
+    (Engine::bootstrap(config).unwrap(), dir)
+  }
+
+  #[test]
+  fn _1() {
+    let (engine, dir) = open("lfkv-engine-shutdown");
+    engine.shutdown().unwrap();
+    engine.shutdown().unwrap();
+    assert!(engine.new_transaction().is_err());
+    drop(engine);
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn _2() {
+    let (engine, dir) = open("lfkv-engine-crud");
+    let mut page = Page::new();
+    page.writer().write_u32(7).unwrap();
+    engine.put(b"key".to_vec(), page).unwrap();
+
+    let found: Option<Page> = engine.get(&b"key".to_vec()).unwrap();
+    assert_eq!(found.unwrap().scanner().read_u32().unwrap(), 7);
+    let missing: Option<Page> = engine.get(&b"missing".to_vec()).unwrap();
+    assert!(missing.is_none());
+
+    let mut page = Page::new();
+    page.writer().write_u32(8).unwrap();
+    engine.put(b"key".to_vec(), page).unwrap();
+    let found: Option<Page> = engine.get(&b"key".to_vec()).unwrap();
+    assert_eq!(found.unwrap().scanner().read_u32().unwrap(), 8);
+
+    drop(engine);
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn _3() {
+    let (engine, dir) = open("lfkv-engine-get-ref");
+    let mut page = Page::new();
+    let payload = (0..4000).map(|i| i as u8).collect::<Vec<_>>();
+    page.writer().write(&payload).unwrap();
+    engine.put(b"large".to_vec(), page).unwrap();
+
+    let cursor = engine.new_transaction().unwrap();
+    let guard = cursor.get_ref(&b"large".to_vec()).unwrap().unwrap();
+    assert_eq!(&guard[1..4001], payload.as_slice());
+    drop(guard);
+    assert!(cursor.get_ref(&b"missing".to_vec()).unwrap().is_none());
+    cursor.commit().unwrap();
+
+    drop(engine);
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn _4() {
+    let (engine, dir) = open("lfkv-engine-wal-reader");
+    engine.put(b"key".to_vec(), Page::new()).unwrap();
+    engine.shutdown().unwrap();
+
+    let records = WalReader::open(dir.join(WAL_PATH))
+      .unwrap()
+      .collect::<crate::Result<Vec<_>>>()
+      .unwrap();
+    assert!(records.windows(2).all(|w| w[0].index.lt(&w[1].index)));
+    let tx_id = records
+      .iter()
+      .find(|r| matches!(r.operation, Operation::Insert(_)))
+      .unwrap()
+      .transaction_id;
+    let ops = records
+      .iter()
+      .filter(|r| r.transaction_id.eq(&tx_id))
+      .map(|r| match r.operation {
+        Operation::Start => "start",
+        Operation::Insert(_) => "insert",
+        Operation::Commit => "commit",
+        _ => "other",
+      })
+      .collect::<Vec<_>>();
+    assert_eq!(ops.first(), Some(&"start"));
+    assert_eq!(ops.last(), Some(&"commit"));
+    assert!(ops.contains(&"insert"));
+    assert!(!ops.contains(&"other"));
+
+    drop(engine);
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn _5() {
+    let (engine, dir) = open("lfkv-engine-abort");
+    engine.put(b"kept".to_vec(), Page::new()).unwrap();
+    let next = engine.freelist.acquire();
+    engine.freelist.insert(next);
+
+    for i in 0..20u32 {
+      let cursor = engine.new_transaction().unwrap();
+      cursor
+        .insert(i.to_be_bytes().to_vec(), Page::new())
+        .unwrap();
+      cursor.abort().unwrap();
+      assert!(cursor.abort().is_err());
+    }
+    assert_eq!(engine.freelist.acquire(), next);
+
+    for i in 0..20u32 {
+      let found: Option<Page> = engine.get(&i.to_be_bytes().to_vec()).unwrap();
+      assert!(found.is_none());
+    }
+    let kept: Option<Page> = engine.get(&b"kept".to_vec()).unwrap();
+    assert!(kept.is_some());
+
+    drop(engine);
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  static DESERIALIZED: AtomicUsize = AtomicUsize::new(0);
+
+  struct Counted(Page);
+  impl Serializable for Counted {
+    fn serialize(&self) -> Result<Page, Error> {
+      Ok(self.0.copy())
+    }
+    fn deserialize(value: &Page) -> Result<Self, Error> {
+      DESERIALIZED.fetch_add(1, Ordering::SeqCst);
+      Ok(Self(value.copy()))
+    }
+  }
+
+  #[test]
+  fn _6() {
+    let (engine, dir) = open("lfkv-engine-scan-filtered");
+    let cursor = engine.new_transaction().unwrap();
+    for (i, size) in [10, 3000, 20, 4000, 30].into_iter().enumerate() {
+      let mut page = Page::new();
+      page.writer().write(&vec![1; size]).unwrap();
+      cursor.insert(vec![b'a' + i as u8], Counted(page)).unwrap();
+    }
+    cursor.commit().unwrap();
+
+    let cursor = engine.new_transaction().unwrap();
+    let mut iter = cursor
+      .scan_filtered::<Counted, _>(&vec![], b"z", |size| size.le(&100))
+      .unwrap();
+    let mut found = vec![];
+    while let Some((key, value)) = iter.try_next().unwrap() {
+      found.push((key, value.0.used_len()));
+    }
+    assert_eq!(
+      found,
+      vec![
+        (b"a".to_vec(), 10),
+        (b"c".to_vec(), 20),
+        (b"e".to_vec(), 30)
+      ]
+    );
+    assert_eq!(DESERIALIZED.load(Ordering::SeqCst), 3);
+    cursor.commit().unwrap();
+
+    drop(engine);
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn _7() {
+    let (engine, dir) = open("lfkv-engine-own-writes");
+    let cursor = engine.new_transaction().unwrap();
+    for i in 0..40u32 {
+      cursor
+        .insert(i.to_be_bytes().to_vec(), Page::new())
+        .unwrap();
+    }
+    for i in (0..40u32).step_by(7) {
+      cursor.remove(&i.to_be_bytes().to_vec()).unwrap();
+    }
+
+    let mut iter = cursor.scan::<Page>(&vec![], &[0xff]).unwrap();
+    let mut found = vec![];
+    while let Some((key, _)) = iter.try_next().unwrap() {
+      found.push(key);
+    }
+    let expected = (0..40u32)
+      .filter(|i| i % 7 != 0)
+      .map(|i| i.to_be_bytes().to_vec())
+      .collect::<Vec<_>>();
+    assert_eq!(found, expected);
+    cursor.commit().unwrap();
+
+    drop(engine);
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn _8() {
+    let mut config = config("lfkv-engine-tx-timeout");
+    config.transaction_timeout = Some(Duration::from_millis(100));
+    let dir = config.base_path.clone();
+    let engine = Engine::bootstrap(config).unwrap();
+
+    let cursor = engine.new_transaction().unwrap();
+    cursor.insert(b"stale".to_vec(), Page::new()).unwrap();
+    std::thread::sleep(Duration::from_millis(400));
+
+    assert!(matches!(
+      cursor.get::<Page>(&b"stale".to_vec()),
+      Err(Error::TransactionTimedOut)
+    ));
+    assert!(matches!(cursor.commit(), Err(Error::TransactionTimedOut)));
+    assert!(engine.transactions.is_empty());
+    drop(cursor);
+
+    let found: Option<Page> = engine.get(&b"stale".to_vec()).unwrap();
+    assert!(found.is_none());
+    engine.put(b"fresh".to_vec(), Page::new()).unwrap();
+    let found: Option<Page> = engine.get(&b"fresh".to_vec()).unwrap();
+    assert!(found.is_some());
+
+    drop(engine);
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn _9() {
+    let (engine, dir) = open("lfkv-engine-scan-stability");
+    let expected = (0..60u32)
+      .map(|i| (i * 4).to_be_bytes().to_vec())
+      .collect::<Vec<_>>();
+    let cursor = engine.new_transaction().unwrap();
+    for key in &expected {
+      cursor.insert(key.clone(), Page::new()).unwrap();
+    }
+    cursor.commit().unwrap();
+
+    let cursor = engine.new_transaction().unwrap();
+    let mut iter = cursor.scan::<Page>(&vec![], &[0xff]).unwrap();
+    let mut found = vec![iter.try_next().unwrap().unwrap().0];
+    let (split_t, split_r) = std::sync::mpsc::channel();
+    std::thread::scope(|scope| {
+      let writer = scope.spawn(|| {
+        let tx = engine.new_transaction().unwrap();
+        for i in 0..60u32 {
+          for offset in 1..4 {
+            let key = (i * 4 + offset).to_be_bytes().to_vec();
+            tx.insert(key, Page::new()).unwrap();
+          }
+          if i.eq(&20) {
+            split_t.send(()).unwrap();
+          }
+        }
+        tx.commit().unwrap();
+      });
+      // the leaves in the path of the scan are split before it moves on,
+      // and the rest are split while it runs.
+      split_r.recv().unwrap();
+      while let Some((key, _)) = iter.try_next().unwrap() {
+        found.push(key);
+      }
+      writer.join().unwrap();
+    });
+    drop(iter);
+    assert_eq!(found, expected);
+    cursor.commit().unwrap();
+
+    let cursor = engine.new_transaction().unwrap();
+    let mut iter = cursor.scan::<Page>(&vec![], &[0xff]).unwrap();
+    let mut count = 0;
+    while iter.try_next().unwrap().is_some() {
+      count += 1;
+    }
+    drop(iter);
+    assert_eq!(count, 240);
+    cursor.commit().unwrap();
+
+    drop(engine);
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn _10() {
+    let (engine, dir) = open("lfkv-engine-tombstones");
+    for key in [b"a", b"b", b"c"] {
+      engine.put(key.to_vec(), Page::new()).unwrap();
+    }
+
+    let cursor = engine.new_transaction().unwrap();
+    cursor.remove(&b"b".to_vec()).unwrap();
+    cursor.remove(&b"c".to_vec()).unwrap();
+    cursor.insert(b"c".to_vec(), Page::new()).unwrap();
+    let mut iter = cursor
+      .scan_with_tombstones::<Page>(&vec![], &[0xff])
+      .unwrap();
+    let mut found = vec![];
+    while let Some((key, value)) = iter.try_next().unwrap() {
+      found.push((key, value.is_some()));
+    }
+    assert_eq!(
+      found,
+      vec![
+        (b"a".to_vec(), true),
+        (b"b".to_vec(), false),
+        (b"c".to_vec(), true)
+      ]
+    );
+    cursor.commit().unwrap();
+
+    let cursor = engine.new_transaction().unwrap();
+    let mut iter = cursor
+      .scan_with_tombstones::<Page>(&vec![], &[0xff])
+      .unwrap();
+    let mut found = vec![];
+    while let Some((key, value)) = iter.try_next().unwrap() {
+      found.push((key, value.is_some()));
+    }
+    assert_eq!(found, vec![(b"a".to_vec(), true), (b"c".to_vec(), true)]);
+    cursor.commit().unwrap();
+
+    drop(engine);
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn _11() {
+    let signed = || {
+      let mut config = config("lfkv-engine-comparator");
+      config.key_comparator = KeyComparator::SignedBigEndianI64;
+      config
+    };
+    let dir = signed().base_path;
+    let engine = Engine::bootstrap(signed()).unwrap();
+    for i in [3i64, -1, 200, i64::MIN, 0, -300, 7] {
+      engine.put(i.to_be_bytes().to_vec(), Page::new()).unwrap();
+    }
+
+    let scan_all = |engine: &Engine| {
+      let (items, _) = engine.scan_page::<Page>(&vec![], &[0xff], 100).unwrap();
+      items
+        .into_iter()
+        .map(|(key, _)| i64::from_be_bytes(key.try_into().unwrap()))
+        .collect::<Vec<_>>()
+    };
+    let expected = vec![i64::MIN, -300, -1, 0, 3, 7, 200];
+    assert_eq!(scan_all(&engine), expected);
+
+    let (first, token) = engine.scan_page::<Page>(&vec![], &[0xff], 3).unwrap();
+    assert_eq!(first.len(), 3);
+    let (rest, _) = engine.scan_resume::<Page>(&token.unwrap(), 100).unwrap();
+    let keys = first
+      .into_iter()
+      .chain(rest)
+      .map(|(key, _)| i64::from_be_bytes(key.try_into().unwrap()))
+      .collect::<Vec<_>>();
+    assert_eq!(keys, expected);
+    drop(engine);
+
+    let engine = Engine::bootstrap(signed()).unwrap();
+    assert_eq!(scan_all(&engine), expected);
+    drop(engine);
+
+    assert!(matches!(
+      Engine::bootstrap(config("lfkv-engine-comparator")),
+      Err(Error::ComparatorMismatch(KeyComparator::SignedBigEndianI64))
+    ));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn _12() {
+    let (engine, dir) = open("lfkv-engine-delete-if");
+    let value = |bytes: &[u8]| {
+      let mut page = Page::new();
+      page.writer().write(bytes).unwrap();
+      page
+    };
+    engine.put(b"lease".to_vec(), value(b"owner-1")).unwrap();
+
+    let cursor = engine.new_transaction().unwrap();
+    assert!(!cursor.delete_if(&b"lease".to_vec(), b"owner-2").unwrap());
+    assert!(!cursor.delete_if(&b"absent".to_vec(), b"owner-1").unwrap());
+    assert!(cursor.get::<Page>(&b"lease".to_vec()).is_ok());
+    assert!(cursor.delete_if(&b"lease".to_vec(), b"owner-1").unwrap());
+    assert!(!cursor.delete_if(&b"lease".to_vec(), b"owner-1").unwrap());
+    cursor.commit().unwrap();
+    assert!(matches!(
+      cursor.delete_if(&b"lease".to_vec(), b"owner-1"),
+      Err(Error::TransactionClosed)
+    ));
+
+    let cursor = engine.new_transaction().unwrap();
+    assert!(matches!(
+      cursor.get::<Page>(&b"lease".to_vec()),
+      Err(Error::NotFound)
+    ));
+    cursor.commit().unwrap();
+
+    engine.put(b"lease".to_vec(), value(b"owner-1")).unwrap();
+    let cursor = engine.new_transaction().unwrap();
+    engine.put(b"lease".to_vec(), value(b"owner-1")).unwrap();
+    assert!(cursor.delete_if(&b"lease".to_vec(), b"owner-1").unwrap());
+    assert!(cursor.would_conflict().unwrap());
+    cursor.abort().unwrap();
+
+    drop(engine);
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn _13() {
+    let dir = config("lfkv-engine-separate-paths").base_path;
+    let wal_dir = dir.with_extension("wal");
+    let separate = || {
+      let mut config = config("lfkv-engine-separate-paths");
+      config.wal_path = Some(wal_dir.join("redo.log"));
+      config
+    };
+    let engine = Engine::bootstrap(separate()).unwrap();
+    for i in 0..10u32 {
+      let mut page = Page::new();
+      page.writer().write_u32(i).unwrap();
+      engine.put(i.to_be_bytes().to_vec(), page).unwrap();
+    }
+    drop(engine);
+    assert!(wal_dir.join("redo.log").exists());
+    assert!(!dir.join(WAL_PATH).exists());
+
+    let engine = Engine::bootstrap(separate()).unwrap();
+    for i in 0..10u32 {
+      let page: Page = engine.get(&i.to_be_bytes().to_vec()).unwrap().unwrap();
+      assert_eq!(page.scanner().read_u32().unwrap(), i);
+    }
+    drop(engine);
+
+    let mut shared = config("lfkv-engine-separate-paths");
+    shared.undo_path = Some(dir.join("..").join(dir.file_name().unwrap()).join(WAL_PATH));
+    assert!(Engine::bootstrap(shared).is_err());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+    std::fs::remove_dir_all(&wal_dir).unwrap();
+  }
+
+  #[test]
+  fn _14() {
+    let (engine, dir) = open("lfkv-engine-scan-corruption");
+    let cursor = engine.new_transaction().unwrap();
+    for i in 0..30u32 {
+      cursor
+        .insert(i.to_be_bytes().to_vec(), Page::new())
+        .unwrap();
+    }
+    cursor.commit().unwrap();
+
+    let cursor = engine.new_transaction().unwrap();
+    let mut dump = vec![];
+    cursor.dump_tree(&mut dump).unwrap();
+    cursor.commit().unwrap();
+    let leaves = String::from_utf8(dump)
+      .unwrap()
+      .lines()
+      .filter(|line| line.contains("leaf"))
+      .map(|line| {
+        let line = line.trim_start().trim_start_matches('[');
+        line[..line.find(']').unwrap()].parse::<usize>().unwrap()
+      })
+      .collect::<Vec<_>>();
+    assert!(leaves.len().gt(&2));
+    engine.shutdown().unwrap();
+    drop(engine);
+
+    // overwrite the type tag of the second leaf on disk.
+    let mut file = std::fs::OpenOptions::new()
+      .read(true)
+      .write(true)
+      .open(dir.join(DISK_PATH))
+      .unwrap();
+    let offset = (leaves[1] * BLOCK_SIZE) as u64;
+    let mut block = [0u8; 18];
+    file.seek(SeekFrom::Start(offset)).unwrap();
+    file.read_exact(&mut block).unwrap();
+    let page_offset = match block[17] {
+      1 => 28,
+      _ => 20,
+    };
+    file
+      .seek(SeekFrom::Start(offset + page_offset + 1))
+      .unwrap();
+    file.write_all(&[0xee]).unwrap();
+    file.sync_all().unwrap();
+    drop(file);
+
+    let mut config = config("lfkv-engine-scan-corruption");
+    config.verify_on_open = VerifyLevel::None;
+    let engine = Engine::bootstrap(config).unwrap();
+    let cursor = engine.new_transaction().unwrap();
+    let mut iter = cursor.scan::<Page>(&vec![], &[0xff]).unwrap();
+    let mut yielded = vec![];
+    let err = loop {
+      match iter.try_next() {
+        Ok(Some((key, _))) => yielded.push(key),
+        Ok(None) => panic!("scan passed the corrupted leaf"),
+        Err(err) => break err,
+      }
+    };
+    assert!(!yielded.is_empty());
+    match err {
+      Error::ScanCorruption { index, last_key } => {
+        assert_eq!(index, leaves[1]);
+        assert_eq!(last_key.as_ref(), yielded.last());
+      }
+      err => panic!("unexpected error {:?}", err),
+    }
+    drop(iter);
+    cursor.commit().unwrap();
+
+    drop(engine);
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn _15() {
+    let (engine, dir) = open("lfkv-engine-group-commit");
+    std::thread::scope(|scope| {
+      for _ in 0..8 {
+        let engine = &engine;
+        scope.spawn(move || {
+          for _ in 0..5 {
+            engine.new_transaction().unwrap().commit().unwrap();
+          }
+        });
+      }
+    });
+
+    let stats = engine.stats();
+    assert!(stats.wal_disk.batch_fsyncs.gt(&0));
+    assert!(stats.commits_per_fsync.gt(&1.0));
+
+    drop(engine);
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+  #[test]
+  fn _16() {
+    let mut config = config("lfkv-engine-in-memory");
+    config.in_memory = true;
+    let dir = config.base_path.clone();
+    let engine = Engine::bootstrap(config).unwrap();
+    for i in 0..200u32 {
+      let mut page = Page::new();
+      page.writer().write_u32(i).unwrap();
+      engine.put(i.to_be_bytes().to_vec(), page).unwrap();
+    }
+    let cursor = engine.new_transaction().unwrap();
+    cursor.remove(&5u32.to_be_bytes().to_vec()).unwrap();
+    cursor.commit().unwrap();
+
+    let page: Page = engine.get(&150u32.to_be_bytes().to_vec()).unwrap().unwrap();
+    assert_eq!(page.scanner().read_u32().unwrap(), 150);
+    assert!(engine
+      .get::<Page>(&5u32.to_be_bytes().to_vec())
+      .unwrap()
+      .is_none());
+
+    let cursor = engine.new_transaction().unwrap();
+    let mut iter = cursor
+      .scan::<Page>(&3u32.to_be_bytes().to_vec(), &8u32.to_be_bytes())
+      .unwrap();
+    let mut scanned = vec![];
+    while let Some((_, page)) = iter.try_next().unwrap() {
+      scanned.push(page.scanner().read_u32().unwrap());
+    }
+    assert_eq!(scanned, vec![3, 4, 6, 7]);
+    drop(iter);
+    cursor.commit().unwrap();
+
+    drop(engine);
+    assert!(!dir.exists());
+  }
+  #[test]
+  fn _17() {
+    let (engine, dir) = open("lfkv-engine-leaf-links");
+    let cursor = engine.new_transaction().unwrap();
+    for i in 0..200u32 {
+      let key = (i * 7919 % 200).to_be_bytes().to_vec();
+      cursor.insert(key, Page::new()).unwrap();
+    }
+    cursor.commit().unwrap();
+
+    let cursor = engine.new_transaction().unwrap();
+    let mut dump = vec![];
+    cursor.dump_tree(&mut dump).unwrap();
+    cursor.commit().unwrap();
+    let link = |s: &str| match s {
+      "None" => None,
+      s => Some(s["Some(".len()..s.len() - 1].parse::<usize>().unwrap()),
+    };
+    let leaves = String::from_utf8(dump)
+      .unwrap()
+      .lines()
+      .filter(|line| line.contains("leaf"))
+      .map(|line| {
+        let line = line.trim_start().trim_start_matches('[');
+        let index = line[..line.find(']').unwrap()].parse::<usize>().unwrap();
+        let prev = &line[line.find("prev=").unwrap() + 5..line.find(" next=").unwrap()];
+        let next = &line[line.find("next=").unwrap() + 5..];
+        (index, (link(prev), link(next)))
+      })
+      .collect::<Vec<_>>();
+    assert!(leaves.len().gt(&2));
+
+    let links = leaves.iter().cloned().collect::<BTreeMap<_, _>>();
+    let mut backward = vec![];
+    let mut current = leaves.last().map(|(index, _)| *index);
+    while let Some(index) = current {
+      backward.push(index);
+      current = links[&index].0;
+    }
+    backward.reverse();
+    let forward = leaves.iter().map(|(index, _)| *index).collect::<Vec<_>>();
+    assert_eq!(backward, forward);
+    for pair in forward.windows(2) {
+      assert_eq!(links[&pair[0]].1, Some(pair[1]));
+    }
+    assert_eq!(links[forward.last().unwrap()].1, None);
+
+    drop(engine);
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+  #[test]
+  fn _18() {
+    let (engine, dir) = open("lfkv-engine-scan-deadline");
+    let cursor = engine.new_transaction().unwrap();
+    for i in 0..100u32 {
+      cursor
+        .insert(i.to_be_bytes().to_vec(), Page::new())
+        .unwrap();
+    }
+    cursor.commit().unwrap();
+    engine.shutdown().unwrap();
+    drop(engine);
+
+    // reopen without verifying so every leaf is read from the disk by the scan.
+    let mut cold = config("lfkv-engine-scan-deadline");
+    cold.verify_on_open = VerifyLevel::None;
+    let engine = Engine::bootstrap(cold).unwrap();
+    engine
+      .buffer_pool
+      .set_disk_fault(FaultSpec::SlowIo(Duration::from_millis(5)));
+    let cursor = engine.new_transaction().unwrap();
+    let deadline = Instant::now() + Duration::from_millis(20);
+    let mut iter = cursor
+      .scan_until::<Page>(&vec![], &[0xff], deadline)
+      .unwrap();
+    let mut yielded = 0;
+    let err = loop {
+      match iter.try_next() {
+        Ok(Some(_)) => yielded += 1,
+        Ok(None) => panic!("scan finished before the deadline"),
+        Err(err) => break err,
+      }
+    };
+    assert!(matches!(err, Error::Timeout));
+    assert!(yielded.gt(&0));
+    assert!(yielded.lt(&100));
+
+    engine
+      .buffer_pool
+      .set_disk_fault(FaultSpec::SlowIo(Duration::ZERO));
+    let token = iter.resume_token();
+    drop(iter);
+    let (rest, _) = cursor.scan_resume::<Page>(&token, 100).unwrap();
+    assert_eq!(yielded + rest.len(), 100);
+    cursor.commit().unwrap();
+
+    drop(engine);
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+  #[test]
+  fn _19() {
+    let (engine, dir) = open("lfkv-engine-scoped");
+    let users = engine.scoped(b"users/".to_vec()).unwrap();
+    let orders = engine.scoped(b"orders/".to_vec()).unwrap();
+    let value = |i: u32| {
+      let mut page = Page::new();
+      page.writer().write_u32(i).unwrap();
+      page
+    };
+    users.insert(b"1", value(1)).unwrap();
+    users.insert(b"2", value(2)).unwrap();
+    orders.insert(b"1", value(10)).unwrap();
+    // neighbours of the users range which must not leak into it.
+    engine.put(b"users".to_vec(), value(100)).unwrap();
+    engine.put(b"users0".to_vec(), value(101)).unwrap();
+
+    let read = |page: Page| page.scanner().read_u32().unwrap();
+    assert_eq!(users.get::<Page>(b"1").unwrap().map(read), Some(1));
+    assert_eq!(orders.get::<Page>(b"1").unwrap().map(read), Some(10));
+    assert!(orders.get::<Page>(b"2").unwrap().is_none());
+
+    let all = |scope: &Scoped| {
+      scope
+        .scan_all::<Page>()
+        .unwrap()
+        .into_iter()
+        .map(|(key, page)| (key, read(page)))
+        .collect::<Vec<_>>()
+    };
+    assert_eq!(all(&users), vec![(b"1".to_vec(), 1), (b"2".to_vec(), 2)]);
+    assert_eq!(all(&orders), vec![(b"1".to_vec(), 10)]);
+    assert_eq!(users.scan::<Page>(b"2", b"").unwrap().len(), 1);
+
+    users.remove(b"1").unwrap();
+    assert_eq!(all(&users), vec![(b"2".to_vec(), 2)]);
+    assert_eq!(orders.get::<Page>(b"1").unwrap().map(read), Some(10));
+    assert!(engine.scoped(vec![0xff, 0xff]).is_err());
+
+    drop(engine);
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+  #[test]
+  fn _20() {
+    let (engine, dir) = open("lfkv-engine-swap");
+    let value = |i: u32| {
+      let mut page = Page::new();
+      page.writer().write_u32(i).unwrap();
+      page
+    };
+    let read = |key: &[u8]| {
+      engine
+        .get::<Page>(&key.to_vec())
+        .unwrap()
+        .map(|page| page.scanner().read_u32().unwrap())
+    };
+    engine.put(b"a".to_vec(), value(1)).unwrap();
+    engine.put(b"b".to_vec(), value(2)).unwrap();
+
+    let cursor = engine.new_transaction().unwrap();
+    cursor.swap(&b"a".to_vec(), &b"b".to_vec()).unwrap();
+    assert_eq!((read(b"a"), read(b"b")), (Some(1), Some(2)));
+    cursor.commit().unwrap();
+    assert_eq!((read(b"a"), read(b"b")), (Some(2), Some(1)));
+
+    let cursor = engine.new_transaction().unwrap();
+    cursor.swap(&b"a".to_vec(), &b"c".to_vec()).unwrap();
+    assert_eq!(cursor.get::<Page>(&b"c".to_vec()).unwrap(), value(2));
+    cursor.abort().unwrap();
+    assert_eq!((read(b"a"), read(b"c")), (Some(2), None));
+
+    let cursor = engine.new_transaction().unwrap();
+    cursor.swap(&b"a".to_vec(), &b"c".to_vec()).unwrap();
+    cursor.commit().unwrap();
+    assert_eq!((read(b"a"), read(b"c")), (None, Some(2)));
+
+    drop(engine);
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+  #[test]
+  fn _21() {
+    let mut slow = config("lfkv-engine-commit-ack");
+    slow.group_commit_delay = Duration::from_millis(300);
+    let dir = slow.base_path.clone();
+    let engine = Engine::bootstrap(slow).unwrap();
+
+    // a synchronous commit restarts the group commit delay.
+    engine.new_transaction().unwrap().commit().unwrap();
+    let (ack_t, ack_r) = crossbeam::channel::unbounded();
+    let started = Instant::now();
+    for i in 0..5u32 {
+      let cursor = engine.new_transaction().unwrap();
+      cursor
+        .insert(i.to_be_bytes().to_vec(), Page::new())
+        .unwrap();
+      let ack_t = ack_t.clone();
+      cursor
+        .commit_with_ack(move |result| ack_t.send((i, result)).unwrap())
+        .unwrap();
+    }
+    assert!(started.elapsed().lt(&Duration::from_millis(300)));
+    assert!(ack_r.is_empty());
+    assert!(engine
+      .get::<Page>(&4u32.to_be_bytes().to_vec())
+      .unwrap()
+      .is_some());
+
+    let mut acked = (0..5)
+      .map(|_| ack_r.recv_timeout(Duration::from_secs(5)).unwrap())
+      .map(|(i, result)| result.map(|_| i).unwrap())
+      .collect::<Vec<_>>();
+    assert!(started.elapsed().ge(&Duration::from_millis(300)));
+    acked.sort();
+    assert_eq!(acked, vec![0, 1, 2, 3, 4]);
+    assert!(engine.stats().wal_disk.batch_fsyncs.gt(&0));
+
+    drop(engine);
+    assert!(ack_r.try_recv().is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+  #[test]
+  fn _22() {
+    let (engine, dir) = open("lfkv-engine-get-many");
+    let cursor = engine.new_transaction().unwrap();
+    for i in (0..200u32).step_by(2) {
+      let mut page = Page::new();
+      page.writer().write_u32(i).unwrap();
+      cursor.insert(i.to_be_bytes().to_vec(), page).unwrap();
+    }
+    cursor.commit().unwrap();
+
+    let keys = (0..200u32)
+      .map(|i| (i * 7919 % 200).to_be_bytes().to_vec())
+      .collect::<Vec<_>>();
+    let cursor = engine.new_transaction().unwrap();
+    let values = cursor.get_many::<Page>(&keys).unwrap();
+    cursor.commit().unwrap();
+    for (key, value) in keys.iter().zip(values) {
+      let i = u32::from_be_bytes(key[..].try_into().unwrap());
+      let value = value.map(|page| page.scanner().read_u32().unwrap());
+      assert_eq!(value, (i % 2 == 0).then_some(i));
+    }
+
+    drop(engine);
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn _23() {
+    let (engine, dir) = open("lfkv-engine-drop-keyspace");
+    let value = |i: u32| {
+      let mut page = Page::new();
+      page.writer().write_u32(i).unwrap();
+      page
+    };
+    let cursor = engine.new_transaction().unwrap();
+    for (name, count) in [(&b"a"[..], 10u32), (b"b", 40), (b"c", 5)] {
+      let keyspace = cursor.keyspace(name);
+      for i in 0..count {
+        keyspace.insert(i.to_be_bytes().to_vec(), value(i)).unwrap();
+      }
+    }
+    cursor.commit().unwrap();
+
+    let counts = |engine: &Engine| {
+      engine
+        .iter_keyspaces()
+        .unwrap()
+        .into_iter()
+        .map(|info| (info.name, info.key_count))
+        .collect::<Vec<_>>()
+    };
+    let referenced = |engine: &Engine| {
+      let cursor = engine.new_transaction().unwrap();
+      let referenced = cursor.verify().unwrap();
+      cursor.commit().unwrap();
+      referenced
+    };
+    assert_eq!(
+      counts(&engine),
+      vec![
+        (vec![], 0),
+        (b"a".to_vec(), 10),
+        (b"b".to_vec(), 40),
+        (b"c".to_vec(), 5)
+      ]
+    );
+    assert_eq!(engine.key_count().unwrap(), 55);
+
+    let before = referenced(&engine);
+    assert!(engine.drop_keyspace(b"b").unwrap());
+    assert!(!engine.drop_keyspace(b"b").unwrap());
+    assert!(engine.drop_keyspace(b"").is_err());
+    let after = referenced(&engine);
+    let freed = before.difference(&after).copied().collect::<BTreeSet<_>>();
+    // 40 values, 5 leaves and the root.
+    assert_eq!(freed.len(), 46);
+    assert_eq!(
+      counts(&engine),
+      vec![(vec![], 0), (b"a".to_vec(), 10), (b"c".to_vec(), 5)]
+    );
+    assert_eq!(engine.key_count().unwrap(), 15);
+
+    let cursor = engine.new_transaction().unwrap();
+    let read = |page: Page| page.scanner().read_u32().unwrap();
+    assert_eq!(
+      read(
+        cursor
+          .keyspace(b"a")
+          .get(&9u32.to_be_bytes().to_vec())
+          .unwrap()
+      ),
+      9
+    );
+    assert_eq!(
+      read(
+        cursor
+          .keyspace(b"c")
+          .get(&4u32.to_be_bytes().to_vec())
+          .unwrap()
+      ),
+      4
+    );
+    assert!(cursor
+      .keyspace(b"b")
+      .get::<Page>(&0u32.to_be_bytes().to_vec())
+      .is_err());
+
+    // the freed pages are handed out again before the file grows.
+    let keyspace = cursor.keyspace(b"d");
+    for i in 0..20u32 {
+      keyspace.insert(i.to_be_bytes().to_vec(), value(i)).unwrap();
+    }
+    cursor.commit().unwrap();
+    let added = referenced(&engine)
+      .difference(&after)
+      .copied()
+      .collect::<Vec<_>>();
+    assert!(added.iter().all(|i| freed.contains(i)));
+
+    drop(engine);
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn _24() {
+    let mut limited = config("lfkv-engine-max-transactions");
+    limited.max_open_transactions = Some(3);
+    let dir = limited.base_path.clone();
+    let engine = Engine::bootstrap(limited).unwrap();
+    assert_eq!(engine.open_transaction_count(), 0);
+
+    let mut open = (0..3)
+      .map(|_| engine.new_transaction().unwrap())
+      .collect::<Vec<_>>();
+    assert_eq!(engine.open_transaction_count(), 3);
+    assert!(matches!(
+      engine.new_transaction(),
+      Err(Error::TooManyOpenTransactions(3))
+    ));
+
+    open.pop().unwrap().commit().unwrap();
+    assert_eq!(engine.open_transaction_count(), 2);
+    open.push(engine.new_transaction().unwrap());
+    open.pop().unwrap().abort().unwrap();
+    assert_eq!(engine.open_transaction_count(), 2);
+
+    drop(open);
+    assert_eq!(engine.open_transaction_count(), 0);
+    drop(engine);
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn _25() {
+    let (engine, dir) = open("lfkv-engine-key-state");
+    let mut page = Page::new();
+    page.writer().write_u32(7).unwrap();
+    engine.put(b"committed".to_vec(), page.copy()).unwrap();
+
+    let cursor = engine.new_transaction().unwrap();
+    assert_eq!(
+      cursor.get_state::<Page>(&b"committed".to_vec()).unwrap(),
+      KeyState::Present(page.copy())
+    );
+    cursor.remove(&b"committed".to_vec()).unwrap();
+    assert_eq!(
+      cursor.get_state::<Page>(&b"committed".to_vec()).unwrap(),
+      KeyState::DeletedInTx
+    );
+    assert_eq!(
+      cursor.get_state::<Page>(&b"never".to_vec()).unwrap(),
+      KeyState::Absent
+    );
+
+    // inserted and deleted by the transaction, so absent before it.
+    cursor.insert(b"temporary".to_vec(), page.copy()).unwrap();
+    cursor.remove(&b"temporary".to_vec()).unwrap();
+    assert_eq!(
+      cursor.get_state::<Page>(&b"temporary".to_vec()).unwrap(),
+      KeyState::Absent
+    );
+    cursor.commit().unwrap();
+
+    let cursor = engine.new_transaction().unwrap();
+    assert_eq!(
+      cursor.get_state::<Page>(&b"committed".to_vec()).unwrap(),
+      KeyState::Absent
+    );
+    cursor.commit().unwrap();
+
+    drop(engine);
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn _26() {
+    let (engine, dir) = open("lfkv-engine-clean-shutdown");
+    engine.put(b"before".to_vec(), Page::new()).unwrap();
+    engine.shutdown().unwrap();
+    drop(engine);
+
+    let reopen = || Engine::bootstrap(config("lfkv-engine-clean-shutdown")).unwrap();
+    let engine = reopen();
+    assert_eq!(engine.wal.redone(), None);
+    assert!(engine.get::<Page>(&b"before".to_vec()).unwrap().is_some());
+
+    for i in 0..5u32 {
+      engine.put(i.to_be_bytes().to_vec(), Page::new()).unwrap();
+    }
+    // files as a crash would leave them, with the commits only in the wal.
+    let crashed = dir.with_extension("crashed");
+    std::fs::create_dir_all(&crashed).unwrap();
+    for file in [DISK_PATH, WAL_PATH, UNDO_PATH] {
+      std::fs::copy(dir.join(file), crashed.join(file)).unwrap();
+    }
+    drop(engine);
+
+    for file in [DISK_PATH, WAL_PATH, UNDO_PATH] {
+      std::fs::copy(crashed.join(file), dir.join(file)).unwrap();
+    }
+    std::fs::remove_file(dir.join(format!("{WAL_PATH}.clean"))).unwrap();
+    let engine = reopen();
+    assert!(engine.wal.redone().is_some_and(|redone| redone.gt(&0)));
+    for i in 0..5u32 {
+      let key = i.to_be_bytes().to_vec();
+      assert!(engine.get::<Page>(&key).unwrap().is_some());
+    }
+
+    drop(engine);
+    std::fs::remove_dir_all(&dir).unwrap();
+    std::fs::remove_dir_all(&crashed).unwrap();
+  }
+
+  #[test]
+  fn _27() {
+    let (engine, dir) = open("lfkv-engine-scan-map");
+    let cursor = engine.new_transaction().unwrap();
+    for i in 0..40u8 {
+      let mut page = Page::new();
+      page.writer().write(&[i * 3, 0xff]).unwrap();
+      cursor.insert(vec![i], page).unwrap();
+    }
+    cursor.commit().unwrap();
+
+    let cursor = engine.new_transaction().unwrap();
+    // the raw page starts with its marker byte.
+    let projected = cursor
+      .scan_map(&vec![5], &[30], |key, value| (key[0], value[1]))
+      .unwrap()
+      .collect::<Result<Vec<_>, _>>()
+      .unwrap();
+    assert_eq!(projected, (5..30u8).map(|i| (i, i * 3)).collect::<Vec<_>>());
+    assert_eq!(
+      cursor
+        .scan_map(&vec![50], &[60], |_, v| v[1])
+        .unwrap()
+        .count(),
+      0
+    );
+    cursor.commit().unwrap();
+
+    drop(engine);
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn _28() {
+    let (engine, dir) = open("lfkv-engine-snapshot");
+    let value = |i: u32| {
+      let mut page = Page::new();
+      page.writer().write_u32(i).unwrap();
+      page
+    };
+    let read = |page: Page| page.scanner().read_u32().unwrap();
+    for i in 0..20u32 {
+      engine.put(i.to_be_bytes().to_vec(), value(i)).unwrap();
+    }
+
+    let snapshot = engine.create_snapshot().unwrap();
+    let key = |i: u32| i.to_be_bytes().to_vec();
+    engine.put(key(3), value(300)).unwrap();
+    engine.put(key(50), value(50)).unwrap();
+    let cursor = engine.new_transaction().unwrap();
+    for i in 10..20u32 {
+      cursor.remove(&key(i)).unwrap();
+    }
+    cursor.commit().unwrap();
+
+    assert_eq!(snapshot.get::<Page>(&key(3)).unwrap().map(read), Some(3));
+    assert_eq!(snapshot.get::<Page>(&key(15)).unwrap().map(read), Some(15));
+    assert!(snapshot.get::<Page>(&key(50)).unwrap().is_none());
+    let mut iter = snapshot.scan::<Page>(&key(0), &key(100)).unwrap();
+    let mut scanned = vec![];
+    while let Some((_, page)) = iter.try_next().unwrap() {
+      scanned.push(read(page));
+    }
+    drop(iter);
+    assert_eq!(scanned, (0..20).collect::<Vec<_>>());
+
+    assert_eq!(engine.get::<Page>(&key(3)).unwrap().map(read), Some(300));
+    assert!(engine.get::<Page>(&key(15)).unwrap().is_none());
+    snapshot.release();
+
+    let snapshot = engine.create_snapshot().unwrap();
+    assert_eq!(snapshot.get::<Page>(&key(50)).unwrap().map(read), Some(50));
+    drop(snapshot);
+
+    drop(engine);
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn _29() {
+    let (engine, dir) = open("lfkv-engine-prefetch");
+    let count = 400u32;
+    let cursor = engine.new_transaction().unwrap();
+    cursor
+      .put_all_sorted((0..count).map(|i| (i.to_be_bytes().to_vec(), Page::new())))
+      .unwrap();
+    cursor.commit().unwrap();
+    drop(engine);
+
+    // reopened, so the scan starts from a cold cache.
+    let mut reopened = config("lfkv-engine-prefetch");
+    reopened.verify_on_open = VerifyLevel::None;
+    let engine = Engine::bootstrap(reopened).unwrap();
+    let reads = || engine.stats().data_disk.io.count();
+    let cursor = engine.new_transaction().unwrap();
+    let before = reads();
+    let depth = 16;
+    let mut iter = cursor
+      .scan_prefetch::<Page>(&vec![], &[0xff], depth)
+      .unwrap();
+    let mut keys = vec![iter.try_next().unwrap().unwrap().0];
+    std::thread::sleep(Duration::from_millis(200));
+    // each leaf ahead is read with its values while the first is consumed.
+    assert!(reads().saturating_sub(before).gt(&(depth * 2)));
+
+    while let Some((key, _)) = iter.try_next().unwrap() {
+      keys.push(key);
+    }
+    drop(iter);
+    cursor.commit().unwrap();
+    let expected = (0..count)
+      .map(|i| i.to_be_bytes().to_vec())
+      .collect::<Vec<_>>();
+    assert_eq!(keys, expected);
+
+    drop(engine);
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn _30() {
+    let (engine, dir) = open("lfkv-engine-write-conflict");
+    let key = b"contended".to_vec();
+    engine.put(key.clone(), Page::new()).unwrap();
+    engine.put(b"other".to_vec(), Page::new()).unwrap();
+
+    let holder = engine.new_transaction().unwrap();
+    holder.insert(key.clone(), Page::new()).unwrap();
+
+    let cursor = engine.new_transaction().unwrap();
+    match cursor.insert(key.clone(), Page::new()) {
+      Err(Error::WriteConflict { key: k, holder: h }) => {
+        assert_eq!(k, key);
+        assert_eq!(h, holder.get_id());
+      }
+      other => panic!("expected a write conflict, got {other:?}"),
+    }
+    assert!(matches!(
+      cursor.remove(&key),
+      Err(Error::WriteConflict { .. })
+    ));
+    // other keys are not held.
+    cursor.remove(&b"other".to_vec()).unwrap();
+    cursor.commit().unwrap();
+
+    holder.commit().unwrap();
+    let cursor = engine.new_transaction().unwrap();
+    cursor.insert(key.clone(), Page::new()).unwrap();
+    cursor.commit().unwrap();
+
+    drop(engine);
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn _31() {
+    let (engine, dir) = open("lfkv-engine-key-filter");
+    let count = 300u32;
+    let cursor = engine.new_transaction().unwrap();
+    cursor
+      .put_all_sorted((0..count).map(|i| (i.to_be_bytes().to_vec(), Page::new())))
+      .unwrap();
+    cursor.commit().unwrap();
+    drop(engine);
+
+    // reopened, so every value read by the scan is read from the disk.
+    let mut reopened = config("lfkv-engine-key-filter");
+    reopened.verify_on_open = VerifyLevel::None;
+    let engine = Engine::bootstrap(reopened).unwrap();
+    let reads = || engine.stats().data_disk.io.count();
+    let cursor = engine.new_transaction().unwrap();
+    let before = reads();
+    let is_matched = |key: &[u8]| u32::from_be_bytes(key.try_into().unwrap()) % 10 == 0;
+    let mut iter = cursor
+      .scan_keys_where::<Page, _>(&vec![], &[0xff], is_matched)
+      .unwrap();
+    let mut keys = vec![];
+    while let Some((key, _)) = iter.try_next().unwrap() {
+      keys.push(u32::from_be_bytes(key.try_into().unwrap()));
+    }
+    drop(iter);
+    assert_eq!(keys, (0..count).step_by(10).collect::<Vec<_>>());
+    // the leaves and the values of the matched keys only.
+    assert!(reads().saturating_sub(before).lt(&(count as usize / 2)));
+    cursor.commit().unwrap();
+
+    drop(engine);
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn _32() {
+    let (engine, dir) = open("lfkv-engine-disk-full");
+    engine.put(b"before".to_vec(), Page::new()).unwrap();
+
+    engine.wal.set_disk_fault(FaultSpec::DiskFull);
+    assert!(matches!(
+      engine.put(b"failed".to_vec(), Page::new()),
+      Err(Error::DiskFull)
+    ));
+    assert!(engine.is_disk_full());
+    // rejected before anything is written.
+    let cursor = engine.new_transaction().unwrap();
+    assert!(matches!(
+      cursor.insert(b"rejected".to_vec(), Page::new()),
+      Err(Error::DiskFull)
+    ));
+    cursor.abort().unwrap();
+    assert!(engine.get::<Page>(&b"before".to_vec()).unwrap().is_some());
+    assert!(engine.get::<Page>(&b"rejected".to_vec()).unwrap().is_none());
+
+    engine.wal.set_disk_fault(FaultSpec::SlowIo(Duration::ZERO));
+    assert!(matches!(
+      engine.put(b"after".to_vec(), Page::new()),
+      Err(Error::DiskFull)
+    ));
+    engine.resume_writes();
+    assert!(!engine.is_disk_full());
+    engine.put(b"after".to_vec(), Page::new()).unwrap();
+    drop(engine);
+
+    let engine = Engine::bootstrap(config("lfkv-engine-disk-full")).unwrap();
+    assert!(engine.get::<Page>(&b"before".to_vec()).unwrap().is_some());
+    assert!(engine.get::<Page>(&b"after".to_vec()).unwrap().is_some());
+    assert!(engine.get::<Page>(&b"rejected".to_vec()).unwrap().is_none());
+    drop(engine);
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn _33() {
+    let (engine, dir) = open("lfkv-engine-read-committed");
+    let value = |i: u32| {
+      let mut page = Page::new();
+      page.writer().write_u32(i).unwrap();
+      page
+    };
+    let read = |page: Page| page.scanner().read_u32().unwrap();
+    let key = b"k".to_vec();
+    engine.put(key.clone(), value(1)).unwrap();
+
+    let snapshot = engine.new_transaction().unwrap();
+    let committed = engine
+      .new_transaction()
+      .unwrap()
+      .with_isolation(IsolationLevel::ReadCommitted);
+    assert_eq!(read(snapshot.get(&key).unwrap()), 1);
+    assert_eq!(read(committed.get(&key).unwrap()), 1);
+
+    engine.put(key.clone(), value(2)).unwrap();
+    engine.put(b"n".to_vec(), value(3)).unwrap();
+    assert_eq!(read(snapshot.get(&key).unwrap()), 1);
+    assert_eq!(read(committed.get(&key).unwrap()), 2);
+    let count = |cursor: &Cursor| {
+      let mut iter = cursor.scan::<Page>(&vec![], &[0xff]).unwrap();
+      let mut count = 0;
+      while iter.try_next().unwrap().is_some() {
+        count += 1;
+      }
+      count
+    };
+    assert_eq!(count(&snapshot), 1);
+    assert_eq!(count(&committed), 2);
+
+    // its own writes are still read back.
+    committed.insert(key.clone(), value(4)).unwrap();
+    assert_eq!(read(committed.get(&key).unwrap()), 4);
+    snapshot.commit().unwrap();
+    committed.commit().unwrap();
+    assert_eq!(read(engine.get(&key).unwrap().unwrap()), 4);
+
+    drop(engine);
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn _34() {
+    let (engine, dir) = open("lfkv-engine-compact-wal");
+    for i in 0..40u32 {
+      engine.put(i.to_be_bytes().to_vec(), Page::new()).unwrap();
+    }
+    let reclaimed = engine.compact_wal().unwrap();
+    assert!(reclaimed.gt(&0));
+    assert_eq!(reclaimed % WAL_PAGE_SIZE, 0);
+    for i in 40..45u32 {
+      engine.put(i.to_be_bytes().to_vec(), Page::new()).unwrap();
+    }
+
+    // files as a crash would leave them, with the freed pages in front.
+    let crashed = dir.with_extension("crashed");
+    std::fs::create_dir_all(&crashed).unwrap();
+    for file in [DISK_PATH, WAL_PATH, UNDO_PATH] {
+      std::fs::copy(dir.join(file), crashed.join(file)).unwrap();
+    }
+    drop(engine);
+    for file in [DISK_PATH, WAL_PATH, UNDO_PATH] {
+      std::fs::copy(crashed.join(file), dir.join(file)).unwrap();
+    }
+    std::fs::remove_file(dir.join(format!("{WAL_PATH}.clean"))).unwrap();
+
+    let engine = Engine::bootstrap(config("lfkv-engine-compact-wal")).unwrap();
+    assert!(engine.wal.redone().is_some_and(|redone| redone.gt(&0)));
+    for i in 0..45u32 {
+      let key = i.to_be_bytes().to_vec();
+      assert!(engine.get::<Page>(&key).unwrap().is_some());
+    }
+
+    drop(engine);
+    std::fs::remove_dir_all(&dir).unwrap();
+    std::fs::remove_dir_all(&crashed).unwrap();
+  }
+
+  #[test]
+  fn _35() {
+    let (engine, dir) = open("lfkv-engine-scan-multi");
+    let count = 300u32;
+    let cursor = engine.new_transaction().unwrap();
+    cursor
+      .put_all_sorted((0..count).map(|i| (i.to_be_bytes().to_vec(), Page::new())))
+      .unwrap();
+    cursor.commit().unwrap();
+    drop(engine);
+
+    // reopened, so every value read by the scan is read from the disk.
+    let mut reopened = config("lfkv-engine-scan-multi");
+    reopened.verify_on_open = VerifyLevel::None;
+    let engine = Engine::bootstrap(reopened).unwrap();
+    let reads = || engine.stats().data_disk.io.count();
+    let key = |i: u32| i.to_be_bytes().to_vec();
+    let cursor = engine.new_transaction().unwrap();
+    let before = reads();
+    let ranges = vec![
+      (key(200), key(205)),
+      (key(10), key(20)),
+      (key(100), key(110)),
+    ];
+    let mut entries = vec![];
+    for entry in cursor.scan_multi::<Page>(ranges).unwrap() {
+      let (range, key, _) = entry.unwrap();
+      entries.push((range, u32::from_be_bytes(key.try_into().unwrap())));
+    }
+    let expected = (10..20)
+      .map(|i| (1, i))
+      .chain((100..110).map(|i| (2, i)))
+      .chain((200..205).map(|i| (0, i)))
+      .collect::<Vec<_>>();
+    assert_eq!(entries, expected);
+    // the leaves up to the last range and the values of the matched keys only.
+    assert!(reads().saturating_sub(before).lt(&(count as usize / 2)));
+
+    // a key in overlapping ranges is yielded for each of them.
+    let overlapping = vec![(key(0), key(3)), (key(2), key(4)), (key(9), key(9))];
+    let entries = cursor
+      .scan_multi::<Page>(overlapping)
+      .unwrap()
+      .map(|entry| {
+        let (range, key, _) = entry.unwrap();
+        (range, u32::from_be_bytes(key.try_into().unwrap()))
+      })
+      .collect::<Vec<_>>();
+    assert_eq!(entries, vec![(0, 0), (0, 1), (0, 2), (1, 2), (1, 3)]);
+    cursor.commit().unwrap();
+
+    drop(engine);
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn _36() {
+    let (engine, dir) = open("lfkv-engine-write-conflict");
+    engine.put(b"seed".to_vec(), Page::new()).unwrap();
+    let barrier = std::sync::Barrier::new(2);
+    let conflicts = AtomicUsize::new(0);
+    std::thread::scope(|scope| {
+      for offset in 0..2u32 {
+        let (engine, barrier, conflicts) = (&engine, &barrier, &conflicts);
+        scope.spawn(move || {
+          let mut first = true;
+          loop {
+            let cursor = engine.new_transaction().unwrap();
+            // disjoint keys in the only leaf of the tree.
+            let inserted = (0..3u32).try_for_each(|i| {
+              cursor.insert((i * 2 + offset).to_be_bytes().to_vec(), Page::new())
+            });
+            // both are pending before either commits on the first try.
+            if std::mem::take(&mut first) {
+              barrier.wait();
+            }
+            match inserted.and_then(|_| cursor.commit()) {
+              Ok(()) => return,
+              Err(err) => {
+                assert!(matches!(err, Error::WriteConflict { .. }));
+                conflicts.fetch_add(1, Ordering::SeqCst);
+                cursor.abort().unwrap();
+              }
+            }
+          }
+        });
+      }
+    });
+    assert!(conflicts.load(Ordering::SeqCst).gt(&0));
+
+    for i in 0..6u32 {
+      let key = i.to_be_bytes().to_vec();
+      assert!(engine.get::<Page>(&key).unwrap().is_some());
+    }
+    assert!(engine.get::<Page>(&b"seed".to_vec()).unwrap().is_some());
+
+    drop(engine);
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn _37() {
+    struct ManualClock(AtomicUsize);
+    impl Clock for ManualClock {
+      fn now(&self) -> Duration {
+        Duration::from_millis(self.0.load(Ordering::SeqCst) as u64)
+      }
+    }
+
+    let (mut engine, dir) = open("lfkv-engine-ttl");
+    let clock = Arc::new(ManualClock(AtomicUsize::new(1000)));
+    engine.set_clock(clock.clone());
+    let cursor = engine.new_transaction().unwrap();
+    cursor
+      .set_ttl(b"short".to_vec(), Page::new(), Duration::from_millis(50))
+      .unwrap();
+    cursor
+      .set_ttl(b"long".to_vec(), Page::new(), Duration::from_secs(60))
+      .unwrap();
+    cursor.commit().unwrap();
+
+    let keys = |cursor: &Cursor| {
+      let mut iter = cursor.scan_unexpired::<Page>(&vec![], &[0xff]).unwrap();
+      let mut keys = vec![];
+      while let Some((key, _)) = iter.try_next().unwrap() {
+        keys.push(key);
+      }
+      keys
+    };
+    let cursor = engine.new_transaction().unwrap();
+    assert!(cursor.get_unexpired::<Page>(&b"short".to_vec()).is_ok());
+    assert_eq!(keys(&cursor), vec![b"long".to_vec(), b"short".to_vec()]);
+    cursor.commit().unwrap();
+
+    clock.0.fetch_add(50, Ordering::SeqCst);
+    let cursor = engine.new_transaction().unwrap();
+    assert!(matches!(
+      cursor.get_unexpired::<Page>(&b"short".to_vec()),
+      Err(Error::NotFound)
+    ));
+    assert_eq!(keys(&cursor), vec![b"long".to_vec()]);
+    let free = engine.freelist.len();
+    assert_eq!(cursor.purge_expired(&vec![], &[0xff]).unwrap(), 1);
+    cursor.commit().unwrap();
+    // the page of the expired value is free once the purge is committed.
+    assert_eq!(engine.freelist.len(), free + 1);
+    assert!(engine.get::<Page>(&b"short".to_vec()).unwrap().is_none());
+    assert!(engine.get::<Page>(&b"long".to_vec()).unwrap().is_some());
+
+    drop(engine);
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn _38() {
+    let (engine, dir) = open("lfkv-engine-put-if-version");
+    let key = b"versioned".to_vec();
+    let cursor = engine.new_transaction().unwrap();
+    assert!(cursor
+      .put_if_version(key.clone(), None, Page::new())
+      .unwrap());
+    // written by this transaction, so no longer absent.
+    assert!(!cursor
+      .put_if_version(key.clone(), None, Page::new())
+      .unwrap());
+    assert!(cursor
+      .put_if_version(key.clone(), Some(0), Page::new())
+      .unwrap());
+    cursor.commit().unwrap();
+
+    let cursor = engine.new_transaction().unwrap();
+    let (version, _) = cursor.get_with_version::<Page>(&key).unwrap();
+    assert!(version.gt(&0));
+    assert!(cursor
+      .put_if_version(key.clone(), Some(version), Page::new())
+      .unwrap());
+    cursor.commit().unwrap();
+
+    // the version read before the last commit is stale.
+    let cursor = engine.new_transaction().unwrap();
+    assert!(!cursor
+      .put_if_version(key.clone(), Some(version), Page::new())
+      .unwrap());
+    assert!(!cursor
+      .put_if_version(key.clone(), None, Page::new())
+      .unwrap());
+    let (current, _) = cursor.get_with_version::<Page>(&key).unwrap();
+    assert!(current.gt(&version));
+    assert!(cursor
+      .put_if_version(key, Some(current), Page::new())
+      .unwrap());
+    cursor.commit().unwrap();
+
+    drop(engine);
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn _39() {
+    let (engine, dir) = open("lfkv-engine-undo-last");
+    let value = |v: u32| {
+      let mut page = Page::new();
+      page.writer().write_u32(v).unwrap();
+      page
+    };
+    let cursor = engine.new_transaction().unwrap().with_undo();
+    cursor.insert(b"first".to_vec(), value(1)).unwrap();
+    cursor.insert(b"second".to_vec(), value(2)).unwrap();
+    assert!(cursor.undo_last().unwrap());
+    cursor.commit().unwrap();
+
+    let found: Option<Page> = engine.get(&b"first".to_vec()).unwrap();
+    assert_eq!(found.unwrap().scanner().read_u32().unwrap(), 1);
+    let found: Option<Page> = engine.get(&b"second".to_vec()).unwrap();
+    assert!(found.is_none());
+
+    let cursor = engine.new_transaction().unwrap().with_undo();
+    cursor.insert(b"first".to_vec(), value(3)).unwrap();
+    cursor.remove(&b"first".to_vec()).unwrap();
+    assert!(cursor.undo_last().unwrap());
+    assert!(cursor.undo_last().unwrap());
+    assert!(!cursor.undo_last().unwrap());
+    cursor.commit().unwrap();
+    let found: Option<Page> = engine.get(&b"first".to_vec()).unwrap();
+    assert_eq!(found.unwrap().scanner().read_u32().unwrap(), 1);
+
+    // without undo the writes keep no previous pages.
+    let cursor = engine.new_transaction().unwrap();
+    cursor.insert(b"third".to_vec(), value(4)).unwrap();
+    assert!(matches!(cursor.undo_last(), Err(Error::Invalid)));
+    cursor.commit().unwrap();
+
+    drop(engine);
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn _40() {
+    let (engine, dir) = open("lfkv-engine-rewrite-in-tx");
+    let value = |v: u32| {
+      let mut page = Page::new();
+      page.writer().write_u32(v).unwrap();
+      page
+    };
+    let read = |page: Page| page.scanner().read_u32().unwrap();
+    let key = b"rewritten".to_vec();
+    engine.put(key.clone(), value(1)).unwrap();
+
+    let snapshot = engine.create_snapshot().unwrap();
+    let cursor = engine.new_transaction().unwrap();
+    for v in 2..5 {
+      cursor.insert(key.clone(), value(v)).unwrap();
+    }
+    cursor.commit().unwrap();
+
+    // the versions in between were never committed,
+    // so the older reader still finds the one before the transaction.
+    assert_eq!(snapshot.get::<Page>(&key).unwrap().map(read), Some(1));
+    assert_eq!(engine.get::<Page>(&key).unwrap().map(read), Some(4));
+    snapshot.release();
+
+    drop(engine);
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn _41() {
+    let (engine, dir) = open("lfkv-engine-key-count");
+    let key = |i: u32| i.to_be_bytes().to_vec();
+    let scanned = |engine: &Engine| {
+      let cursor = engine.new_transaction().unwrap();
+      let mut iter = cursor.scan::<Page>(&key(0), &key(u32::MAX)).unwrap();
+      let mut count = 0;
+      while iter.try_next().unwrap().is_some() {
+        count += 1;
+      }
+      drop(iter);
+      cursor.commit().unwrap();
+      count
+    };
+
+    let cursor = engine.new_transaction().unwrap();
+    for i in 0..300 {
+      cursor.insert(key(i), Page::new()).unwrap();
+    }
+    cursor.commit().unwrap();
+    assert_eq!(engine.key_count().unwrap(), 300);
+
+    // overwrites do not count, and deletes of missing keys fail.
+    let cursor = engine.new_transaction().unwrap();
+    for i in 250..350 {
+      cursor.insert(key(i), Page::new()).unwrap();
+    }
+    for i in 0..100 {
+      cursor.remove(&key(i)).unwrap();
+    }
+    assert!(cursor.remove(&key(1000)).is_err());
+    assert_eq!(cursor.key_count().unwrap(), 250);
+    assert_eq!(engine.key_count().unwrap(), 300);
+    cursor.commit().unwrap();
+    assert_eq!(engine.key_count().unwrap(), 250);
+    assert_eq!(engine.key_count().unwrap(), scanned(&engine));
+
+    let cursor = engine.new_transaction().unwrap();
+    for i in 1000..1010 {
+      cursor.insert(key(i), Page::new()).unwrap();
+    }
+    cursor.abort().unwrap();
+    assert_eq!(engine.key_count().unwrap(), 250);
+
+    // the count is stored on a clean shutdown and counted again after a crash.
+    drop(engine);
+    let engine = Engine::bootstrap(config("lfkv-engine-key-count")).unwrap();
+    assert!(engine.wal.redone().is_none());
+    assert_eq!(engine.key_count().unwrap(), 250);
+
+    let crashed = dir.with_extension("crashed");
+    std::fs::create_dir_all(&crashed).unwrap();
+    engine.put(key(2000), Page::new()).unwrap();
+    for file in [DISK_PATH, WAL_PATH, UNDO_PATH] {
+      std::fs::copy(dir.join(file), crashed.join(file)).unwrap();
+    }
+    drop(engine);
+    for file in [DISK_PATH, WAL_PATH, UNDO_PATH] {
+      std::fs::copy(crashed.join(file), dir.join(file)).unwrap();
+    }
+    std::fs::remove_file(dir.join(format!("{WAL_PATH}.clean"))).unwrap();
+
+    let engine = Engine::bootstrap(config("lfkv-engine-key-count")).unwrap();
+    assert!(engine.wal.redone().is_some());
+    assert_eq!(engine.key_count().unwrap(), 251);
+    assert_eq!(engine.key_count().unwrap(), scanned(&engine));
+
+    drop(engine);
+    std::fs::remove_dir_all(&dir).unwrap();
+    std::fs::remove_dir_all(&crashed).unwrap();
+  }
+
+  #[test]
+  fn _42() {
+    let (engine, dir) = open("lfkv-engine-resume-token");
+    for i in 0..10u32 {
+      engine.put(i.to_be_bytes().to_vec(), Page::new()).unwrap();
+    }
+    let (first, token) = engine.scan_page::<Page>(&vec![], &[0xff], 4).unwrap();
+    assert_eq!(first.len(), 4);
+    let token = token.unwrap();
+
+    let mut forged = token.to_bytes();
+    forged[..8].copy_from_slice(&(u32::MAX as u64).to_be_bytes());
+    let forged = ResumeToken::from_bytes(&forged).unwrap();
+    assert!(matches!(
+      engine.scan_resume::<Page>(&forged, 100),
+      Err(Error::Invalid)
+    ));
+
+    let token = ResumeToken::from_bytes(&token.to_bytes()).unwrap();
+    let (rest, _) = engine.scan_resume::<Page>(&token, 100).unwrap();
+    assert_eq!(rest.len(), 6);
+
+    drop(engine);
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn _43() {
+    let config = config("lfkv-engine-torn-wal");
+    let dir = config.base_path.clone();
+    std::fs::create_dir_all(&dir).unwrap();
+    let value = |v: u32| {
+      let mut page = Page::new();
+      page.writer().write_u32(v).unwrap();
+      page
+    };
+    let entry = |records: Vec<LogRecord>, first: usize| {
+      let mut entry = LogEntry::new();
+      for (i, mut record) in records.into_iter().enumerate() {
+        record.assign_id(first + i);
+        entry.append(record);
+      }
+      entry
+    };
+
+    let wal = Finder::<WAL_PAGE_SIZE>::open(FinderConfig {
+      path: dir.join(WAL_PATH),
+      batch_delay: Duration::from_millis(10),
+      batch_size: 100,
+      file_mode: None,
+      sync_mode: Default::default(),
+      io_budget: None,
+      in_memory: false,
+    })
+    .unwrap();
+    let committed = vec![
+      LogRecord::new_start(1),
+      LogRecord::new_insert(1, 100, value(7)),
+      LogRecord::new_commit(1),
+    ];
+    wal.write_from(0, &entry(committed, 1)).unwrap();
+    wal
+      .write_from(1, &entry(vec![LogRecord::new_start(2)], 4))
+      .unwrap();
+    // the page is written again with the rest of the transaction, and the
+    // write stops in the middle of the insert, before the commit.
+    let torn = vec![
+      LogRecord::new_start(2),
+      LogRecord::new_insert(2, 101, value(8)),
+      LogRecord::new_commit(2),
+    ];
+    wal.set_fault(FaultSpec::TornWrite(1 + 8 + 17 + 17 + 600));
+    assert!(wal.write_from(1, &entry(torn, 4)).is_err());
+    wal.fsync().unwrap();
+    wal.close();
+
+    let engine = Engine::bootstrap(config).unwrap();
+    assert_eq!(engine.wal.redone(), Some(1));
+    let page = engine.buffer_pool.get_latest(100).unwrap();
+    assert_eq!(page.scanner().read_u32().unwrap(), 7);
+    assert!(matches!(
+      engine.buffer_pool.get_latest(101),
+      Err(Error::NotFound)
+    ));
+
+    drop(engine);
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn _44() {
+    let (engine, dir) = open("lfkv-engine-reclaim-orphans");
+    engine.put(b"committed".to_vec(), Page::new()).unwrap();
+    let free = engine.freelist.len();
+
+    // the insert allocates a page for its value, and the checkpoint
+    // writes its records to the wal without a commit.
+    let cursor = engine.new_transaction().unwrap();
+    cursor.insert(b"open".to_vec(), Page::new()).unwrap();
+    engine.checkpoint().unwrap();
+    let crashed = dir.with_extension("crashed");
+    std::fs::create_dir_all(&crashed).unwrap();
+    for file in [DISK_PATH, WAL_PATH, UNDO_PATH] {
+      std::fs::copy(dir.join(file), crashed.join(file)).unwrap();
+    }
+    drop(cursor);
+    drop(engine);
+    for file in [DISK_PATH, WAL_PATH, UNDO_PATH] {
+      std::fs::copy(crashed.join(file), dir.join(file)).unwrap();
+    }
+    let _ = std::fs::remove_file(dir.join(format!("{WAL_PATH}.clean")));
+
+    let engine = Engine::bootstrap(config("lfkv-engine-reclaim-orphans")).unwrap();
+    assert!(engine.get::<Page>(&b"open".to_vec()).unwrap().is_none());
+    assert!(engine
+      .get::<Page>(&b"committed".to_vec())
+      .unwrap()
+      .is_some());
+    assert_eq!(engine.freelist.len(), free + 1);
+
+    drop(engine);
+    std::fs::remove_dir_all(&dir).unwrap();
+    std::fs::remove_dir_all(&crashed).unwrap();
+  }
+
+  #[test]
+  fn _45() {
+    let (sorted, sorted_dir) = open("lfkv-engine-sorted-fast-path");
+    let (unsorted, unsorted_dir) = open("lfkv-engine-sorted-slow-path");
+    let count = 400u32;
+    let pairs = || (0..count).map(|i| (i.to_be_bytes().to_vec(), Page::new()));
+
+    let cursor = sorted.new_transaction().unwrap();
+    cursor.put_all_sorted(pairs()).unwrap();
+    cursor.commit().unwrap();
+    let cursor = unsorted.new_transaction().unwrap();
+    for (key, value) in pairs() {
+      cursor.insert(key, value).unwrap();
+    }
+    cursor.commit().unwrap();
+
+    for i in 0..count {
+      let key = i.to_be_bytes().to_vec();
+      assert!(sorted.get::<Page>(&key).unwrap().is_some());
+    }
+    assert_eq!(sorted.key_count().unwrap(), count as usize);
+    // both trees hold the same value pages, so the rest are tree nodes.
+    let pages = |engine: &Engine| {
+      let cursor = engine.new_transaction().unwrap();
+      let pages = cursor.verify().unwrap().len();
+      cursor.abort().unwrap();
+      pages
+    };
+    let (fast, slow) = (pages(&sorted), pages(&unsorted));
+    assert!(
+      fast.le(&slow),
+      "sorted path used {fast} pages, unsorted {slow}"
+    );
+
+    drop(sorted);
+    drop(unsorted);
+    std::fs::remove_dir_all(&sorted_dir).unwrap();
+    std::fs::remove_dir_all(&unsorted_dir).unwrap();
+  }
+
+  #[test]
+  fn _46() {
+    let (engine, dir) = open("lfkv-engine-repair-wal");
+    for i in 0..300u32 {
+      engine.put(i.to_be_bytes().to_vec(), Page::new()).unwrap();
+    }
+    drop(engine);
+    std::fs::remove_file(dir.join(WAL_PATH)).unwrap();
+
+    let mut repair = config("lfkv-engine-repair-wal");
+    repair.repair = true;
+    let engine = Engine::bootstrap(repair).unwrap();
+    for i in 0..300u32 {
+      let key = i.to_be_bytes().to_vec();
+      assert!(engine.get::<Page>(&key).unwrap().is_some());
+    }
+    // commits after the repair continue from the data file.
+    engine.put(b"after".to_vec(), Page::new()).unwrap();
+    drop(engine);
+
+    let engine = Engine::bootstrap(config("lfkv-engine-repair-wal")).unwrap();
+    assert!(engine.get::<Page>(&b"after".to_vec()).unwrap().is_some());
+    assert!(engine
+      .get::<Page>(&299u32.to_be_bytes().to_vec())
+      .unwrap()
+      .is_some());
+    drop(engine);
+
+    // the nodes past the header read as not found, so the repair is refused
+    // and the wal is left empty.
+    std::fs::remove_file(dir.join(WAL_PATH)).unwrap();
+    let mut data = std::fs::read(dir.join(DISK_PATH)).unwrap();
+    data[BLOCK_SIZE..].fill(0);
+    std::fs::write(dir.join(DISK_PATH), data).unwrap();
+    let mut repair = config("lfkv-engine-repair-wal");
+    repair.repair = true;
+    assert!(matches!(
+      Engine::bootstrap(repair),
+      Err(Error::Corruption(_))
+    ));
+    assert_eq!(std::fs::metadata(dir.join(WAL_PATH)).unwrap().len(), 0);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn _47() {
+    let (engine, dir) = open("lfkv-engine-batch-thread");
+    let value = |i: u32| {
+      let mut page = Page::new();
+      page.writer().write_u32(i).unwrap();
+      page
+    };
+    let batch = std::thread::spawn(move || {
+      let mut batch = WriteBatch::new();
+      for i in 0..700u32 {
+        batch.put(i.to_be_bytes().to_vec(), &value(i)).unwrap();
+      }
+      for i in 0..300u32 {
+        batch.delete(i.to_be_bytes().to_vec());
+      }
+      batch
+    })
+    .join()
+    .unwrap();
+    assert_eq!(batch.len(), 1000);
+
+    let cursor = engine.new_transaction().unwrap();
+    cursor.apply(batch).unwrap();
+    cursor.commit().unwrap();
+
+    for i in 0..700u32 {
+      let found = engine.get::<Page>(&i.to_be_bytes().to_vec()).unwrap();
+      match i.lt(&300) {
+        true => assert!(found.is_none()),
+        false => assert_eq!(found.unwrap().scanner().read_u32().unwrap(), i),
+      }
+    }
+    assert_eq!(engine.key_count().unwrap(), 400);
+
+    drop(engine);
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn _48() {
+    let (engine, dir) = open("lfkv-engine-scan-channel");
+    let count = 400u32;
+    let cursor = engine.new_transaction().unwrap();
+    for i in 0..count {
+      let mut page = Page::new();
+      page.writer().write_u32(i).unwrap();
+      cursor.insert(i.to_be_bytes().to_vec(), page).unwrap();
+    }
+    cursor.commit().unwrap();
+
+    let cursor = engine.new_transaction().unwrap();
+    let rx = cursor.scan_channel::<Page>(&[], &[0xff]).unwrap();
+    // committed after the scan starts, so it is not in the snapshot.
+    engine.put(vec![0xfe], Page::new()).unwrap();
+    let mut expected = 0u32;
+    for item in rx {
+      let (key, value) = item.unwrap();
+      assert_eq!(key, expected.to_be_bytes().to_vec());
+      assert_eq!(value.scanner().read_u32().unwrap(), expected);
+      expected += 1;
+    }
+    assert_eq!(expected, count);
+
+    drop(cursor);
+    drop(engine);
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn _49() {
+    let (engine, dir) = open("lfkv-engine-scan-channel-stop");
+    let count = 400u32;
+    let cursor = engine.new_transaction().unwrap();
+    cursor
+      .put_all_sorted((0..count).map(|i| (i.to_be_bytes().to_vec(), Page::new())))
+      .unwrap();
+    cursor.commit().unwrap();
+    drop(engine);
+
+    // reopened, so every value read by the producer is a disk read.
+    let mut reopened = config("lfkv-engine-scan-channel-stop");
+    reopened.verify_on_open = VerifyLevel::None;
+    let engine = Engine::bootstrap(reopened).unwrap();
+    let reads = || engine.stats().data_disk.io.count();
+    let cursor = engine.new_transaction().unwrap();
+    let before = reads();
+    let rx = cursor.scan_channel::<Page>(&[], &[0xff]).unwrap();
+    rx.recv().unwrap().unwrap();
+    drop(rx);
+    std::thread::sleep(Duration::from_millis(200));
+    // the producer stops at the full channel instead of reading the range.
+    assert!(reads().saturating_sub(before).lt(&(count as usize)));
+
+    let rx = cursor.scan_channel::<Page>(&[], &[0xff]).unwrap();
+    rx.recv().unwrap().unwrap();
+    cursor.abort().unwrap();
+    let items = rx.iter().collect::<Vec<_>>();
+    assert!(items.len().lt(&(count as usize)));
+    assert!(matches!(items.last(), Some(Err(Error::TransactionClosed))));
+
+    drop(engine);
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn _50() {
+    let (engine, dir) = open("lfkv-engine-read-cache");
+    for i in 0..300u32 {
+      engine.put(i.to_be_bytes().to_vec(), Page::new()).unwrap();
+    }
+    let key = 150u32.to_be_bytes().to_vec();
+
+    let cursor = engine.new_transaction().unwrap();
+    let before = engine.buffer_pool.read_count();
+    cursor.get::<Page>(&key).unwrap();
+    let first = engine.buffer_pool.read_count();
+    assert!(first.gt(&before));
+    cursor.get::<Page>(&key).unwrap();
+    assert_eq!(engine.buffer_pool.read_count(), first);
+
+    // the write of the cursor invalidates the cached value.
+    cursor.insert(key.clone(), Page::new()).unwrap();
+    cursor.get::<Page>(&key).unwrap();
+    assert!(engine.buffer_pool.read_count().gt(&first));
+
+    // so does a write through the handle of the default keyspace.
+    let mut page = Page::new();
+    page.writer().write(&[9]).unwrap();
+    cursor
+      .keyspace(b"")
+      .insert(key.clone(), page.copy())
+      .unwrap();
+    assert_eq!(cursor.get::<Page>(&key).unwrap(), page);
+    cursor.keyspace(b"").remove(&key).unwrap();
+    assert!(matches!(cursor.get::<Page>(&key), Err(Error::NotFound)));
+    cursor.commit().unwrap();
+
+    drop(engine);
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn _51() {
+    let (engine, dir) = open("lfkv-engine-checkpoint-id");
+    for i in 0..300u32 {
+      engine.put(i.to_be_bytes().to_vec(), Page::new()).unwrap();
+    }
+    let checkpoint = engine.checkpoint().unwrap();
+    assert!(checkpoint.gt(&0));
+    for i in 300..310u32 {
+      engine.put(i.to_be_bytes().to_vec(), Page::new()).unwrap();
+    }
+    assert!(engine.checkpoint().unwrap().ge(&checkpoint));
+    for i in 310..320u32 {
+      engine.put(i.to_be_bytes().to_vec(), Page::new()).unwrap();
+    }
+    let crashed = dir.with_extension("crashed");
+    std::fs::create_dir_all(&crashed).unwrap();
+    for file in [DISK_PATH, WAL_PATH, UNDO_PATH] {
+      std::fs::copy(dir.join(file), crashed.join(file)).unwrap();
+    }
+    drop(engine);
+    for file in [DISK_PATH, WAL_PATH, UNDO_PATH] {
+      std::fs::copy(crashed.join(file), dir.join(file)).unwrap();
+    }
+    let _ = std::fs::remove_file(dir.join(format!("{WAL_PATH}.clean")));
+
+    // only the inserts from the last checkpoint are redone.
+    let mut records = WalReader::open(dir.join(WAL_PATH))
+      .unwrap()
+      .collect::<crate::Result<Vec<_>>>()
+      .unwrap();
+    // the file wraps around, so the records are ordered by index.
+    records.sort_by_key(|r| r.index);
+    let last = records
+      .iter()
+      .filter_map(|r| match r.operation {
+        Operation::Checkpoint(i) => Some(i),
+        _ => None,
+      })
+      .max()
+      .unwrap();
+    let inserts = |from: usize| {
+      records
+        .iter()
+        .filter(|r| r.index.ge(&from) && matches!(r.operation, Operation::Insert(_)))
+        .count()
+    };
+    assert!(last.ge(&checkpoint));
+    let engine = Engine::bootstrap(config("lfkv-engine-checkpoint-id")).unwrap();
+    assert_eq!(engine.wal.redone(), Some(inserts(last)));
+    assert!(inserts(last).lt(&inserts(0)));
+    for i in 0..320u32 {
+      let key = i.to_be_bytes().to_vec();
+      assert!(engine.get::<Page>(&key).unwrap().is_some());
+    }
+
+    drop(engine);
+    std::fs::remove_dir_all(&dir).unwrap();
+    std::fs::remove_dir_all(&crashed).unwrap();
+  }
+
+  #[test]
+  fn _52() {
+    let (engine, dir) = open("lfkv-engine-wal-before-data");
+    engine.put(b"committed".to_vec(), Page::new()).unwrap();
+    // so the replay does not redo the committed pages over the flushed ones.
+    engine.checkpoint().unwrap();
+
+    // the records of the insert are buffered, and the wal fails to write them.
+    let cursor = engine.new_transaction().unwrap();
+    cursor.insert(b"open".to_vec(), Page::new()).unwrap();
+    engine.wal.set_disk_fault(FaultSpec::DiskFull);
+    assert!(engine.checkpoint().is_err());
+    let crashed = dir.with_extension("crashed");
+    std::fs::create_dir_all(&crashed).unwrap();
+    for file in [DISK_PATH, WAL_PATH, UNDO_PATH] {
+      std::fs::copy(dir.join(file), crashed.join(file)).unwrap();
+    }
+    engine.wal.set_disk_fault(FaultSpec::SlowIo(Duration::ZERO));
+    drop(cursor);
+    drop(engine);
+    for file in [DISK_PATH, WAL_PATH, UNDO_PATH] {
+      std::fs::copy(crashed.join(file), dir.join(file)).unwrap();
+    }
+    let _ = std::fs::remove_file(dir.join(format!("{WAL_PATH}.clean")));
+
+    // the data pages of the insert were not flushed without their records.
+    let engine = Engine::bootstrap(config("lfkv-engine-wal-before-data")).unwrap();
+    assert!(engine.get::<Page>(&b"open".to_vec()).unwrap().is_none());
+    assert!(engine
+      .get::<Page>(&b"committed".to_vec())
+      .unwrap()
+      .is_some());
+
+    drop(engine);
+    std::fs::remove_dir_all(&dir).unwrap();
+    std::fs::remove_dir_all(&crashed).unwrap();
+  }
+
+  #[test]
+  fn _53() {
+    let (engine, dir) = open("lfkv-engine-range-map");
+    let value = |i: u32| {
+      let mut page = Page::new();
+      page.writer().write_u32(i).unwrap();
+      page
+    };
+    let cursor = engine.new_transaction().unwrap();
+    // inserted in reverse, so the map is ordered by the scan.
+    for i in (0..300u32).rev() {
+      cursor.insert(i.to_be_bytes().to_vec(), value(i)).unwrap();
+    }
+    cursor.commit().unwrap();
+
+    let cursor = engine.new_transaction().unwrap();
+    let (start, end) = (100u32.to_be_bytes().to_vec(), 200u32.to_be_bytes());
+    let map = cursor.range_map::<Page>(&start, &end, None).unwrap();
+    assert_eq!(map.len(), 100);
+    for ((key, value), i) in map.into_iter().zip(100..200u32) {
+      assert_eq!(key, i.to_be_bytes().to_vec());
+      assert_eq!(value.scanner().read_u32().unwrap(), i);
+    }
+
+    assert_eq!(
+      cursor
+        .range_map::<Page>(&start, &end, Some(100))
+        .unwrap()
+        .len(),
+      100
+    );
+    assert!(matches!(
+      cursor.range_map::<Page>(&start, &end, Some(99)),
+      Err(Error::ResultTooLarge)
+    ));
+    cursor.commit().unwrap();
+
+    drop(engine);
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn _54() {
+    let (engine, dir) = open("lfkv-engine-scan-items");
+    let count = 400u32;
+    let cursor = engine.new_transaction().unwrap();
+    cursor
+      .put_all_sorted((0..count).map(|i| (i.to_be_bytes().to_vec(), Page::new())))
+      .unwrap();
+    cursor.commit().unwrap();
+
+    let cursor = engine.new_transaction().unwrap();
+    let items = cursor
+      .scan::<Page>(&vec![], &[0xff])
+      .unwrap()
+      .items()
+      .collect::<crate::Result<Vec<_>>>()
+      .unwrap();
+    let keys = items.into_iter().map(|(key, _)| key).collect::<Vec<_>>();
+    assert_eq!(
+      keys,
+      (0..count)
+        .map(|i| i.to_be_bytes().to_vec())
+        .collect::<Vec<_>>()
+    );
+
+    // the deadline fails the scan at the second leaf, and nothing follows.
+    let mut items = cursor
+      .scan_until::<Page>(&vec![], &[0xff], Instant::now())
+      .unwrap()
+      .items();
+    let mut read = 0;
+    let err = loop {
+      match items.next().unwrap() {
+        Ok(_) => read += 1,
+        Err(err) => break err,
+      }
+    };
+    assert!(matches!(err, Error::Timeout));
+    assert!(read.lt(&count));
+    assert!(items.next().is_none());
+    cursor.commit().unwrap();
+
+    drop(engine);
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn _55() {
+    let (engine, dir) = open("lfkv-engine-insert-returning");
+    let value = |i: u32| {
+      let mut page = Page::new();
+      page.writer().write_u32(i).unwrap();
+      page
+    };
+    let read = |page: Page| page.scanner().read_u32().unwrap();
+    let key = b"k".to_vec();
+    engine.put(key.clone(), value(1)).unwrap();
+
+    let cursor = engine.new_transaction().unwrap();
+    let previous = cursor.insert_returning(key.clone(), value(2)).unwrap();
+    assert_eq!(previous.map(read), Some(1));
+    // the pending write of the cursor is the previous value of the next one.
+    let previous = cursor.insert_returning(key.clone(), value(3)).unwrap();
+    assert_eq!(previous.map(read), Some(2));
+    assert!(cursor
+      .insert_returning(b"fresh".to_vec(), value(4))
+      .unwrap()
+      .is_none());
+    cursor.commit().unwrap();
+
+    assert_eq!(engine.get::<Page>(&key).unwrap().map(read), Some(3));
+    assert_eq!(
+      engine.get::<Page>(&b"fresh".to_vec()).unwrap().map(read),
+      Some(4)
+    );
+
+    drop(engine);
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn _56() {
+    let value = |i: u8| {
+      let mut page = Page::new();
+      page.writer().write(&[i]).unwrap();
+      page
+    };
+    let change = |key: &[u8], value: Option<Page>| LogicalLog {
+      keyspace: vec![],
+      key: key.to_vec(),
+      value,
+    };
+
+    // physical mode logs no key changes.
+    let (engine, dir) = open("lfkv-engine-physical-log");
+    engine.put(b"a".to_vec(), value(1)).unwrap();
+    let remove = |engine: &Engine, key: &[u8]| {
+      let cursor = engine.new_transaction().unwrap();
+      cursor.remove(&key.to_vec()).unwrap();
+      cursor.commit().unwrap();
+    };
+    remove(&engine, b"a");
+    drop(engine);
+    assert!(WalReader::open(dir.join(WAL_PATH))
+      .unwrap()
+      .all(|r| !matches!(
+        r.unwrap().operation,
+        Operation::LogicalInsert(_) | Operation::LogicalDelete(_)
+      )));
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    let logical = || {
+      let mut config = config("lfkv-engine-logical-log");
+      config.log_mode = LogMode::Logical;
+      config
+    };
+    let dir = logical().base_path;
+    let engine = Engine::bootstrap(logical()).unwrap();
+    engine.put(b"a".to_vec(), value(1)).unwrap();
+    engine.put(b"b".to_vec(), value(2)).unwrap();
+
+    // only the keys the tree does not hold yet are written.
+    let cursor = engine.new_transaction().unwrap();
+    let changes = vec![
+      change(b"a", Some(value(5))),
+      change(b"b", Some(value(2))),
+      change(b"c", None),
+      change(b"a", Some(value(3))),
+      change(b"b", None),
+    ];
+    assert_eq!(apply_changes(&cursor, changes).unwrap(), 2);
+    cursor.commit().unwrap();
+    let read = |engine: &Engine, key: &[u8]| {
+      engine
+        .get::<Page>(&key.to_vec())
+        .unwrap()
+        .map(|page| page.scanner().read().unwrap())
+    };
+    assert_eq!(read(&engine, b"a"), Some(3));
+    assert_eq!(read(&engine, b"b"), None);
+
+    engine.put(b"c".to_vec(), value(4)).unwrap();
+    remove(&engine, b"a");
+    let crashed = dir.with_extension("crashed");
+    std::fs::create_dir_all(&crashed).unwrap();
+    for file in [DISK_PATH, WAL_PATH, UNDO_PATH] {
+      std::fs::copy(dir.join(file), crashed.join(file)).unwrap();
+    }
+    drop(engine);
+    for file in [DISK_PATH, WAL_PATH, UNDO_PATH] {
+      std::fs::copy(crashed.join(file), dir.join(file)).unwrap();
+    }
+    let _ = std::fs::remove_file(dir.join(format!("{WAL_PATH}.clean")));
+    assert!(WalReader::open(dir.join(WAL_PATH))
+      .unwrap()
+      .any(|r| matches!(r.unwrap().operation, Operation::LogicalDelete(_))));
+
+    // the replayed changes agree with the redone pages.
+    let engine = Engine::bootstrap(logical()).unwrap();
+    assert!(engine.wal.take_replayed_changes().is_empty());
+    assert_eq!(read(&engine, b"a"), None);
+    assert_eq!(read(&engine, b"b"), None);
+    assert_eq!(read(&engine, b"c"), Some(4));
+    assert_eq!(engine.key_count().unwrap(), 1);
+
+    drop(engine);
+    std::fs::remove_dir_all(&dir).unwrap();
+    std::fs::remove_dir_all(&crashed).unwrap();
+  }
+
+  #[test]
+  fn _57() {
+    let (engine, dir) = open("lfkv-engine-keyspace-isolation");
+    let value = |i: u32| {
+      let mut page = Page::new();
+      page.writer().write_u32(i).unwrap();
+      page
+    };
+    let read = |page: Page| page.scanner().read_u32().unwrap();
+    let key = b"k".to_vec();
+    let cursor = engine.new_transaction().unwrap();
+    cursor.insert(key.clone(), value(0)).unwrap();
+    cursor.keyspace(b"a").insert(key.clone(), value(1)).unwrap();
+    cursor.keyspace(b"b").insert(key.clone(), value(2)).unwrap();
+    cursor.commit().unwrap();
+
+    let cursor = engine.new_transaction().unwrap();
+    assert_eq!(read(cursor.get(&key).unwrap()), 0);
+    assert_eq!(read(cursor.keyspace(b"a").get(&key).unwrap()), 1);
+    assert_eq!(read(cursor.keyspace(b"b").get(&key).unwrap()), 2);
+
+    // writes to the key in one keyspace leave the others as they are.
+    cursor.keyspace(b"a").insert(key.clone(), value(3)).unwrap();
+    cursor.keyspace(b"b").remove(&key).unwrap();
+    cursor.commit().unwrap();
+    drop(engine);
+
+    let engine = Engine::bootstrap(config("lfkv-engine-keyspace-isolation")).unwrap();
+    let cursor = engine.new_transaction().unwrap();
+    assert_eq!(read(cursor.get(&key).unwrap()), 0);
+    assert_eq!(read(cursor.keyspace(b"a").get(&key).unwrap()), 3);
+    assert!(matches!(
+      cursor.keyspace(b"b").get::<Page>(&key),
+      Err(Error::NotFound)
+    ));
+    let mut iter = cursor
+      .keyspace(b"a")
+      .scan::<Page>(&vec![], &[0xff])
+      .unwrap();
+    assert_eq!(
+      iter.try_next().unwrap().map(|(k, v)| (k, read(v))),
+      Some((key, 3))
+    );
+    assert!(iter.try_next().unwrap().is_none());
+    drop(iter);
+    cursor.commit().unwrap();
+
+    drop(engine);
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn _58() {
+    let mut slow = config("lfkv-engine-ack-durable");
+    slow.group_commit_delay = Duration::from_millis(300);
+    let dir = slow.base_path.clone();
+    let engine = Engine::bootstrap(slow).unwrap();
+    engine.put(b"removed".to_vec(), Page::new()).unwrap();
+    let (ack_t, ack_r) = crossbeam::channel::unbounded();
+
+    // the released page of the value is kept until the commit is durable.
+    let free = engine.freelist.len();
+    let cursor = engine.new_transaction().unwrap();
+    cursor.remove(&b"removed".to_vec()).unwrap();
+    let cloned = ack_t.clone();
+    cursor
+      .commit_with_ack(move |result| cloned.send(result).unwrap())
+      .unwrap();
+    assert_eq!(engine.freelist.len(), free);
+    ack_r.recv_timeout(Duration::from_secs(5)).unwrap().unwrap();
+    assert_eq!(engine.freelist.len(), free + 1);
+
+    // a full disk on the fsync suspends the writes like a commit does.
+    engine.wal.set_disk_fault(FaultSpec::DiskFull);
+    let cursor = engine.new_transaction().unwrap();
+    cursor.insert(b"failed".to_vec(), Page::new()).unwrap();
+    let cloned = ack_t.clone();
+    cursor
+      .commit_with_ack(move |result| cloned.send(result).unwrap())
+      .unwrap();
+    assert!(matches!(
+      ack_r.recv_timeout(Duration::from_secs(5)).unwrap(),
+      Err(Error::DiskFull)
+    ));
+    assert!(engine.is_disk_full());
+
+    // a transaction which wrote nothing is discarded and acked.
+    let cursor = engine.new_transaction().unwrap();
+    assert!(engine.get::<Page>(&b"removed".to_vec()).unwrap().is_none());
+    cursor
+      .commit_with_ack(move |result| ack_t.send(result).unwrap())
+      .unwrap();
+    ack_r.recv_timeout(Duration::from_secs(5)).unwrap().unwrap();
+
+    engine.wal.set_disk_fault(FaultSpec::SlowIo(Duration::ZERO));
+    engine.resume_writes();
+    drop(engine);
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn _59() {
+    let mut small = config("lfkv-engine-resume-expired");
+    // a ring of a few undo records, which the writes below wrap.
+    small.undo_file_size = 1 << 16;
+    let dir = small.base_path.clone();
+    let engine = Engine::bootstrap(small).unwrap();
+    for i in 0..10u32 {
+      engine.put(i.to_be_bytes().to_vec(), Page::new()).unwrap();
+    }
+    let (_, token) = engine.scan_page::<Page>(&vec![], &[0xff], 4).unwrap();
+    let token = token.unwrap();
+    for _ in 0..10 {
+      for i in 0..10u32 {
+        engine.put(i.to_be_bytes().to_vec(), Page::new()).unwrap();
+      }
+    }
+
+    assert!(matches!(
+      engine.scan_resume::<Page>(&token, 100),
+      Err(Error::SnapshotExpired)
+    ));
+
+    drop(engine);
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn _60() {
+    let (engine, dir) = open("lfkv-engine-dump-keyspaces");
+    let cursor = engine.new_transaction().unwrap();
+    cursor.insert(b"plain".to_vec(), Page::new()).unwrap();
+    cursor
+      .keyspace(b"users")
+      .insert(b"alice".to_vec(), Page::new())
+      .unwrap();
+    cursor.commit().unwrap();
+
+    let cursor = engine.new_transaction().unwrap();
+    let mut dump = vec![];
+    cursor.dump_tree(&mut dump).unwrap();
+    let dump = String::from_utf8(dump).unwrap();
+    let lines = dump.lines().collect::<Vec<_>>();
+    assert_eq!(lines[0], "keyspace \"\"");
+    assert!(lines[1].contains("leaf keys=[\"plain->"));
+    assert_eq!(lines[2], "keyspace \"users\"");
+    assert!(lines[3].contains("leaf keys=[\"alice->"));
+    cursor.commit().unwrap();
+
+    // fails after commit like every other cursor read.
+    assert!(matches!(
+      cursor.dump_tree(&mut vec![]),
+      Err(Error::TransactionClosed)
+    ));
+
+    drop(engine);
+    std::fs::remove_dir_all(&dir).unwrap();
   }
 }
@@ -1,5 +1,13 @@
 use thiserror::Error;
 
+use crate::cursor::KeyComparator;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiskOp {
+  Read,
+  Write,
+}
+
 #[derive(Debug, Error)]
 pub enum Error {
   #[error("not found")]
@@ -14,14 +22,70 @@ pub enum Error {
   #[error("io error")]
   IO(std::io::Error),
 
+  #[error("disk {op:?} failed at page {index}")]
+  Disk {
+    op: DiskOp,
+    index: usize,
+    source: std::io::Error,
+  },
+
   #[error("end of file")]
   EOF,
 
+  /// The key has an uncommitted write by the holder transaction.
+  #[error("write conflict on key {key:?} held by transaction {holder}")]
+  WriteConflict { key: Vec<u8>, holder: usize },
+
   #[error("transaction already closed")]
   TransactionClosed,
 
+  #[error("transaction aborted after exceeding the timeout")]
+  TransactionTimedOut,
+
   #[error("engine unavailable")]
   EngineUnavailable,
+
+  #[error("result too large")]
+  ResultTooLarge,
+
+  #[error("database is locked by another process")]
+  AlreadyLocked,
+
+  #[error("keys are not sorted in ascending order")]
+  NotSorted,
+
+  #[error("corruption detected: {0}")]
+  Corruption(String),
+
+  /// Leaf of a scan which could not be read, with the last key
+  /// the scan yielded before it, to resume past the leaf.
+  #[error("corruption detected at leaf {index} of the scan")]
+  ScanCorruption {
+    index: usize,
+    last_key: Option<Vec<u8>>,
+  },
+
+  #[error("database was created with the key comparator {0:?}")]
+  ComparatorMismatch(KeyComparator),
+
+  /// Files written in another on-disk format, 0 for files older than the
+  /// format version.
+  #[error("database was written in the unsupported format version {0}")]
+  UnsupportedFormat(u8),
+
+  /// Writes are suspended until `Engine::resume_writes` is called.
+  #[error("disk is full")]
+  DiskFull,
+
+  #[error("deadline exceeded")]
+  Timeout,
+
+  #[error("too many open transactions, at most {0}")]
+  TooManyOpenTransactions(usize),
+
+  /// Versions of the snapshot were overwritten when the undo ring wrapped.
+  #[error("snapshot expired")]
+  SnapshotExpired,
 }
 impl Error {
   pub fn unknown<E>(e: E) -> Error
@@ -30,6 +94,104 @@ impl Error {
   {
     Error::Unknown(e.into())
   }
+
+  pub fn disk(op: DiskOp, index: usize) -> impl FnOnce(std::io::Error) -> Error {
+    move |source| match source.kind() {
+      std::io::ErrorKind::StorageFull => Error::DiskFull,
+      _ => Error::Disk { op, index, source },
+    }
+  }
+
+  /// Io failures which usually pass on their own, like a syscall
+  /// interrupted by a signal, so the same io can be tried again at once.
+  pub fn is_transient_io(&self) -> bool {
+    match self {
+      Error::IO(err) | Error::Disk { source: err, .. } => matches!(
+        err.kind(),
+        std::io::ErrorKind::Interrupted | std::io::ErrorKind::WouldBlock
+      ),
+      _ => false,
+    }
+  }
+
+  pub fn is_retryable(&self) -> bool {
+    match self {
+      Error::IO(err) | Error::Disk { source: err, .. } => matches!(
+        err.kind(),
+        std::io::ErrorKind::Interrupted
+          | std::io::ErrorKind::WouldBlock
+          | std::io::ErrorKind::TimedOut
+      ),
+      Error::Timeout
+      | Error::TooManyOpenTransactions(_)
+      | Error::WriteConflict { .. } => true,
+      Error::NotFound
+      | Error::Invalid
+      | Error::Unknown(_)
+      | Error::EOF
+      | Error::TransactionClosed
+      | Error::TransactionTimedOut
+      | Error::EngineUnavailable
+      | Error::ResultTooLarge
+      | Error::AlreadyLocked
+      | Error::NotSorted
+      | Error::Corruption(_)
+      | Error::ScanCorruption { .. }
+      | Error::ComparatorMismatch(_)
+      | Error::UnsupportedFormat(_)
+      | Error::DiskFull
+      | Error::SnapshotExpired => false,
+    }
+  }
 }
 
 pub type Result<T = ()> = std::result::Result<T, Error>;
+
+#[cfg(test)]
+mod tests {
+  use std::io::ErrorKind;
+
+  use crate::cursor::KeyComparator;
+
+  use super::{DiskOp, Error};
+
+  #[test]
+  fn _1() {
+    assert!(Error::IO(ErrorKind::Interrupted.into()).is_retryable());
+    assert!(Error::IO(ErrorKind::WouldBlock.into()).is_retryable());
+    assert!(Error::IO(ErrorKind::TimedOut.into()).is_retryable());
+    assert!(!Error::IO(ErrorKind::PermissionDenied.into()).is_retryable());
+    assert!(Error::disk(DiskOp::Read, 1)(ErrorKind::Interrupted.into()).is_retryable());
+    assert!(!Error::disk(DiskOp::Write, 1)(ErrorKind::Other.into()).is_retryable());
+    assert!(Error::disk(DiskOp::Read, 1)(ErrorKind::Interrupted.into()).is_transient_io());
+    assert!(Error::IO(ErrorKind::WouldBlock.into()).is_transient_io());
+    assert!(!Error::IO(ErrorKind::TimedOut.into()).is_transient_io());
+    assert!(!Error::Timeout.is_transient_io());
+    assert!(!Error::NotFound.is_retryable());
+    assert!(!Error::Invalid.is_retryable());
+    assert!(!Error::unknown("unknown").is_retryable());
+    assert!(!Error::EOF.is_retryable());
+    assert!(!Error::TransactionClosed.is_retryable());
+    assert!(!Error::EngineUnavailable.is_retryable());
+    assert!(!Error::ResultTooLarge.is_retryable());
+    assert!(!Error::NotSorted.is_retryable());
+    assert!(!Error::Corruption(String::new()).is_retryable());
+    assert!(!Error::AlreadyLocked.is_retryable());
+    assert!(!Error::ScanCorruption {
+      index: 1,
+      last_key: None
+    }
+    .is_retryable());
+    assert!(!Error::ComparatorMismatch(KeyComparator::Lexicographic).is_retryable());
+    assert!(!Error::UnsupportedFormat(0).is_retryable());
+    assert!(!Error::DiskFull.is_retryable());
+    assert!(!Error::SnapshotExpired.is_retryable());
+    assert!(Error::Timeout.is_retryable());
+    assert!(Error::TooManyOpenTransactions(1).is_retryable());
+    assert!(Error::WriteConflict {
+      key: vec![],
+      holder: 1
+    }
+    .is_retryable());
+  }
+}
@@ -1,5 +1,7 @@
 mod buffer;
+pub use buffer::EvictionPolicy;
 mod wal;
+pub use wal::{InsertLog, LogMode, LogRecord, LogicalLog, Operation, WalReader};
 
 mod thread;
 pub use thread::*;
@@ -7,6 +9,14 @@ pub use thread::*;
 mod engine;
 pub use engine::*;
 
+mod scoped;
+pub use scoped::*;
+
+#[cfg(feature = "async")]
+mod async_engine;
+#[cfg(feature = "async")]
+pub use async_engine::*;
+
 mod cursor;
 pub use cursor::*;
 
@@ -17,7 +27,10 @@ mod utils;
 pub use utils::*;
 
 mod disk;
-pub use disk::{Page, Serializable, PAGE_SIZE};
+pub use disk::{
+  AllocStrategy, DiskStats, LatencySnapshot, Page, PageHeader, Serializable, SyncMode,
+  PAGE_SIZE,
+};
 
 mod log;
 use log::*;
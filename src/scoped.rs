@@ -0,0 +1,102 @@
+use crate::{Engine, Error, Result, Serializable};
+
+/// Handle which prepends a fixed prefix to every key, so applications can
+/// partition a shared keyspace without managing the prefixes themselves.
+/// Keys are returned without the prefix and scans are bounded by the range
+/// of the prefix. The range is contiguous under the lexicographic comparator,
+/// which scopes assume.
+pub struct Scoped<'a> {
+  engine: &'a Engine,
+  prefix: Vec<u8>,
+  /// Smallest key greater than every key with the prefix.
+  upper: Vec<u8>,
+}
+impl<'a> Scoped<'a> {
+  /// Fails with `Error::Invalid` if the prefix is empty or only `0xff` bytes,
+  /// since the keys of such a prefix have no upper bound.
+  pub fn new(engine: &'a Engine, prefix: Vec<u8>) -> Result<Self> {
+    let upper = prefix_upper_bound(&prefix).ok_or(Error::Invalid)?;
+    Ok(Self {
+      engine,
+      prefix,
+      upper,
+    })
+  }
+
+  pub fn get<T>(&self, key: &[u8]) -> Result<Option<T>>
+  where
+    T: Serializable,
+  {
+    self.engine.get(&self.encode(key))
+  }
+
+  pub fn insert<T>(&self, key: &[u8], value: T) -> Result
+  where
+    T: Serializable,
+  {
+    self.engine.put(self.encode(key), value)
+  }
+
+  pub fn remove(&self, key: &[u8]) -> Result {
+    let cursor = self.engine.new_transaction()?;
+    cursor.remove(&self.encode(key))?;
+    cursor.commit()
+  }
+
+  /// Reads the keys in `[start, end)` of the scope in a short lived
+  /// transaction. An empty end is the end of the scope.
+  pub fn scan<T>(&self, start: &[u8], end: &[u8]) -> Result<Vec<(Vec<u8>, T)>>
+  where
+    T: Serializable,
+  {
+    let end = match end.is_empty() {
+      true => self.upper.clone(),
+      false => self.encode(end),
+    };
+    let cursor = self.engine.new_transaction()?;
+    let mut iter = cursor.scan(&self.encode(start), &end)?;
+    let mut entries = vec![];
+    while let Some((key, value)) = iter.try_next()? {
+      entries.push((key[self.prefix.len()..].to_vec(), value));
+    }
+    drop(iter);
+    cursor.commit()?;
+    Ok(entries)
+  }
+
+  /// Reads every key of the scope.
+  pub fn scan_all<T>(&self) -> Result<Vec<(Vec<u8>, T)>>
+  where
+    T: Serializable,
+  {
+    self.scan(&[], &[])
+  }
+
+  fn encode(&self, key: &[u8]) -> Vec<u8> {
+    let mut encoded = Vec::with_capacity(self.prefix.len() + key.len());
+    encoded.extend_from_slice(&self.prefix);
+    encoded.extend_from_slice(key);
+    encoded
+  }
+}
+
+/// Increments the last byte which is not `0xff` and drops the bytes after it.
+fn prefix_upper_bound(prefix: &[u8]) -> Option<Vec<u8>> {
+  let i = prefix.iter().rposition(|b| b.ne(&0xff))?;
+  let mut upper = prefix[..=i].to_vec();
+  upper[i] += 1;
+  Some(upper)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::prefix_upper_bound;
+
+  #[test]
+  fn _1() {
+    assert_eq!(prefix_upper_bound(b"ab"), Some(b"ac".to_vec()));
+    assert_eq!(prefix_upper_bound(&[1, 0xff, 0xff]), Some(vec![2]));
+    assert_eq!(prefix_upper_bound(&[0xff]), None);
+    assert_eq!(prefix_upper_bound(&[]), None);
+  }
+}
@@ -0,0 +1,232 @@
+use crate::logger;
+
+/// OS scheduling priority of a worker thread, mapped to a nice value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+  Low,
+  Normal,
+  /// Usually requires `CAP_SYS_NICE`, otherwise the hint is ignored.
+  High,
+}
+impl Priority {
+  #[cfg(target_os = "linux")]
+  fn nice(&self) -> i32 {
+    match self {
+      Priority::Low => 10,
+      Priority::Normal => 0,
+      Priority::High => -10,
+    }
+  }
+}
+
+/// Placement hints applied by a worker thread when it starts.
+/// Hints which can not be applied are logged and ignored,
+/// and on platforms other than linux every hint is ignored.
+#[derive(Debug, Clone, Default)]
+pub struct ThreadHints {
+  core_affinity: Option<Vec<usize>>,
+  numa_node: Option<usize>,
+  priority: Option<Priority>,
+}
+impl ThreadHints {
+  pub fn core_affinity(mut self, cores: Vec<usize>) -> Self {
+    self.core_affinity = Some(cores);
+    self
+  }
+
+  /// Runs the thread on the cores of the NUMA node, so the pages it allocates
+  /// are placed on the node's memory by the default first touch policy.
+  /// Ignored if the core affinity is set, or if the node does not exist,
+  /// which includes systems without NUMA.
+  pub fn numa_node(mut self, node: usize) -> Self {
+    self.numa_node = Some(node);
+    self
+  }
+
+  pub fn thread_priority(mut self, priority: Priority) -> Self {
+    self.priority = Some(priority);
+    self
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.core_affinity.is_none() && self.numa_node.is_none() && self.priority.is_none()
+  }
+
+  /// Applies the hints to the calling thread.
+  pub fn apply(&self, name: &str) {
+    if self.is_empty() {
+      return;
+    }
+    #[cfg(target_os = "linux")]
+    {
+      if let Some(cores) = &self.core_affinity {
+        if let Err(err) = sys::set_affinity(cores) {
+          logger::warn(format!("{name} failed to set core affinity {:?}", err));
+        }
+      } else if let Some(node) = self.numa_node {
+        if let Err(err) =
+          sys::node_cores(node).and_then(|cores| sys::set_affinity(&cores))
+        {
+          logger::warn(format!(
+            "{name} failed to run on numa node {node} {:?}",
+            err
+          ));
+        }
+      }
+      if let Some(priority) = self.priority {
+        if let Err(err) = sys::set_nice(priority.nice()) {
+          logger::warn(format!("{name} failed to set priority {:?}", err));
+        }
+      }
+    }
+    #[cfg(not(target_os = "linux"))]
+    logger::warn(format!("{name} thread hints are not supported"));
+  }
+}
+
+/// Returns the cores the calling thread is allowed to run on.
+#[cfg(target_os = "linux")]
+pub fn current_affinity() -> std::io::Result<Vec<usize>> {
+  sys::get_affinity()
+}
+
+/// Returns the cores of the NUMA node.
+#[cfg(target_os = "linux")]
+pub fn numa_node_cores(node: usize) -> std::io::Result<Vec<usize>> {
+  sys::node_cores(node)
+}
+
+#[cfg(target_os = "linux")]
+mod sys {
+  use std::{fs, io};
+
+  /// Same size as `cpu_set_t` of glibc, 1024 cores.
+  const MASK_LEN: usize = 16;
+  const PRIO_PROCESS: i32 = 0;
+
+  extern "C" {
+    fn sched_setaffinity(pid: i32, size: usize, mask: *const u64) -> i32;
+    fn sched_getaffinity(pid: i32, size: usize, mask: *mut u64) -> i32;
+    fn setpriority(which: i32, who: u32, prio: i32) -> i32;
+  }
+
+  pub fn set_affinity(cores: &[usize]) -> io::Result<()> {
+    let mut mask = [0u64; MASK_LEN];
+    for &core in cores {
+      if core.ge(&(MASK_LEN * 64)) {
+        return Err(io::Error::from(io::ErrorKind::InvalidInput));
+      }
+      mask[core / 64] |= 1 << (core % 64);
+    }
+    // pid 0 is the calling thread.
+    let r = unsafe { sched_setaffinity(0, size_of_val(&mask), mask.as_ptr()) };
+    match r {
+      0 => Ok(()),
+      _ => Err(io::Error::last_os_error()),
+    }
+  }
+
+  pub fn get_affinity() -> io::Result<Vec<usize>> {
+    let mut mask = [0u64; MASK_LEN];
+    let r = unsafe { sched_getaffinity(0, size_of_val(&mask), mask.as_mut_ptr()) };
+    if r.lt(&0) {
+      return Err(io::Error::last_os_error());
+    }
+    Ok(
+      (0..MASK_LEN * 64)
+        .filter(|&core| mask[core / 64] & (1 << (core % 64)) != 0)
+        .collect(),
+    )
+  }
+
+  pub fn node_cores(node: usize) -> io::Result<Vec<usize>> {
+    let path = format!("/sys/devices/system/node/node{node}/cpulist");
+    parse_core_list(fs::read_to_string(path)?.trim())
+  }
+
+  /// Parses a cpu list of the kernel like `0-3,8,10-11`.
+  pub fn parse_core_list(list: &str) -> io::Result<Vec<usize>> {
+    let invalid = || io::Error::from(io::ErrorKind::InvalidData);
+    let mut cores = vec![];
+    for range in list.split(',').filter(|r| !r.is_empty()) {
+      let (start, end) = range.split_once('-').unwrap_or((range, range));
+      let start: usize = start.parse().map_err(|_| invalid())?;
+      let end: usize = end.parse().map_err(|_| invalid())?;
+      cores.extend(start..=end);
+    }
+    Ok(cores)
+  }
+
+  /// Linux keeps the nice value per thread, and who 0 is the calling thread.
+  pub fn set_nice(nice: i32) -> io::Result<()> {
+    match unsafe { setpriority(PRIO_PROCESS, 0, nice) } {
+      0 => Ok(()),
+      _ => Err(io::Error::last_os_error()),
+    }
+  }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+  use crate::{BackgroundThread, BackgroundWork, SharedWorkThread};
+
+  use super::{current_affinity, numa_node_cores, sys, Priority, ThreadHints};
+
+  #[test]
+  fn _1() {
+    let allowed = current_affinity().unwrap();
+    let core = *allowed.last().unwrap();
+    let hints = ThreadHints::default()
+      .core_affinity(vec![core])
+      .thread_priority(Priority::Low);
+
+    let pool =
+      SharedWorkThread::new("test", 1 << 16, 2, |_: ()| current_affinity().unwrap());
+    assert_eq!(pool.send_await(()), allowed);
+    pool.set_hints(hints.clone());
+    assert_eq!(pool.count(), 2);
+    for _ in 0..4 {
+      assert_eq!(pool.send_await(()), vec![core]);
+    }
+    pool.close();
+
+    let thread = BackgroundThread::new(
+      "test",
+      1 << 16,
+      BackgroundWork::no_timeout(|_: ()| current_affinity().unwrap()),
+    );
+    thread.set_hints(hints);
+    assert_eq!(thread.send_await(()), vec![core]);
+    thread.close();
+    assert_eq!(current_affinity().unwrap(), allowed);
+  }
+
+  #[test]
+  fn _2() {
+    assert_eq!(
+      sys::parse_core_list("0-3,8,10-11").unwrap(),
+      vec![0, 1, 2, 3, 8, 10, 11]
+    );
+    assert_eq!(sys::parse_core_list("").unwrap(), Vec::<usize>::new());
+    assert!(sys::parse_core_list("0-a").is_err());
+
+    let allowed = current_affinity().unwrap();
+    let node = match numa_node_cores(0) {
+      Ok(cores) => cores,
+      // no numa support, so there is no node to run on.
+      Err(_) => return,
+    };
+    let pool =
+      SharedWorkThread::new("test", 1 << 16, 1, |_: ()| current_affinity().unwrap());
+    pool.set_hints(ThreadHints::default().numa_node(0));
+    let cores = pool.send_await(());
+    assert!(cores
+      .iter()
+      .all(|core| node.contains(core) && allowed.contains(core)));
+
+    // a node which does not exist is ignored.
+    pool.set_hints(ThreadHints::default().numa_node(1 << 16));
+    assert_eq!(pool.send_await(()), allowed);
+    pool.close();
+  }
+}
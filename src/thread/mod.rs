@@ -1,2 +1,11 @@
 mod thread;
 pub use thread::*;
+
+mod shared;
+pub use shared::*;
+
+mod oneshot;
+pub use oneshot::*;
+
+mod hints;
+pub use hints::*;
@@ -0,0 +1,144 @@
+use std::{
+  future::Future,
+  pin::Pin,
+  sync::{Arc, Condvar, Mutex},
+  task::{Context, Poll, Waker},
+  time::Duration,
+};
+
+use crate::ShortenedMutex;
+
+type Shared<T> = Arc<(Mutex<OneshotState<T>>, Condvar)>;
+
+struct OneshotState<T> {
+  value: Option<T>,
+  waker: Option<Waker>,
+}
+
+/// Receiving half of a single value channel which can be awaited or waited.
+pub struct Oneshot<T>(Shared<T>);
+
+/// Sending half of the oneshot which wakes the waiting task on send.
+pub struct OneshotSender<T>(Shared<T>);
+
+pub fn oneshot<T>() -> (OneshotSender<T>, Oneshot<T>) {
+  let state = Arc::new((
+    Mutex::new(OneshotState {
+      value: None,
+      waker: None,
+    }),
+    Condvar::new(),
+  ));
+  (OneshotSender(state.clone()), Oneshot(state))
+}
+
+impl<T> OneshotSender<T> {
+  pub fn send(self, value: T) {
+    let waker = {
+      let mut state = self.0 .0.l();
+      state.value = Some(value);
+      state.waker.take()
+    };
+    self.0 .1.notify_all();
+    if let Some(waker) = waker {
+      waker.wake();
+    }
+  }
+
+  /// Returns true if the receiver is dropped and nobody waits for the value.
+  pub fn is_disconnected(&self) -> bool {
+    Arc::strong_count(&self.0).eq(&1)
+  }
+}
+
+impl<T> Oneshot<T> {
+  /// Blocks the current thread until the value is sent.
+  pub fn wait(self) -> T {
+    let (lock, cvar) = &*self.0;
+    let mut state = lock.l();
+    loop {
+      if let Some(value) = state.value.take() {
+        return value;
+      }
+      state = cvar.wait(state).unwrap();
+    }
+  }
+
+  /// Returns none if the value is not sent within the timeout.
+  pub fn wait_timeout(&self, timeout: Duration) -> Option<T> {
+    let (lock, cvar) = &*self.0;
+    let state = lock.l();
+    let (mut state, _) = cvar
+      .wait_timeout_while(state, timeout, |s| s.value.is_none())
+      .unwrap();
+    state.value.take()
+  }
+}
+
+impl<T> Future for Oneshot<T> {
+  type Output = T;
+
+  fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+    let mut state = self.0 .0.l();
+    if let Some(value) = state.value.take() {
+      return Poll::Ready(value);
+    }
+    state.waker = Some(cx.waker().clone());
+    Poll::Pending
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{
+    future::Future,
+    pin::pin,
+    sync::Arc,
+    task::{Context, Poll, Wake},
+    thread::{self, Thread},
+    time::Duration,
+  };
+
+  use super::oneshot;
+
+  struct ThreadWaker(Thread);
+  impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+      self.0.unpark();
+    }
+  }
+
+  #[test]
+  fn _1() {
+    let (tx, rx) = oneshot();
+    let t = thread::spawn(move || {
+      thread::sleep(Duration::from_millis(50));
+      tx.send(10);
+    });
+
+    let waker = Arc::new(ThreadWaker(thread::current())).into();
+    let mut cx = Context::from_waker(&waker);
+    let mut rx = pin!(rx);
+    let value = loop {
+      match rx.as_mut().poll(&mut cx) {
+        Poll::Ready(v) => break v,
+        Poll::Pending => thread::park(),
+      }
+    };
+    assert_eq!(value, 10);
+    t.join().unwrap();
+  }
+
+  #[test]
+  fn _2() {
+    let (tx, rx) = oneshot::<usize>();
+    assert!(!tx.is_disconnected());
+    assert_eq!(rx.wait_timeout(Duration::from_millis(10)), None);
+    drop(rx);
+    assert!(tx.is_disconnected());
+
+    let (tx, rx) = oneshot();
+    thread::spawn(move || tx.send(3));
+    assert_eq!(rx.wait(), 3);
+  }
+}
@@ -0,0 +1,321 @@
+use std::{
+  collections::BTreeMap,
+  ops::{Add, Sub},
+  sync::{Arc, Mutex},
+  thread::JoinHandle,
+  time::Duration,
+};
+
+use crossbeam::channel::{unbounded, Receiver, Sender};
+
+use crate::{
+  logger, oneshot, Oneshot, OneshotSender, Result, ShortenedMutex, ThreadHints,
+  UnwrappedSender,
+};
+
+/// How long a shrinking resize waits for an exit before it looks for
+/// workers which panicked, since those never send their exit.
+const EXIT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+enum Context<T, R> {
+  Work(T, Option<OneshotSender<R>>),
+  Term,
+}
+
+pub struct SharedWorkThread<T, R = ()> {
+  inner: Mutex<SharedWorkThreadInner>,
+  tx: Sender<Context<T, R>>,
+  rx: Receiver<Context<T, R>>,
+  exit_t: Sender<usize>,
+  exit_r: Receiver<usize>,
+  func: Arc<dyn Fn(T) -> R + Send + Sync>,
+}
+
+struct SharedWorkThreadInner {
+  threads: BTreeMap<usize, JoinHandle<()>>,
+  last_id: usize,
+  name: String,
+  size: usize,
+  hints: ThreadHints,
+}
+
+impl<T, R> SharedWorkThread<T, R>
+where
+  T: Send + 'static,
+  R: Send + 'static,
+{
+  pub fn new<S: ToString, F>(name: S, size: usize, count: usize, f: F) -> Self
+  where
+    F: Fn(T) -> R + Send + Sync + 'static,
+  {
+    let (tx, rx) = unbounded();
+    let (exit_t, exit_r) = unbounded();
+    let pool = Self {
+      inner: Mutex::new(SharedWorkThreadInner {
+        threads: Default::default(),
+        last_id: 0,
+        name: name.to_string(),
+        size,
+        hints: Default::default(),
+      }),
+      tx,
+      rx,
+      exit_t,
+      exit_r,
+      func: Arc::new(f),
+    };
+    pool.resize(count);
+    pool
+  }
+
+  pub fn count(&self) -> usize {
+    self.inner.l().threads.len()
+  }
+
+  /// Workers which panicked are dropped from the pool and not counted.
+  /// A worker panicking while the pool shrinks leaves a term in the queue,
+  /// so the pool can end up a worker short until the next resize.
+  pub fn resize(&self, count: usize) {
+    let mut inner = self.inner.l();
+    reap_finished(&mut inner);
+    let current = inner.threads.len();
+    if current.lt(&count) {
+      for _ in current..count {
+        let id = inner.last_id.add(1);
+        inner.last_id = id;
+        let t = self.spawn(&inner, id);
+        inner.threads.insert(id, t);
+      }
+      return;
+    }
+
+    for _ in 0..current.sub(count) {
+      self.tx.must_send(Context::Term);
+    }
+    while inner.threads.len().gt(&count) {
+      match self.exit_r.recv_timeout(EXIT_POLL_INTERVAL) {
+        Ok(id) => {
+          if let Some(t) = inner.threads.remove(&id) {
+            join_thread(t);
+          }
+        }
+        Err(_) => reap_finished(&mut inner),
+      }
+    }
+  }
+
+  /// Restarts the threads so every thread of the pool applies the hints.
+  pub fn set_hints(&self, hints: ThreadHints) {
+    let count = self.count();
+    self.resize(0);
+    self.inner.l().hints = hints;
+    self.resize(count);
+  }
+
+  fn spawn(&self, inner: &SharedWorkThreadInner, id: usize) -> JoinHandle<()> {
+    let rx = self.rx.clone();
+    let exit_t = self.exit_t.clone();
+    let func = self.func.clone();
+    let hints = inner.hints.clone();
+    let name = format!("{} {}", inner.name, id);
+    std::thread::Builder::new()
+      .name(name.clone())
+      .stack_size(inner.size)
+      .spawn(move || {
+        hints.apply(&name);
+        while let Ok(Context::Work(v, done)) = rx.recv() {
+          match done {
+            Some(done) if done.is_disconnected() => continue,
+            Some(done) => done.send(func(v)),
+            None => {
+              func(v);
+            }
+          }
+        }
+        exit_t.send(id).ok();
+      })
+      .unwrap()
+  }
+
+  /// The work is skipped if the returned oneshot is dropped before
+  /// a worker picks it up.
+  pub fn send(&self, v: T) -> Oneshot<R> {
+    let (done_t, done_r) = oneshot();
+    self.tx.must_send(Context::Work(v, Some(done_t)));
+    done_r
+  }
+
+  /// Runs the work without waiting for the result.
+  pub fn execute(&self, v: T) {
+    self.tx.must_send(Context::Work(v, None));
+  }
+
+  pub fn send_await(&self, v: T) -> R {
+    self.send(v).wait()
+  }
+
+  pub fn close(&self) {
+    self.resize(0);
+    logger::info(format!("{} threads done", self.inner.l().name))
+  }
+}
+
+impl<T, V> SharedWorkThread<T, Result<V>>
+where
+  T: Send + 'static,
+  V: Send + 'static,
+{
+  /// Runs the work for every item on the pool and returns the results
+  /// in the order of the items. Every item is waited for even after
+  /// a failure, and the first error in item order is returned.
+  pub fn map<I>(&self, items: I) -> Result<Vec<V>>
+  where
+    I: IntoIterator<Item = T>,
+  {
+    let waiting = items.into_iter().map(|v| self.send(v)).collect::<Vec<_>>();
+    let mut results = Vec::with_capacity(waiting.len());
+    let mut error = None;
+    for r in waiting {
+      match r.wait() {
+        Ok(v) => results.push(v),
+        Err(err) => {
+          error.get_or_insert(err);
+        }
+      }
+    }
+    match error {
+      Some(err) => Err(err),
+      None => Ok(results),
+    }
+  }
+}
+
+/// Joins and drops the workers which are no longer running.
+/// A worker which panicked never sends its exit, so it is only found here.
+fn reap_finished(inner: &mut SharedWorkThreadInner) {
+  let finished = inner
+    .threads
+    .iter()
+    .filter(|(_, t)| t.is_finished())
+    .map(|(id, _)| *id)
+    .collect::<Vec<_>>();
+  for id in finished {
+    if let Some(t) = inner.threads.remove(&id) {
+      join_thread(t);
+    }
+  }
+}
+
+fn join_thread(t: JoinHandle<()>) {
+  if let Err(err) = t.join() {
+    logger::error(format!("{:?}", err));
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{
+    sync::{
+      atomic::{AtomicBool, AtomicUsize, Ordering},
+      Arc,
+    },
+    thread,
+    time::Duration,
+  };
+
+  use crossbeam::channel::unbounded;
+
+  use crate::{Error, UnwrappedReceiver};
+
+  use super::SharedWorkThread;
+
+  #[test]
+  fn _1() {
+    let pool = Arc::new(SharedWorkThread::new("test", 1 << 16, 2, |i: usize| {
+      thread::sleep(Duration::from_millis(1));
+      i * 2
+    }));
+
+    let cloned = pool.clone();
+    let sender = thread::spawn(move || {
+      (0..300usize)
+        .map(|i| (i, cloned.send(i)))
+        .collect::<Vec<_>>()
+    });
+
+    pool.resize(6);
+    assert_eq!(pool.count(), 6);
+    pool.resize(2);
+    assert_eq!(pool.count(), 2);
+
+    for (i, r) in sender.join().unwrap() {
+      assert_eq!(r.wait(), i * 2);
+    }
+    pool.close();
+    assert_eq!(pool.count(), 0);
+  }
+
+  #[test]
+  fn _2() {
+    let (block_t, block_r) = unbounded::<()>();
+    let executed = Arc::new(AtomicBool::new(false));
+    let flag = executed.clone();
+    let pool = SharedWorkThread::new("test", 1 << 16, 1, move |i: usize| {
+      match i {
+        0 => block_r.must_recv(),
+        1 => flag.store(true, Ordering::SeqCst),
+        _ => {}
+      };
+    });
+
+    let blocked = pool.send(0);
+    drop(pool.send(1));
+    block_t.send(()).unwrap();
+    blocked.wait();
+    pool.send_await(2);
+
+    assert!(!executed.load(Ordering::SeqCst));
+    pool.close();
+  }
+
+  #[test]
+  fn _3() {
+    let done = Arc::new(AtomicUsize::new(0));
+    let counter = done.clone();
+    let pool = SharedWorkThread::new("test", 1 << 16, 4, move |i: usize| {
+      counter.fetch_add(1, Ordering::SeqCst);
+      match i {
+        120 => Err(Error::Invalid),
+        130 => Err(Error::NotFound),
+        _ => Ok(i * 2),
+      }
+    });
+
+    let doubled = pool.map(0..100usize).unwrap();
+    assert_eq!(doubled, (0..100usize).map(|i| i * 2).collect::<Vec<_>>());
+
+    done.store(0, Ordering::SeqCst);
+    assert!(matches!(pool.map(100..200usize), Err(Error::Invalid)));
+    assert_eq!(done.load(Ordering::SeqCst), 100);
+    pool.close();
+  }
+
+  #[test]
+  fn _4() {
+    let pool = SharedWorkThread::new("test", 1 << 16, 3, |i: usize| {
+      if i.eq(&0) {
+        panic!("work panicked");
+      }
+      i * 2
+    });
+    pool.execute(0);
+    thread::sleep(Duration::from_millis(100));
+
+    // the panicked worker never sends its exit.
+    pool.resize(1);
+    assert_eq!(pool.count(), 1);
+    assert_eq!(pool.send_await(3), 6);
+    pool.close();
+    assert_eq!(pool.count(), 0);
+  }
+}
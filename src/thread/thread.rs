@@ -8,6 +8,8 @@ use crossbeam::channel::{unbounded, Receiver, RecvTimeoutError, Sender};
 
 use crate::{logger, AsTimer, ShortenedMutex, UnwrappedReceiver, UnwrappedSender};
 
+use super::ThreadHints;
+
 pub trait Callable<T, R> {
   fn call(&mut self, v: T) -> R;
 }
@@ -74,7 +76,10 @@ impl<T, R> BackgroundWork<T, R> {
           let arg = match rx.recv_timeout(timer.get_remain()) {
             Ok((v, done)) => Some((v, done)),
             Err(RecvTimeoutError::Timeout) => None,
-            Err(RecvTimeoutError::Disconnected) => break,
+            Err(RecvTimeoutError::Disconnected) => {
+              job.call(None);
+              break;
+            }
           };
           match job.call(arg) {
             true => timer.reset(),
@@ -94,6 +99,7 @@ struct BackgroundThreadInner<T, R> {
   func: Arc<Mutex<BackgroundWork<T, R>>>,
   name: String,
   size: usize,
+  hints: ThreadHints,
 }
 impl<T, R> BackgroundThread<T, R>
 where
@@ -106,6 +112,7 @@ where
       func: Arc::new(Mutex::new(work)),
       name: name.to_string(),
       size,
+      hints: Default::default(),
     }))
   }
 
@@ -115,6 +122,7 @@ where
       func: Arc::new(Mutex::new(BackgroundWork::Empty)),
       name: name.to_string(),
       size,
+      hints: Default::default(),
     }))
   }
 
@@ -123,6 +131,12 @@ where
     inner.func = Arc::new(Mutex::new(work));
   }
 
+  /// Hints are applied when the worker thread is spawned next,
+  /// so a running thread keeps its placement until it is restarted.
+  pub fn set_hints(&self, hints: ThreadHints) {
+    self.0.l().hints = hints;
+  }
+
   fn checked_send(&self, v: T) -> Receiver<R> {
     let mut inner = self.0.l();
     if let Some((t, tx)) = inner.thread.take() {
@@ -135,21 +149,32 @@ where
       close_thread(t, tx);
     }
 
-    let func = inner.func.clone();
-    let (tx, rx) = unbounded::<(T, Sender<R>)>();
-    let t = std::thread::Builder::new()
-      .name(inner.name.clone())
-      .stack_size(inner.size)
-      .spawn(move || {
-        func.l().run(rx);
-      })
-      .unwrap();
+    let tx = inner.spawn();
     let (done_t, done_r) = unbounded();
-    tx.must_send((v, done_t));
-    inner.thread = Some((t, tx));
+    // a thread without work can finish before the value is sent.
+    tx.maybe_send((v, done_t));
     return done_r;
   }
 
+  /// Returns true if the worker thread is spawned and has not finished yet.
+  pub fn is_running(&self) -> bool {
+    let inner = self.0.l();
+    match &inner.thread {
+      Some((t, _)) => !t.is_finished(),
+      None => false,
+    }
+  }
+
+  /// Closes the current worker thread if any and spawns a new one.
+  pub fn restart(&self) {
+    let mut inner = self.0.l();
+    if let Some((t, tx)) = inner.thread.take() {
+      close_thread(t, tx);
+    }
+    inner.spawn();
+    logger::info(format!("{} thread restarted", inner.name))
+  }
+
   pub fn send(&self, v: T) -> Receiver<R> {
     self.checked_send(v)
   }
@@ -165,9 +190,55 @@ where
   }
 }
 
+impl<T, R> BackgroundThreadInner<T, R>
+where
+  T: Send + 'static,
+  R: Send + 'static,
+{
+  fn spawn(&mut self) -> Sender<(T, Sender<R>)> {
+    let func = self.func.clone();
+    let hints = self.hints.clone();
+    let name = self.name.clone();
+    let (tx, rx) = unbounded::<(T, Sender<R>)>();
+    let t = std::thread::Builder::new()
+      .name(self.name.clone())
+      .stack_size(self.size)
+      .spawn(move || {
+        hints.apply(&name);
+        func.l().run(rx);
+      })
+      .unwrap();
+    self.thread = Some((t, tx.clone()));
+    tx
+  }
+}
+
 fn close_thread<T>(t: JoinHandle<()>, tx: Sender<T>) {
   drop(tx);
   if let Err(err) = t.join() {
     logger::error(format!("{:?}", err));
   };
 }
+
+#[cfg(test)]
+mod tests {
+  use super::{BackgroundThread, BackgroundWork};
+
+  #[test]
+  fn _1() {
+    let thread = BackgroundThread::new(
+      "test background",
+      1 << 16,
+      BackgroundWork::no_timeout(|v: usize| v + 1),
+    );
+    assert!(!thread.is_running());
+    assert_eq!(thread.send_await(1), 2);
+    assert!(thread.is_running());
+    thread.close();
+    assert!(!thread.is_running());
+    thread.restart();
+    assert!(thread.is_running());
+    assert_eq!(thread.send_await(2), 3);
+    thread.close();
+  }
+}
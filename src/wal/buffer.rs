@@ -1,26 +1,58 @@
 use std::{
-  collections::BTreeMap,
+  collections::{BTreeMap, BTreeSet},
   ops::{Add, AddAssign, SubAssign},
   sync::Mutex,
 };
 
 use crate::{DrainAll, Page, ShortenedMutex};
 
-use super::LogRecord;
+use super::{LogRecord, LogicalLog};
 
 struct LogBufferCore {
   last_transaction: usize,
   map: BTreeMap<usize, Vec<LogRecord>>,
+  /// Transactions started and not yet committed or rolled back.
+  open: BTreeSet<usize>,
   size: usize,
+  capacity: usize,
 }
+impl LogBufferCore {
+  /// Buffers the record, or takes every buffered record first if it would
+  /// not fit, so the size never exceeds the capacity. A record larger than
+  /// the capacity is returned after them without being buffered.
+  fn push(&mut self, tx_id: usize, record: LogRecord) -> Vec<LogRecord> {
+    let mut flushed = vec![];
+    if self.size.add(record.size()).gt(&self.capacity) {
+      flushed = self.drain();
+      if record.size().gt(&self.capacity) {
+        flushed.push(record);
+        return flushed;
+      }
+    }
+    self.size.add_assign(record.size());
+    self.map.entry(tx_id).or_default().push(record);
+    flushed
+  }
+
+  fn drain(&mut self) -> Vec<LogRecord> {
+    self.size = 0;
+    self.map.drain_all().into_values().flatten().collect()
+  }
+}
+/// Pending records are grouped by transaction id,
+/// so commit and rollback only touch the records of that transaction.
+/// The encoded size of the buffered records is capped in bytes,
+/// and the records which make room for a new one are returned to be written.
 pub struct LogBuffer(Mutex<LogBufferCore>);
 
 impl LogBuffer {
-  pub fn new() -> Self {
+  pub fn new(capacity: usize) -> Self {
     Self(Mutex::new(LogBufferCore {
       last_transaction: 0,
       map: Default::default(),
+      open: Default::default(),
       size: 0,
+      capacity,
     }))
   }
 
@@ -29,25 +61,33 @@ impl LogBuffer {
     core.last_transaction = last_transaction
   }
 
-  pub fn new_transaction(&self) -> usize {
+  pub fn last_transaction(&self) -> usize {
+    self.0.l().last_transaction
+  }
+
+  /// Returns the id of the new transaction and the records to be written.
+  pub fn new_transaction(&self) -> (usize, Vec<LogRecord>) {
     let mut core = self.0.l();
     let tx_id = core.last_transaction.add(1);
-    let record = LogRecord::new_start(tx_id);
-    core.size.add_assign(record.size());
-    core.map.insert(tx_id, vec![LogRecord::new_start(tx_id)]);
     core.last_transaction = tx_id;
-    tx_id
+    core.open.insert(tx_id);
+    let flushed = core.push(tx_id, LogRecord::new_start(tx_id));
+    (tx_id, flushed)
   }
 
-  pub fn append(&self, tx_id: usize, page_index: usize, data: Page) {
-    let mut core = self.0.l();
+  pub fn append(&self, tx_id: usize, page_index: usize, data: Page) -> Vec<LogRecord> {
     let record = LogRecord::new_insert(tx_id, page_index, data);
-    core.size.add_assign(record.size());
-    core.map.entry(tx_id).or_default().push(record);
+    self.0.l().push(tx_id, record)
+  }
+
+  pub fn append_logical(&self, tx_id: usize, log: LogicalLog) -> Vec<LogRecord> {
+    let record = LogRecord::new_logical(tx_id, log);
+    self.0.l().push(tx_id, record)
   }
 
   pub fn commit(&self, tx_id: usize) -> Vec<LogRecord> {
     let mut core = self.0.l();
+    core.open.remove(&tx_id);
     let mut records = core.map.remove(&tx_id).unwrap_or_default();
     core
       .size
@@ -58,6 +98,7 @@ impl LogBuffer {
 
   pub fn rollback(&self, tx_id: usize) {
     let mut core = self.0.l();
+    core.open.remove(&tx_id);
     core.map.remove(&tx_id).map(|records| {
       core
         .size
@@ -65,13 +106,100 @@ impl LogBuffer {
     });
   }
 
+  pub fn open_transactions(&self) -> BTreeSet<usize> {
+    self.0.l().open.clone()
+  }
+
+  #[cfg(test)]
   pub fn len(&self) -> usize {
     self.0.l().size
   }
 
   pub fn flush(&self) -> Vec<LogRecord> {
-    let mut core = self.0.l();
-    core.size = 0;
-    core.map.drain_all().into_values().flatten().collect()
+    self.0.l().drain()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::{
+    wal::{LogicalLog, Operation},
+    Page, PAGE_SIZE,
+  };
+
+  use super::LogBuffer;
+
+  #[test]
+  fn _1() {
+    let buffer = LogBuffer::new(usize::MAX);
+    let transactions: Vec<usize> = (0..10).map(|_| buffer.new_transaction().0).collect();
+    for page_index in 0..100 {
+      for &tx_id in &transactions {
+        assert!(buffer
+          .append(tx_id, tx_id * 1000 + page_index, Page::new())
+          .is_empty());
+      }
+    }
+
+    let tx_id = transactions[3];
+    let records = buffer.commit(tx_id);
+    assert_eq!(records.len(), 102);
+    assert!(records.iter().all(|r| r.transaction_id == tx_id));
+    assert!(matches!(
+      records.first().unwrap().operation,
+      Operation::Start
+    ));
+    assert!(matches!(
+      records.last().unwrap().operation,
+      Operation::Commit
+    ));
+    for (i, record) in records[1..101].iter().enumerate() {
+      match &record.operation {
+        Operation::Insert(log) => assert_eq!(log.page_index, tx_id * 1000 + i),
+        _ => panic!("expected insert record"),
+      }
+    }
+
+    assert_eq!(buffer.flush().len(), 9 * 101);
+  }
+
+  #[test]
+  fn _2() {
+    let capacity = 3 * PAGE_SIZE;
+    let buffer = LogBuffer::new(capacity);
+    let (tx_id, _) = buffer.new_transaction();
+    let mut written = 0;
+    for i in 0..50 {
+      let flushed = match i % 3 {
+        0 => buffer.append(tx_id, i, Page::new()),
+        _ => buffer.append_logical(
+          tx_id,
+          LogicalLog {
+            keyspace: vec![],
+            key: vec![7; i * 40],
+            value: (i % 3 == 1).then(Page::new),
+          },
+        ),
+      };
+      written += flushed.len();
+      assert!(buffer.len().le(&capacity));
+    }
+
+    let oversized = LogicalLog {
+      keyspace: vec![],
+      key: vec![7; capacity],
+      value: None,
+    };
+    let flushed = buffer.append_logical(tx_id, oversized);
+    assert!(matches!(
+      flushed.last().unwrap().operation,
+      Operation::LogicalDelete(_)
+    ));
+    assert_eq!(buffer.len(), 0);
+
+    written += flushed.len();
+    assert!(written.gt(&1));
+    // start, every record and the commit.
+    assert_eq!(written + buffer.commit(tx_id).len(), 53);
   }
 }
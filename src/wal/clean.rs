@@ -0,0 +1,98 @@
+use std::{
+  fs::{self, File, OpenOptions},
+  io::{self, Write},
+  path::{Path, PathBuf},
+};
+
+use crate::{Error, Result};
+
+/// State of the wal left by a clean shutdown, which lets the next open
+/// skip the replay. It lives in a file next to the wal and is cleared
+/// once read, so a crash after the open leaves no marker behind.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CleanShutdown {
+  pub last_index: usize,
+  pub last_transaction: usize,
+  /// Page of the wal holding the record of the last index.
+  pub last_page: usize,
+  pub last_checkpoint: usize,
+}
+impl CleanShutdown {
+  const LEN: usize = 32;
+
+  pub fn path(wal_path: &Path) -> PathBuf {
+    let mut path = wal_path.as_os_str().to_owned();
+    path.push(".clean");
+    PathBuf::from(path)
+  }
+
+  pub fn write(&self, path: &Path) -> Result {
+    let mut bytes = Vec::with_capacity(Self::LEN);
+    for v in [
+      self.last_index,
+      self.last_transaction,
+      self.last_page,
+      self.last_checkpoint,
+    ] {
+      bytes.extend_from_slice(&v.to_be_bytes());
+    }
+    let mut file = File::create(path).map_err(Error::IO)?;
+    file.write_all(&bytes).map_err(Error::IO)?;
+    file.sync_all().map_err(Error::IO)
+  }
+
+  /// Reads the marker and clears it durably before returning it.
+  /// A missing or partial marker reads as none.
+  pub fn take(path: &Path) -> Result<Option<Self>> {
+    let bytes = match fs::read(path) {
+      Ok(bytes) => bytes,
+      Err(err) if err.kind().eq(&io::ErrorKind::NotFound) => return Ok(None),
+      Err(err) => return Err(Error::IO(err)),
+    };
+    // truncating and syncing the file needs no sync of the directory.
+    OpenOptions::new()
+      .write(true)
+      .truncate(true)
+      .open(path)
+      .and_then(|file| file.sync_all())
+      .map_err(Error::IO)?;
+    if bytes.len().ne(&Self::LEN) {
+      return Ok(None);
+    }
+
+    let mut values = bytes
+      .chunks_exact(8)
+      .map(|b| usize::from_be_bytes(b.try_into().unwrap()));
+    let mut next = || values.next().unwrap_or_default();
+    Ok(Some(Self {
+      last_index: next(),
+      last_transaction: next(),
+      last_page: next(),
+      last_checkpoint: next(),
+    }))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::CleanShutdown;
+
+  #[test]
+  fn _1() {
+    let path =
+      std::env::temp_dir().join(format!("lfkv-clean-shutdown-{}", std::process::id()));
+    assert_eq!(CleanShutdown::take(&path).unwrap(), None);
+
+    let marker = CleanShutdown {
+      last_index: 120,
+      last_transaction: 7,
+      last_page: 3,
+      last_checkpoint: 118,
+    };
+    marker.write(&path).unwrap();
+    assert_eq!(CleanShutdown::take(&path).unwrap(), Some(marker));
+    assert_eq!(CleanShutdown::take(&path).unwrap(), None);
+
+    std::fs::remove_file(&path).unwrap();
+  }
+}
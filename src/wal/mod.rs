@@ -7,5 +7,11 @@ pub use record::*;
 mod buffer;
 use buffer::*;
 
+mod clean;
+use clean::*;
+
 mod commit;
 pub use commit::*;
+
+mod reader;
+pub use reader::*;
@@ -0,0 +1,70 @@
+use std::{
+  collections::VecDeque,
+  fs::File,
+  io::{ErrorKind, Read},
+  path::Path,
+};
+
+use crate::{disk::Page, Error, Result, Serializable};
+
+use super::{LogEntry, LogRecord, WAL_PAGE_SIZE};
+
+/// Reads the records of a WAL file in file order without opening an engine.
+/// The file is a ring, so the record indexes wrap where the log was rewritten.
+/// Reading stops at the first entry which is empty or torn.
+pub struct WalReader {
+  file: File,
+  pending: VecDeque<LogRecord>,
+  done: bool,
+}
+impl WalReader {
+  pub fn open<T: AsRef<Path>>(path: T) -> Result<Self> {
+    let file = File::open(path).map_err(Error::IO)?;
+    Ok(Self {
+      file,
+      pending: Default::default(),
+      done: false,
+    })
+  }
+
+  pub fn try_next(&mut self) -> Result<Option<LogRecord>> {
+    while self.pending.is_empty() {
+      if self.done {
+        return Ok(None);
+      }
+      match self.read_entry()? {
+        Some(entry) => self.pending.extend(entry.records),
+        None => self.done = true,
+      }
+    }
+    Ok(self.pending.pop_front())
+  }
+
+  fn read_entry(&mut self) -> Result<Option<LogEntry>> {
+    let mut page = Page::<WAL_PAGE_SIZE>::new_empty();
+    if let Err(err) = self.file.read_exact(page.as_mut()) {
+      return match err.kind() {
+        ErrorKind::UnexpectedEof => Ok(None),
+        _ => Err(Error::IO(err)),
+      };
+    }
+    if page.is_empty() {
+      return Ok(None);
+    }
+    Ok(LogEntry::deserialize(&page).ok())
+  }
+}
+impl Iterator for WalReader {
+  type Item = Result<LogRecord>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    match self.try_next() {
+      Ok(record) => record.map(Ok),
+      Err(err) => {
+        self.done = true;
+        self.pending.clear();
+        Some(Err(err))
+      }
+    }
+  }
+}
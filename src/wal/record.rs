@@ -13,7 +13,7 @@ pub struct InsertLog {
   pub data: Page,
 }
 impl InsertLog {
-  fn new(page_index: usize, data: Page) -> Self {
+  pub fn new(page_index: usize, data: Page) -> Self {
     Self { page_index, data }
   }
 }
@@ -26,6 +26,64 @@ impl Clone for InsertLog {
   }
 }
 
+/// Row level change of a key, which is independent of the page format.
+/// The value is none for a delete.
+#[derive(Debug)]
+pub struct LogicalLog {
+  pub keyspace: Vec<u8>,
+  pub key: Vec<u8>,
+  pub value: Option<Page>,
+}
+impl LogicalLog {
+  fn size(&self) -> usize {
+    self
+      .keyspace
+      .len()
+      .add(self.key.len())
+      .add(16)
+      .add(self.value.as_ref().map(|_| PAGE_SIZE).unwrap_or(0))
+  }
+
+  fn write_to(&self, wt: &mut PageWriter<WAL_PAGE_SIZE>) -> crate::Result<()> {
+    for bytes in [&self.keyspace, &self.key] {
+      wt.write(&bytes.len().to_be_bytes())?;
+      wt.write(bytes)?;
+    }
+    if let Some(value) = &self.value {
+      wt.write(value.as_ref())?;
+    }
+    Ok(())
+  }
+
+  fn read_from(
+    sc: &mut PageScanner<WAL_PAGE_SIZE>,
+    with_value: bool,
+  ) -> crate::Result<Self> {
+    let len = sc.read_usize()?;
+    let keyspace = sc.read_n(len)?.to_vec();
+    let len = sc.read_usize()?;
+    let key = sc.read_n(len)?.to_vec();
+    let value = match with_value {
+      true => Some(sc.read_n(PAGE_SIZE)?.into()),
+      false => None,
+    };
+    Ok(Self {
+      keyspace,
+      key,
+      value,
+    })
+  }
+}
+impl Clone for LogicalLog {
+  fn clone(&self) -> Self {
+    Self {
+      keyspace: self.keyspace.clone(),
+      key: self.key.clone(),
+      value: self.value.as_ref().map(|v| v.copy()),
+    }
+  }
+}
+
 #[derive(Debug, Clone)]
 pub enum Operation {
   Start,
@@ -33,6 +91,8 @@ pub enum Operation {
   Abort,
   Checkpoint(usize),
   Insert(InsertLog),
+  LogicalInsert(LogicalLog),
+  LogicalDelete(LogicalLog),
 }
 impl Operation {
   fn size(&self) -> usize {
@@ -42,6 +102,8 @@ impl Operation {
       Operation::Abort => 1,
       Operation::Checkpoint(_) => 9,
       Operation::Insert(_) => 8 + PAGE_SIZE,
+      Operation::LogicalInsert(log) => 1 + log.size(),
+      Operation::LogicalDelete(log) => 1 + log.size(),
     }
   }
 }
@@ -62,7 +124,7 @@ impl LogRecord {
   }
 
   pub fn new_abort(transaction_id: usize) -> Self {
-    Self::new(0, transaction_id, Operation::Commit)
+    Self::new(0, transaction_id, Operation::Abort)
   }
 
   pub fn new_insert(transaction_id: usize, page_index: usize, data: Page) -> Self {
@@ -73,6 +135,14 @@ impl LogRecord {
     )
   }
 
+  pub fn new_logical(transaction_id: usize, log: LogicalLog) -> Self {
+    let operation = match log.value {
+      Some(_) => Operation::LogicalInsert(log),
+      None => Operation::LogicalDelete(log),
+    };
+    Self::new(0, transaction_id, operation)
+  }
+
   pub fn new_checkpoint(applied: usize) -> Self {
     Self::new(0, 0, Operation::Checkpoint(applied))
   }
@@ -115,6 +185,14 @@ impl LogRecord {
         wt.write(log.page_index.to_be_bytes().as_ref())?;
         wt.write(log.data.as_ref())?;
       }
+      Operation::LogicalInsert(log) => {
+        wt.write(&[5])?;
+        log.write_to(wt)?;
+      }
+      Operation::LogicalDelete(log) => {
+        wt.write(&[6])?;
+        log.write_to(wt)?;
+      }
     }
     Ok(())
   }
@@ -135,6 +213,8 @@ impl LogRecord {
         let data = sc.read_n(PAGE_SIZE)?.into();
         Operation::Insert(InsertLog::new(page_index, data))
       }
+      5 => Operation::LogicalInsert(LogicalLog::read_from(sc, true)?),
+      6 => Operation::LogicalDelete(LogicalLog::read_from(sc, false)?),
       _ => return Err(Error::Invalid),
     };
     return Ok(Self::new(index, transaction_id, operation));
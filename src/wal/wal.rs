@@ -2,38 +2,84 @@ use std::{
   collections::{BTreeMap, BTreeSet},
   ops::{Add, AddAssign, DivAssign, Mul},
   path::PathBuf,
-  sync::{Arc, RwLock},
+  sync::{
+    atomic::{AtomicBool, AtomicUsize, Ordering},
+    Arc, Mutex, RwLock,
+  },
   time::Duration,
 };
 
+use crossbeam::channel::Receiver;
+
 use crate::{
   buffer::BufferPool,
-  disk::{Finder, FinderConfig},
-  logger, size, BackgroundThread, BackgroundWork, DrainAll, Page, Result,
-  ShortenedRwLock,
+  disk::{DiskStats, Finder, FinderConfig, IoBudget, SyncMode},
+  logger, size, BackgroundThread, BackgroundWork, DrainAll, Error, Page, Result,
+  Serializable, ShortenedMutex, ShortenedRwLock, UnwrappedReceiver,
+};
+
+use super::{
+  CleanShutdown, CommitInfo, InsertLog, LogBuffer, LogEntry, LogRecord, LogicalLog,
+  Operation, WAL_PAGE_SIZE,
 };
 
-use super::{CommitInfo, LogBuffer, LogEntry, LogRecord, Operation, WAL_PAGE_SIZE};
+/// Logical mode records the changed keys and values next to the page images,
+/// so the log can be consumed without knowing the page format.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogMode {
+  #[default]
+  Physical,
+  Logical,
+}
 
 #[derive(Debug, Clone)]
 pub struct WriteAheadLogConfig {
   pub path: PathBuf,
+  /// Bytes of records buffered before they are written, as a hard cap.
   pub max_buffer_size: usize,
   pub checkpoint_interval: Duration,
   pub checkpoint_count: usize,
   pub group_commit_delay: Duration,
   pub group_commit_count: usize,
   pub max_file_size: usize,
+  pub repair: bool,
+  pub file_mode: Option<u32>,
+  pub sync_mode: SyncMode,
+  pub log_mode: LogMode,
+  pub recovery_threads: usize,
+  pub io_budget: Option<Arc<IoBudget>>,
+  pub in_memory: bool,
 }
 
+/// Receivers of the batches holding the written records,
+/// which complete once the batch is fsynced.
+type Durable = Vec<Receiver<Result>>;
+
+/// Callback run once a commit is durable, with the result of its fsync.
+pub type CommitAck = Box<dyn FnOnce(Result) + Send>;
+
 pub struct WriteAheadLog {
   buffer: Arc<LogBuffer>,
   commit_c: Arc<BackgroundThread<CommitInfo, Result>>,
   disk: Arc<Finder<WAL_PAGE_SIZE>>,
-  io_c: Arc<BackgroundThread<Vec<LogRecord>, Result>>,
-  checkpoint_c: Arc<BackgroundThread<()>>,
+  io_c: Arc<BackgroundThread<Vec<LogRecord>, Result<Durable>>>,
+  checkpoint_c: Arc<BackgroundThread<(), Result<usize>>>,
+  ack_c: BackgroundThread<(Durable, CommitAck)>,
   config: WriteAheadLogConfig,
   last_index: Arc<RwLock<usize>>,
+  /// Page of the wal the last records were written to.
+  last_page: Arc<AtomicUsize>,
+  rolled_back: Mutex<Vec<usize>>,
+  redone: Mutex<Option<usize>>,
+  /// Set when the empty wal was rebuilt from the data file at open.
+  repaired: AtomicBool,
+  /// Key changes committed after the last checkpoint, found at replay.
+  replayed_changes: Mutex<Vec<LogicalLog>>,
+  /// Set once a write fails with a full disk, and cleared by `resume_writes`.
+  disk_full: Arc<AtomicBool>,
+  /// Held by the io thread while it writes a batch,
+  /// so a compaction sees the pages of the wal stay put.
+  io_lock: Arc<Mutex<()>>,
 }
 impl WriteAheadLog {
   pub fn open(
@@ -48,14 +94,18 @@ impl WriteAheadLog {
       path: config.path.clone(),
       batch_delay: config.group_commit_delay,
       batch_size: config.group_commit_count,
+      file_mode: config.file_mode,
+      sync_mode: config.sync_mode,
+      io_budget: config.io_budget.clone(),
+      in_memory: config.in_memory,
     };
     let disk = Arc::new(Finder::open(disk_config)?);
-    let buffer = Arc::new(LogBuffer::new());
+    let buffer = Arc::new(LogBuffer::new(config.max_buffer_size));
 
     let last_index = Arc::new(RwLock::new(0));
 
     let io_c = Arc::new(BackgroundThread::empty("wal io", WAL_PAGE_SIZE.mul(1000)));
-    let checkpoint_c = Arc::new(BackgroundThread::empty("wal checkpoint", size::kb(2)));
+    let checkpoint_c = Arc::new(BackgroundThread::empty("wal checkpoint", size::kb(64)));
 
     let core = Self::new(
       buffer,
@@ -67,29 +117,60 @@ impl WriteAheadLog {
       last_index,
     );
 
-    let (last_transaction, cursor) = core.replay(buffer_pool)?;
+    let clean = match core.config.in_memory {
+      true => None,
+      false => CleanShutdown::take(&CleanShutdown::path(&core.config.path))?,
+    };
+    let (mut last_transaction, cursor, last_checkpoint) =
+      match clean.filter(|clean| core.is_clean(clean)) {
+        Some(clean) => core.resume_clean(clean),
+        None => core.replay(buffer_pool)?,
+      };
+    if core.config.repair && core.last_index.rl().eq(&0) {
+      last_transaction = core.repair(buffer_pool)?;
+    }
 
     core.buffer.initial_state(last_transaction);
-    Ok(core.start_checkpoint(flush_c).start_io(cursor))
+    Ok(
+      core
+        .start_checkpoint(flush_c, last_checkpoint)
+        .start_io(cursor),
+    )
   }
 
   fn new(
     buffer: Arc<LogBuffer>,
     commit_c: Arc<BackgroundThread<CommitInfo, Result>>,
     disk: Arc<Finder<WAL_PAGE_SIZE>>,
-    io_c: Arc<BackgroundThread<Vec<LogRecord>, Result>>,
-    checkpoint_c: Arc<BackgroundThread<()>>,
+    io_c: Arc<BackgroundThread<Vec<LogRecord>, Result<Durable>>>,
+    checkpoint_c: Arc<BackgroundThread<(), Result<usize>>>,
     config: WriteAheadLogConfig,
     last_index: Arc<RwLock<usize>>,
   ) -> Self {
+    let disk_full: Arc<AtomicBool> = Default::default();
+    let disk_full_cloned = disk_full.clone();
     Self {
       buffer,
       commit_c,
       disk,
       io_c,
       checkpoint_c,
+      ack_c: BackgroundThread::new(
+        "wal ack",
+        size::mb(2),
+        BackgroundWork::no_timeout(move |(durable, ack): (Durable, CommitAck)| {
+          ack(suspend_on_full(&disk_full_cloned, wait_durable(durable)))
+        }),
+      ),
       config,
       last_index,
+      last_page: Default::default(),
+      rolled_back: Default::default(),
+      redone: Default::default(),
+      repaired: AtomicBool::new(false),
+      replayed_changes: Default::default(),
+      disk_full,
+      io_lock: Default::default(),
     }
   }
 
@@ -100,98 +181,359 @@ impl WriteAheadLog {
     let checkpoint_c = self.checkpoint_c.clone();
     let last_index = self.last_index.clone();
     let commit_c = self.commit_c.clone();
+    let last_page = self.last_page.clone();
+    let io_lock = self.io_lock.clone();
     let mut current = LogEntry::new();
     let mut counter = 0;
 
     self.io_c.set_work(BackgroundWork::no_timeout(
       move |records: Vec<LogRecord>| {
+        let _io = io_lock.l();
         counter += records.len();
+        let mut commits = vec![];
+        let mut durable = vec![];
         for mut record in records {
           let mut l = last_index.wl();
           record.assign_id(l.add(1));
           if let Operation::Commit = record.operation {
-            commit_c.send(CommitInfo::new(record.transaction_id, record.index));
+            commits
+              .push(commit_c.send(CommitInfo::new(record.transaction_id, record.index)));
           }
 
           if !current.is_available(&record) {
             let entry = current.drain_all();
-            disk.batch_write_from(cursor, &entry)?;
+            durable.push(disk.batch_write_queued(cursor, entry.serialize()?));
             cursor = cursor.add(1).rem_euclid(max_file_size);
           }
           current.append(record);
           l.add_assign(1);
         }
 
-        disk.batch_write_from(cursor, &current)?;
+        durable.push(disk.batch_write_queued(cursor, current.serialize()?));
+        last_page.store(cursor, Ordering::SeqCst);
+        // readers skip the pages of a transaction until its commit is applied,
+        // so the commit returns only after the buffer pool has applied it.
+        for commit in commits {
+          commit.must_recv()?;
+        }
 
         if checkpoint_count.lt(&counter) {
           checkpoint_c.send(());
           counter = 0;
         }
-        Ok(())
+        Ok(durable)
       },
     ));
     self
   }
 
-  fn start_checkpoint(self, flush_c: BackgroundThread<(), Option<usize>>) -> Self {
+  /// Dirty pages of running transactions can be flushed by a checkpoint,
+  /// so every buffered record is written and fsynced before the data pages.
+  fn start_checkpoint(
+    self,
+    flush_c: BackgroundThread<(), Option<usize>>,
+    mut last_checkpoint: usize,
+  ) -> Self {
     let io_c = self.io_c.clone();
+    let buffer = self.buffer.clone();
     self.checkpoint_c.set_work(BackgroundWork::with_timeout(
       self.config.checkpoint_interval,
       move |_| {
+        write_durable(&io_c, buffer.flush())?;
         if let Some(to_be_apply) = flush_c.send_await(()) {
-          io_c
-            .send_await(vec![LogRecord::new_checkpoint(to_be_apply)])
-            .ok();
+          write_durable(&io_c, vec![LogRecord::new_checkpoint(to_be_apply)])?;
+          last_checkpoint = to_be_apply;
         }
+        Ok(last_checkpoint)
       },
     ));
     self
   }
 
+  /// Runs a checkpoint immediately and returns the log index
+  /// up to which the data file is durable.
+  pub fn checkpoint(&self) -> Result<usize> {
+    self.checkpoint_c.send_await(())
+  }
+
+  /// Runs a checkpoint and frees the oldest pages of the wal holding only
+  /// records at or below it, and returns the bytes reclaimed. The run of
+  /// freed pages stops at the start of a transaction which is still open or
+  /// ends after the checkpoint, since the replay needs the start to apply
+  /// its end. Freed pages read as not found, and the replay skips them.
+  pub fn compact(&self) -> Result<usize> {
+    let checkpoint = self.checkpoint()?;
+    // transactions which are open or end after the checkpoint, and need their
+    // start. those started from here log their start after the checkpoint.
+    let mut live = self.buffer.open_transactions();
+    let _io = self.io_lock.l();
+
+    let len = self.disk.len()?.min(self.config.max_file_size);
+    let mut pages = vec![];
+    for index in 0..len {
+      let entry: LogEntry = match self.disk.read(index) {
+        Ok(page) => match page.deserialize() {
+          Ok(entry) => entry,
+          Err(_) => continue,
+        },
+        Err(Error::NotFound) => continue,
+        Err(err) => return Err(err),
+      };
+      let (first, last) = match (entry.records.first(), entry.records.last()) {
+        (Some(first), Some(last)) => (first.index, last.index),
+        _ => continue,
+      };
+      let mut started = vec![];
+      for record in entry.records {
+        match record.operation {
+          Operation::Start => started.push(record.transaction_id),
+          Operation::Commit | Operation::Abort if record.index.gt(&checkpoint) => {
+            live.insert(record.transaction_id);
+          }
+          _ => {}
+        }
+      }
+      pages.push((first, last, index, started));
+    }
+    pages.sort_by_key(|(first, ..)| *first);
+
+    let count = pages
+      .iter()
+      .take_while(|(_, last, _, started)| {
+        last.le(&checkpoint) && !started.iter().any(|tx_id| live.contains(tx_id))
+      })
+      .count();
+    for (_, _, index, ..) in &pages[..count] {
+      self.disk.punch_hole(*index)?;
+    }
+    if count.gt(&0) {
+      logger::info(format!(
+        "wal compacted up to checkpoint {checkpoint}, {count} pages freed"
+      ));
+    }
+    Ok(count.mul(WAL_PAGE_SIZE))
+  }
+
   pub fn append(&self, tx_id: usize, page_index: usize, data: Page) -> Result<()> {
-    self.buffer.append(tx_id, page_index, data);
-    if self.buffer.len().ge(&self.config.max_buffer_size) {
-      self.io_c.send_await(self.buffer.flush())?;
+    self.check_writable()?;
+    let flushed = self.buffer.append(tx_id, page_index, data);
+    self.write_flushed(flushed)
+  }
+
+  /// Records the row level change of the transaction in logical mode.
+  pub fn append_logical(&self, tx_id: usize, log: LogicalLog) -> Result<()> {
+    if self.config.log_mode.eq(&LogMode::Physical) {
+      return Ok(());
     }
-    Ok(())
+    self.check_writable()?;
+    let flushed = self.buffer.append_logical(tx_id, log);
+    self.write_flushed(flushed)
   }
 
   pub fn new_transaction(&self) -> Result<(usize, usize)> {
-    let tx_id = self.buffer.new_transaction();
-    if self.buffer.len().ge(&self.config.max_buffer_size) {
-      self.io_c.send_await(self.buffer.flush())?;
-    }
+    let (tx_id, flushed) = self.buffer.new_transaction();
+    self.write_flushed(flushed)?;
     Ok((tx_id, *self.last_index.rl()))
   }
 
+  /// Writes the records taken out of the full buffer to make room.
+  fn write_flushed(&self, records: Vec<LogRecord>) -> Result<()> {
+    if records.is_empty() {
+      return Ok(());
+    }
+    self.suspend_on_full(write_durable(&self.io_c, records))
+  }
+
+  /// Fails with `Error::DiskFull` while writes are suspended.
+  pub fn check_writable(&self) -> Result<()> {
+    match self.is_disk_full() {
+      true => Err(Error::DiskFull),
+      false => Ok(()),
+    }
+  }
+
+  pub fn is_disk_full(&self) -> bool {
+    self.disk_full.load(Ordering::SeqCst)
+  }
+
+  /// Lets writes through again after space is freed on the disk.
+  pub fn resume_writes(&self) {
+    if self.disk_full.swap(false, Ordering::SeqCst) {
+      logger::info("writes to the wal are resumed");
+    }
+  }
+
+  fn suspend_on_full<T>(&self, result: Result<T>) -> Result<T> {
+    suspend_on_full(&self.disk_full, result)
+  }
+
+  /// Index of the last record, which a new reader takes as its snapshot.
+  pub fn last_index(&self) -> usize {
+    *self.last_index.rl()
+  }
+
   pub fn commit(&self, tx_id: usize) -> Result<()> {
     let records = self.buffer.commit(tx_id);
-    self.io_c.send_await(records)
+    self.suspend_on_full(write_durable(&self.io_c, records))
   }
 
-  pub fn before_shutdown(&self) {
-    self.checkpoint_c.send(());
+  /// Commits without waiting for the fsync, and runs the ack on the ack
+  /// thread once the commit record is durable. Acks run in commit order.
+  pub fn commit_with_ack(&self, tx_id: usize, ack: CommitAck) -> Result<()> {
+    let records = self.buffer.commit(tx_id);
+    let durable = self.suspend_on_full(self.io_c.send_await(records))?;
+    self.ack_c.send((durable, ack));
+    Ok(())
+  }
+
+  /// Drops the records of the transaction still in the buffer,
+  /// and logs the abort for the records already written.
+  /// While writes are suspended the abort is not logged, since the replay
+  /// rolls back the records of a transaction without a commit anyway.
+  pub fn abort(&self, tx_id: usize) -> Result<()> {
+    self.buffer.rollback(tx_id);
+    if self.is_disk_full() {
+      return Ok(());
+    }
+    self.suspend_on_full(write_durable(&self.io_c, vec![LogRecord::new_abort(tx_id)]))
+  }
+
+  /// Drops the records of a transaction which wrote nothing,
+  /// so it ends without writing a commit record.
+  pub fn discard(&self, tx_id: usize) {
+    self.buffer.rollback(tx_id);
+  }
+
+  /// Discards the transaction, and runs the ack on the ack thread
+  /// after the acks of the commits before it.
+  pub fn discard_with_ack(&self, tx_id: usize, ack: CommitAck) {
+    self.discard(tx_id);
+    self.ack_c.send((vec![], ack));
+  }
+
+  /// Takes the pages written by transactions which were not committed
+  /// before the last shutdown, found at replay.
+  pub fn take_rolled_back(&self) -> Vec<usize> {
+    std::mem::take(&mut self.rolled_back.l())
+  }
+
+  /// Takes the key changes committed after the last checkpoint in commit
+  /// order, found at replay of a wal written in logical mode.
+  pub fn take_replayed_changes(&self) -> Vec<LogicalLog> {
+    std::mem::take(&mut self.replayed_changes.l())
+  }
+
+  pub fn log_mode(&self) -> LogMode {
+    self.config.log_mode
+  }
+
+  pub fn disk_stats(&self) -> DiskStats {
+    self.disk.stats()
+  }
+
+  #[cfg(any(test, feature = "fault-injection"))]
+  pub fn set_disk_fault(&self, spec: crate::disk::FaultSpec) {
+    self.disk.set_fault(spec);
+  }
+
+  /// True if the wal was empty at open and rebuilt from the data file,
+  /// which has to be verified before anything is written on it.
+  pub fn is_repaired(&self) -> bool {
+    self.repaired.load(Ordering::SeqCst)
+  }
+
+  /// Inserts redone by the replay at open,
+  /// none if the replay was skipped after a clean shutdown.
+  pub fn redone(&self) -> Option<usize> {
+    *self.redone.l()
+  }
+
+  /// Runs the last checkpoint and stops the threads. If the shutdown is clean,
+  /// with no transaction left open, the state of the wal is recorded
+  /// so the next open can skip the replay.
+  pub fn before_shutdown(&self, clean: bool) {
+    let checkpoint = self.checkpoint_c.send_await(());
     self.commit_c.close();
     self.checkpoint_c.close();
     self.io_c.close();
+    self.ack_c.close();
     self.disk.close();
+
+    let last_checkpoint = match checkpoint {
+      Ok(last_checkpoint) if clean && !self.config.in_memory => last_checkpoint,
+      _ => return,
+    };
+    let marker = CleanShutdown {
+      last_index: *self.last_index.rl(),
+      last_transaction: self.buffer.last_transaction(),
+      last_page: self.last_page.load(Ordering::SeqCst),
+      last_checkpoint,
+    };
+    if let Err(err) = marker.write(&CleanShutdown::path(&self.config.path)) {
+      logger::warn(format!("failed to record the clean shutdown {:?}", err));
+    }
   }
 
-  fn replay(&self, buffer_pool: &Arc<BufferPool>) -> Result<(usize, usize)> {
+  /// Checks that the last record of the marker is where it says,
+  /// so a marker left next to another wal is not trusted.
+  fn is_clean(&self, clean: &CleanShutdown) -> bool {
+    if clean.last_page.ge(&self.config.max_file_size) {
+      return false;
+    }
+    self
+      .disk
+      .read(clean.last_page)
+      .and_then(|page| LogEntry::deserialize(&page))
+      .is_ok_and(|entry| {
+        entry
+          .records
+          .last()
+          .is_some_and(|record| record.index.eq(&clean.last_index))
+      })
+  }
+
+  fn resume_clean(&self, clean: CleanShutdown) -> (usize, usize, usize) {
+    *self.last_index.wl() = clean.last_index;
+    let cursor = clean.last_page.add(1).rem_euclid(self.config.max_file_size);
+    logger::info(format!(
+      "wal replay skipped after clean shutdown, last tx {}, cursor {cursor}",
+      clean.last_transaction
+    ));
+    (clean.last_transaction, cursor, clean.last_checkpoint)
+  }
+
+  fn repair(&self, buffer_pool: &Arc<BufferPool>) -> Result<usize> {
+    let (last_index, last_transaction) = buffer_pool.last_committed()?;
+    logger::warn(format!(
+      "wal is empty and will be repaired from data file, last index {last_index}, last tx {last_transaction}"
+    ));
+    *self.last_index.wl() = last_index;
+    // a data file without commits is a new database, with nothing to verify.
+    self.repaired.store(last_index.gt(&0), Ordering::SeqCst);
+    Ok(last_transaction)
+  }
+
+  fn replay(&self, buffer_pool: &Arc<BufferPool>) -> Result<(usize, usize, usize)> {
     let mut cursor = 0;
     let mut records: BTreeMap<usize, LogRecord> = BTreeMap::new();
 
     let mut cursor_index = 0;
-    for index in 0..self.config.max_file_size {
+    // pages freed by a compaction read as not found before the end of the file.
+    let len = self.disk.len()?.min(self.config.max_file_size);
+    for index in 0..len {
       let entry: LogEntry = match self.disk.read(index) {
         Ok(page) => match page.deserialize() {
           Ok(e) => e,
           Err(_) => continue,
         },
+        Err(Error::NotFound) => continue,
         Err(_) => break,
       };
       for record in entry.records {
+        // indexes start from 1, so this is the zeroed tail of a torn write.
+        if record.index.eq(&0) {
+          break;
+        }
         if record.index.lt(&cursor_index) {
           cursor = index;
         }
@@ -201,13 +543,129 @@ impl WriteAheadLog {
       }
     }
 
+    let replayed = ReplayState::from_records(records.into_values());
+    let redo = replayed.redo.len();
+    apply_redo(buffer_pool, replayed.redo, self.config.recovery_threads)?;
+    for (tx_id, commit_index) in replayed.commits {
+      self
+        .commit_c
+        .send_await(CommitInfo::new(tx_id, commit_index))?;
+    }
+    let last_index = replayed.last_index;
+    let last_transaction = replayed.last_transaction;
+    *self.redone.l() = Some(redo);
+    buffer_pool.abort_crashed(&replayed.rollback)?;
+    let rollback = replayed.rollback.len();
+    let logical = replayed.logical.len();
+    *self.rolled_back.l() = replayed.rollback.iter().map(|(_, i)| *i).collect();
+    *self.replayed_changes.l() =
+      replayed.logical.into_iter().map(|(_, log)| log).collect();
+
+    self.checkpoint_c.send(());
+    *self.last_index.wl() = last_index;
+
+    logger::info(format!(
+      "wal replay last tx {last_transaction}, cursor {cursor}, {redo} inserts redone, {rollback} inserts to be rollback, {logical} logical records"
+    ));
+    Ok((last_transaction, cursor, replayed.last_checkpoint))
+  }
+}
+
+/// Writes the records and waits until they are fsynced.
+/// The io thread only queues the pages for the disk batch,
+/// so records sent while a batch waits for its fsync share it.
+fn write_durable(
+  io_c: &BackgroundThread<Vec<LogRecord>, Result<Durable>>,
+  records: Vec<LogRecord>,
+) -> Result {
+  wait_durable(io_c.send_await(records)?)
+}
+
+/// Suspends the writes if the result failed with a full disk,
+/// so no more records are written past the failed ones.
+fn suspend_on_full<T>(disk_full: &AtomicBool, result: Result<T>) -> Result<T> {
+  if let Err(Error::DiskFull) = result {
+    if !disk_full.swap(true, Ordering::SeqCst) {
+      logger::error("disk is full, writes to the wal are suspended");
+    }
+  }
+  result
+}
+
+fn wait_durable(durable: Durable) -> Result {
+  for batch in durable {
+    batch.must_recv()?;
+  }
+  Ok(())
+}
+
+/// Redo of a page has to follow the log order while different pages are
+/// independent, so pages are sharded across the threads by index
+/// and every shard is applied in order.
+fn apply_redo(
+  buffer_pool: &BufferPool,
+  redo: Vec<(usize, InsertLog)>,
+  threads: usize,
+) -> Result {
+  if threads.le(&1) {
+    for (tx_id, log) in redo {
+      buffer_pool.insert(tx_id, log.page_index, log.data)?;
+    }
+    return Ok(());
+  }
+
+  let mut shards: Vec<Vec<(usize, InsertLog)>> = (0..threads).map(|_| vec![]).collect();
+  for (tx_id, log) in redo {
+    shards[log.page_index.rem_euclid(threads)].push((tx_id, log));
+  }
+  std::thread::scope(|scope| {
+    let handles = shards
+      .into_iter()
+      .map(|shard| {
+        scope.spawn(move || -> Result {
+          for (tx_id, log) in shard {
+            buffer_pool.insert(tx_id, log.page_index, log.data)?;
+          }
+          Ok(())
+        })
+      })
+      .collect::<Vec<_>>();
+    handles.into_iter().try_for_each(|handle| {
+      handle
+        .join()
+        .unwrap_or_else(|_| Err(Error::unknown("redo thread panicked")))
+    })
+  })
+}
+
+/// A checkpoint only guarantees that pages up to its applied index are on disk.
+/// A transaction can start or commit around the checkpoint record while its inserts
+/// are after the applied index, so only inserts are pruned at a checkpoint and
+/// transaction statuses are kept for the whole replay.
+struct ReplayState {
+  last_index: usize,
+  last_transaction: usize,
+  last_checkpoint: usize,
+  redo: Vec<(usize, InsertLog)>,
+  /// Commit indexes of the transactions with redone inserts.
+  commits: BTreeMap<usize, usize>,
+  rollback: Vec<(usize, usize)>,
+  logical: Vec<(usize, LogicalLog)>,
+}
+impl ReplayState {
+  fn from_records<T>(records: T) -> Self
+  where
+    T: IntoIterator<Item = LogRecord>,
+  {
     let mut last_index = 0;
     let mut last_transaction = 0;
-    let mut committed = BTreeSet::new();
+    let mut last_checkpoint = 0;
+    let mut committed = BTreeMap::new();
     let mut aborted = BTreeSet::new();
     let mut started = BTreeSet::new();
     let mut inserts = BTreeMap::new();
-    for record in records.into_values() {
+    let mut logical = BTreeMap::new();
+    for record in records {
       last_transaction = record.transaction_id.max(last_transaction);
       last_index = record.index.max(last_index);
       match record.operation {
@@ -216,7 +674,7 @@ impl WriteAheadLog {
         }
         Operation::Commit => {
           started.remove(&record.transaction_id).then(|| {
-            committed.insert(record.transaction_id);
+            committed.insert(record.transaction_id, record.index);
           });
         }
         Operation::Abort => {
@@ -226,33 +684,268 @@ impl WriteAheadLog {
         }
         Operation::Checkpoint(i) => {
           inserts = inserts.split_off(&i);
-          started.clear();
-          committed.clear();
-          aborted.clear();
+          logical = logical.split_off(&i);
+          last_checkpoint = i;
         }
         Operation::Insert(log) => {
           inserts.insert(record.index, (record.transaction_id, log));
         }
+        Operation::LogicalInsert(log) | Operation::LogicalDelete(log) => {
+          logical.insert(record.index, (record.transaction_id, log));
+        }
       }
     }
+    // transactions interleave in the log, so the changes are applied
+    // in the order of their commits.
+    let mut logical = logical
+      .into_values()
+      .filter_map(|(tx_id, log)| committed.get(&tx_id).map(|&c| (c, tx_id, log)))
+      .collect::<Vec<_>>();
+    logical.sort_by_key(|(commit_index, _, _)| *commit_index);
+    let logical = logical
+      .into_iter()
+      .map(|(_, tx_id, log)| (tx_id, log))
+      .collect();
 
-    let mut to_be_rollback = vec![];
-
+    let mut redo = vec![];
+    let mut commits = BTreeMap::new();
+    let mut rollback = vec![];
     for (tx_id, log) in inserts.into_values() {
-      if committed.contains(&tx_id) {
-        //TODO error occurs in here
-        buffer_pool.insert(tx_id, log.page_index, log.data)?;
+      if let Some(&commit_index) = committed.get(&tx_id) {
+        commits.insert(tx_id, commit_index);
+        redo.push((tx_id, log));
       } else {
-        to_be_rollback.push((tx_id, log.page_index))
+        rollback.push((tx_id, log.page_index));
       }
     }
 
-    self.checkpoint_c.send(());
-    *self.last_index.wl() = last_index;
+    Self {
+      last_index,
+      last_transaction,
+      last_checkpoint,
+      redo,
+      commits,
+      rollback,
+      logical,
+    }
+  }
+}
 
-    logger::info(format!(
-      "wal replay last tx {last_transaction}, cursor {cursor}"
-    ));
-    Ok((last_transaction, cursor))
+#[cfg(test)]
+mod tests {
+  use std::{
+    collections::BTreeMap,
+    ops::Mul,
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant},
+  };
+
+  use crate::{
+    buffer::{BufferPool, RollbackStorage, RollbackStorageConfig},
+    disk::{Finder, FinderConfig},
+    size,
+    wal::{InsertLog, LogEntry, LogRecord, LogicalLog},
+    Page, Serializable,
+  };
+
+  use super::{apply_redo, ReplayState};
+
+  fn with_index(mut record: LogRecord, index: usize) -> LogRecord {
+    record.assign_id(index);
+    record
+  }
+
+  #[test]
+  fn _1() {
+    let records = vec![
+      with_index(LogRecord::new_start(1), 1),
+      with_index(LogRecord::new_start(2), 2),
+      with_index(LogRecord::new_insert(1, 10, Page::new()), 3),
+      with_index(LogRecord::new_insert(2, 11, Page::new()), 4),
+      with_index(LogRecord::new_checkpoint(3), 5),
+      with_index(LogRecord::new_commit(1), 6),
+      with_index(LogRecord::new_abort(2), 7),
+    ];
+
+    let state = ReplayState::from_records(records);
+    assert_eq!(state.last_index, 7);
+    assert_eq!(state.last_transaction, 2);
+    assert_eq!(state.last_checkpoint, 3);
+    assert_eq!(state.redo.len(), 1);
+    assert_eq!(state.redo[0].0, 1);
+    assert_eq!(state.redo[0].1.page_index, 10);
+    assert_eq!(state.rollback, vec![(2, 11)]);
+  }
+
+  #[test]
+  fn _2() {
+    let records = vec![
+      with_index(LogRecord::new_start(1), 1),
+      with_index(LogRecord::new_insert(1, 10, Page::new()), 2),
+      with_index(LogRecord::new_commit(1), 3),
+      with_index(LogRecord::new_checkpoint(3), 4),
+    ];
+
+    let state = ReplayState::from_records(records);
+    assert!(state.redo.is_empty());
+    assert!(state.rollback.is_empty());
+  }
+
+  #[test]
+  fn _3() {
+    let logical = |tx_id, key: &[u8], value: Option<u8>| {
+      LogRecord::new_logical(
+        tx_id,
+        LogicalLog {
+          keyspace: vec![],
+          key: key.to_vec(),
+          value: value.map(|v| {
+            let mut page = Page::new();
+            page.writer().write(&[v]).unwrap();
+            page
+          }),
+        },
+      )
+    };
+    let mut entry = LogEntry::new();
+    for record in [
+      LogRecord::new_start(1),
+      logical(1, b"a", Some(1)),
+      logical(1, b"b", Some(2)),
+      LogRecord::new_commit(1),
+      LogRecord::new_start(2),
+      logical(2, b"a", None),
+      logical(2, b"c", Some(3)),
+      LogRecord::new_commit(2),
+      LogRecord::new_start(3),
+      logical(3, b"b", None),
+    ] {
+      entry.append(record);
+    }
+    for (i, record) in entry.records.iter_mut().enumerate() {
+      record.assign_id(i + 1);
+    }
+    let entry: LogEntry = entry.serialize().unwrap().deserialize().unwrap();
+
+    let state = ReplayState::from_records(entry.records);
+    let mut rows = BTreeMap::new();
+    for (_, log) in state.logical {
+      match log.value {
+        Some(value) => rows.insert(log.key, value.scanner().read().unwrap()),
+        None => rows.remove(&log.key),
+      };
+    }
+    assert_eq!(
+      rows,
+      BTreeMap::from([(b"b".to_vec(), 2), (b"c".to_vec(), 3)])
+    );
+  }
+
+  fn buffer_pool(dir: &Path) -> BufferPool {
+    std::fs::create_dir_all(dir).unwrap();
+    let rollback = Arc::new(
+      RollbackStorage::open(RollbackStorageConfig {
+        fsync_delay: Duration::from_millis(10),
+        fsync_count: 100,
+        max_cache_size: size::mb(4),
+        max_file_size: size::mb(8),
+        path: dir.join("undo.db"),
+        file_mode: None,
+        sync_mode: Default::default(),
+        io_budget: None,
+        in_memory: false,
+      })
+      .unwrap(),
+    );
+    let disk = Arc::new(
+      Finder::open(FinderConfig {
+        path: dir.join("data.db"),
+        batch_delay: Duration::from_millis(10),
+        batch_size: 100,
+        file_mode: None,
+        sync_mode: Default::default(),
+        io_budget: None,
+        in_memory: false,
+      })
+      .unwrap(),
+    );
+    BufferPool::generate(rollback, disk, size::mb(4), Default::default()).0
+  }
+
+  #[test]
+  fn _4() {
+    let redo = (0..240u32)
+      .map(|i| {
+        let mut page = Page::new();
+        page.writer().write_u32(i).unwrap();
+        let tx_id = (i / 10) as usize + 1;
+        (tx_id, InsertLog::new((i % 37) as usize, page))
+      })
+      .collect::<Vec<_>>();
+
+    let dir = std::env::temp_dir().join(format!("lfkv-wal-redo-{}", std::process::id()));
+    let serial = buffer_pool(&dir.join("serial"));
+    let parallel = buffer_pool(&dir.join("parallel"));
+    let started = Instant::now();
+    apply_redo(&serial, redo.clone(), 1).unwrap();
+    let serial_elapsed = started.elapsed();
+    let started = Instant::now();
+    apply_redo(&parallel, redo, 4).unwrap();
+    // every redo waits for the fsync of its undo record,
+    // which the shards share instead of waiting in turn.
+    assert!(started.elapsed().mul(2).lt(&serial_elapsed));
+
+    for index in 0..37 {
+      let expected = serial.get_latest(index).unwrap();
+      assert_eq!(parallel.get_latest(index).unwrap(), expected);
+      let last = (index as u32..240).step_by(37).next_back().unwrap();
+      assert_eq!(expected.scanner().read_u32().unwrap(), last);
+    }
+
+    serial.before_shutdown();
+    parallel.before_shutdown();
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn _5() {
+    let logical = |tx_id, key: &[u8], value: u8| {
+      let mut page = Page::new();
+      page.writer().write(&[value]).unwrap();
+      LogRecord::new_logical(
+        tx_id,
+        LogicalLog {
+          keyspace: vec![],
+          key: key.to_vec(),
+          value: Some(page),
+        },
+      )
+    };
+    let records = [
+      LogRecord::new_start(1),
+      logical(1, b"a", 1),
+      LogRecord::new_commit(1),
+      LogRecord::new_checkpoint(4),
+      LogRecord::new_start(2),
+      LogRecord::new_start(3),
+      logical(3, b"b", 3),
+      logical(2, b"b", 2),
+      LogRecord::new_commit(3),
+      LogRecord::new_commit(2),
+    ]
+    .into_iter()
+    .enumerate()
+    .map(|(i, record)| with_index(record, i + 1));
+
+    // the change before the checkpoint is dropped, and the rest follow
+    // the commits instead of the log.
+    let state = ReplayState::from_records(records);
+    let changes = state
+      .logical
+      .into_iter()
+      .map(|(tx_id, log)| (tx_id, log.value.unwrap().scanner().read().unwrap()))
+      .collect::<Vec<_>>();
+    assert_eq!(changes, vec![(3, 3), (2, 2)]);
   }
 }
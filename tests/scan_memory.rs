@@ -0,0 +1,100 @@
+use std::{
+  alloc::{GlobalAlloc, Layout, System},
+  path::PathBuf,
+  sync::atomic::{AtomicIsize, Ordering},
+  time::Duration,
+};
+
+use lfkv_db::{Engine, EngineConfig, Page, VerifyLevel, PAGE_SIZE};
+
+/// Counts the bytes allocated and not freed by the whole process, with the
+/// peak, so memory freed on the engine threads is counted as well.
+struct CountingAlloc;
+static LIVE: AtomicIsize = AtomicIsize::new(0);
+static PEAK: AtomicIsize = AtomicIsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+  unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+    let live = LIVE.fetch_add(layout.size() as isize, Ordering::SeqCst);
+    PEAK.fetch_max(live + layout.size() as isize, Ordering::SeqCst);
+    System.alloc(layout)
+  }
+
+  unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+    LIVE.fetch_sub(layout.size() as isize, Ordering::SeqCst);
+    System.dealloc(ptr, layout)
+  }
+}
+#[global_allocator]
+static ALLOC: CountingAlloc = CountingAlloc;
+
+fn config(name: &str) -> EngineConfig<PathBuf> {
+  let dir = std::env::temp_dir().join(format!("{name}-{}", std::process::id()));
+  EngineConfig {
+    base_path: dir,
+    disk_batch_delay: Duration::from_millis(10),
+    disk_batch_size: 100,
+    defragmentation_interval: Duration::from_secs(60),
+    undo_batch_delay: Duration::from_millis(10),
+    undo_batch_size: 100,
+    undo_file_size: 1 << 20,
+    wal_file_size: 1 << 20,
+    checkpoint_interval: Duration::from_secs(60),
+    checkpoint_count: 100,
+    group_commit_delay: Duration::from_millis(10),
+    group_commit_count: 100,
+    repair: false,
+    file_mode: None,
+    sync_mode: Default::default(),
+    log_mode: Default::default(),
+    verify_on_open: VerifyLevel::Full,
+    recovery_threads: 1,
+    transaction_timeout: None,
+    io_buffer_budget: None,
+    punch_holes: false,
+    key_comparator: Default::default(),
+    data_path: None,
+    wal_path: None,
+    undo_path: None,
+    in_memory: false,
+    eviction_policy: Default::default(),
+    max_open_transactions: None,
+    wal_buffer_bytes: None,
+    alloc_strategy: Default::default(),
+  }
+}
+
+#[test]
+fn _1() {
+  let config = config("lfkv-scan-memory");
+  let dir = config.base_path.clone();
+  let engine = Engine::bootstrap(config).unwrap();
+  let count = 300u32;
+  let cursor = engine.new_transaction().unwrap();
+  cursor
+    .put_all_sorted((0..count).map(|i| {
+      let mut page = Page::new();
+      page.writer().write(&[i as u8; PAGE_SIZE - 64]).unwrap();
+      (i.to_be_bytes().to_vec(), page)
+    }))
+    .unwrap();
+  cursor.commit().unwrap();
+
+  let cursor = engine.new_transaction().unwrap();
+  let mut iter = cursor.scan::<Page>(&vec![], &[0xff]).unwrap();
+  let base = LIVE.load(Ordering::SeqCst);
+  PEAK.store(base, Ordering::SeqCst);
+  let mut scanned = 0;
+  while iter.try_next().unwrap().is_some() {
+    scanned += 1;
+  }
+  assert_eq!(scanned, count);
+  // a leaf and a few copies of one value, far less than the range.
+  let held = PEAK.load(Ordering::SeqCst) - base;
+  assert!(held.lt(&(PAGE_SIZE as isize * 8)), "scan held {held} bytes");
+  drop(iter);
+  cursor.commit().unwrap();
+
+  drop(engine);
+  std::fs::remove_dir_all(&dir).unwrap();
+}